@@ -0,0 +1,92 @@
+//! Compares the interpreter with compiled code on every bundled Intcode
+//! input. Run with `cargo bench --bench backends`.
+
+use std::hint::black_box;
+use std::time::Instant;
+use y2019::intcode::{parse_instructions, Machine};
+
+/// Runs a fork of `template`, feeding it `inputs` over and over until it
+/// halts or `limit` values were read. Returns the sum of all outputs.
+fn run(template: &Machine, inputs: &[i64], limit: usize) -> i64 {
+  let mut machine = template.fork();
+  let mut inputs = inputs.iter().copied().cycle().take(limit);
+  let mut checksum = 0i64;
+  machine
+    .run_with(&mut (|| inputs.next(), |value: i64| checksum = checksum.wrapping_add(value)))
+    .unwrap();
+  checksum
+}
+
+/// Day 7 part 1: chains five amplifiers for every phase setting.
+fn amplifiers(template: &Machine) -> i64 {
+  let mut best = 0;
+  for a in 0..5 {
+    for b in 0..5 {
+      for c in 0..5 {
+        for d in 0..5 {
+          for e in 0..5 {
+            let phases = [a, b, c, d, e];
+            if (0..5).any(|phase| !phases.contains(&phase)) {
+              continue;
+            }
+            let signal = phases.iter().fold(0, |signal, phase| run(template, &[*phase, signal], 2));
+            best = best.max(signal);
+          }
+        }
+      }
+    }
+  }
+  best
+}
+
+fn scan(template: &Machine, size: i64) -> i64 {
+  let mut count = 0;
+  for y in 0..size {
+    for x in 0..size {
+      count += run(template, &[x, y], 2);
+    }
+  }
+  count
+}
+
+fn bench<F: FnMut() -> i64>(name: &str, iterations: u32, mut f: F) {
+  let start = Instant::now();
+  let mut checksum = 0i64;
+  for _ in 0..iterations {
+    checksum = checksum.wrapping_add(black_box(f()));
+  }
+  let elapsed = start.elapsed();
+  println!("{:<32} {:>12.3?}/iter  (checksum {})", name, elapsed / iterations, checksum);
+}
+
+// name, program, iterations and what to do with a machine running it
+type Workload = (&'static str, Vec<i64>, u32, Box<dyn Fn(&Machine) -> i64>);
+
+fn main() {
+  let mut day2 = parse_instructions(include_str!("../src/day2/data/input-1.txt").trim());
+  day2[1] = 12;
+  day2[2] = 2;
+  let mut day13 = parse_instructions(include_str!("../src/day13/data/input-1.txt").trim());
+  day13[0] = 2;
+
+  let inputs: Vec<Workload> = vec![
+    ("day2", day2, 1000, Box::new(|m| run(m, &[], 0))),
+    ("day5", parse_instructions(include_str!("../src/day5/data/input-1.txt").trim()), 1000, Box::new(|m| run(m, &[5], 1))),
+    ("day7", parse_instructions(include_str!("../src/day7/data/input-1.txt").trim()), 20, Box::new(amplifiers)),
+    ("day9", parse_instructions(include_str!("../src/day9/data/input-1.txt").trim()), 5, Box::new(|m| run(m, &[2], 1))),
+    ("day11", parse_instructions(include_str!("../src/day11/data/input-1.txt").trim()), 20, Box::new(|m| run(m, &[0, 1, 1], 20000))),
+    ("day13", day13, 5, Box::new(|m| run(m, &[0, 1, -1], 20000))),
+    ("day15", parse_instructions(include_str!("../src/day15/data/input-1.txt").trim()), 20, Box::new(|m| run(m, &[1, 4, 2, 3], 5000))),
+    ("day17", parse_instructions(include_str!("../src/day17/data/input-1.txt").trim()), 20, Box::new(|m| run(m, &[], 0))),
+    ("day19", parse_instructions(include_str!("../src/day19/data/input-1.txt").trim()), 2, Box::new(|m| scan(m, 50))),
+  ];
+
+  for (day, program, iterations, workload) in inputs.iter() {
+    let interpreted = Machine::new(program);
+    let mut compiled = interpreted.fork();
+    compiled.compile();
+
+    bench(&format!("{} interpreted", day), *iterations, || workload(&interpreted));
+    bench(&format!("{} compiled", day), *iterations, || workload(&compiled));
+  }
+}
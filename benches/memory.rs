@@ -0,0 +1,47 @@
+//! Compares the original 4MB dense memory layout with the paged memory
+//! backend on the day 9 and day 19 inputs. Run with `cargo bench --bench memory`.
+
+use std::hint::black_box;
+use std::time::Instant;
+use y2019::intcode::{parse_instructions, DenseMemory, Machine, Memory, PagedMemory, State};
+
+fn run<M: Memory>(program: &[i64], input: &[i64]) -> i64 {
+  let mut machine = Machine::with_memory(M::load(program));
+  machine.extend_input(input.iter().copied());
+  assert_eq!(machine.run(), Ok(State::Halted));
+  machine.drain_output().pop().unwrap_or(0)
+}
+
+fn scan<M: Memory>(program: &[i64], xs: std::ops::Range<i64>, ys: std::ops::Range<i64>) -> i64 {
+  let mut count = 0;
+  for y in ys {
+    for x in xs.clone() {
+      count += run::<M>(program, &[x, y]);
+    }
+  }
+  count
+}
+
+fn bench<F: FnMut() -> i64>(name: &str, iterations: u32, mut f: F) {
+  let start = Instant::now();
+  let mut checksum = 0i64;
+  for _ in 0..iterations {
+    checksum = checksum.wrapping_add(black_box(f()));
+  }
+  let elapsed = start.elapsed();
+  println!("{:<32} {:>12.3?}/iter  (checksum {})", name, elapsed / iterations, checksum);
+}
+
+fn main() {
+  let day9 = parse_instructions(include_str!("../src/day9/data/input-1.txt").trim());
+  let day19 = parse_instructions(include_str!("../src/day19/data/input-1.txt").trim());
+
+  bench("day9 part1 dense", 20, || run::<DenseMemory>(&day9, &[1]));
+  bench("day9 part1 paged", 20, || run::<PagedMemory>(&day9, &[1]));
+  bench("day9 part2 dense", 3, || run::<DenseMemory>(&day9, &[2]));
+  bench("day9 part2 paged", 3, || run::<PagedMemory>(&day9, &[2]));
+  bench("day19 50x50 dense", 1, || scan::<DenseMemory>(&day19, 0..50, 0..50));
+  bench("day19 50x50 paged", 1, || scan::<PagedMemory>(&day19, 0..50, 0..50));
+  bench("day19 400x10 rows dense", 1, || scan::<DenseMemory>(&day19, 600..1000, 950..960));
+  bench("day19 400x10 rows paged", 1, || scan::<PagedMemory>(&day19, 600..1000, 950..960));
+}
@@ -0,0 +1,149 @@
+//! Command line tools for Intcode programs.
+//!
+//! ```text
+//! cargo run --bin intcode -- debug src/day9/data/input-1.txt
+//! cargo run --bin intcode -- trace src/day9/data/input-1.txt boost.trace 1
+//! cargo run --bin intcode -- cfg src/day9/data/input-1.txt | dot -Tsvg > boost.svg
+//! cargo run --bin intcode -- decompile src/day19/data/input-1.txt
+//! cargo run --bin intcode -- profile src/day9/data/input-1.txt 2
+//! cargo run --bin intcode -- pack src/day9/data/input-1.txt boost.icp
+//! cargo run --release --bin intcode -- fuzz 0 100000
+//! ```
+
+use std::env;
+use std::fs;
+use std::io::{stdin, stdout, BufReader, BufWriter};
+use std::process::exit;
+use std::sync::{Arc, Mutex};
+
+use y2019::intcode::{analyze, decompile, diff, fuzz, load_file, read_trace, replay, serialize, Debugger, Machine, Profiler, State, TraceEntry, TraceWriter};
+
+const USAGE: &str = "\
+usage: intcode <command> <program> [args]
+
+commands:
+  debug <program>                    run the program in the interactive debugger
+  trace <program> <trace> [input..]  run the program and record a trace
+  show <trace>                       print a trace
+  replay <program> <trace>           re-run a recorded trace and check it
+  diff <trace> <trace>               find the first entry two traces differ in
+  cfg <program>                      print the control-flow graph in Graphviz DOT
+  decompile <program>                print the program as C-like pseudo-code
+  profile <program> [input..]        run the program and print a profile
+  coverage <program> [input..]       run the program and print an annotated disassembly
+  pack <program> <output>            store the program in the binary format
+  fuzz [seed] [runs]                 compare the backends on random programs
+
+programs are text or binary files, see intcode::load";
+
+fn fail<E: std::fmt::Display>(err: E) -> ! {
+  eprintln!("{}", err);
+  exit(1);
+}
+
+fn load(path: &str) -> Vec<i64> {
+  load_file(path).unwrap_or_else(|err| fail(err))
+}
+
+fn load_trace(path: &str) -> Vec<TraceEntry> {
+  fs::File::open(path)
+    .and_then(|file| read_trace(BufReader::new(file)))
+    .unwrap_or_else(|err| fail(format!("could not read {}: {}", path, err)))
+}
+
+fn parse_inputs(inputs: &[&str]) -> Vec<i64> {
+  inputs
+    .iter()
+    .map(|input| input.parse::<i64>().unwrap_or_else(|_| fail(format!("invalid input {}", input))))
+    .collect()
+}
+
+fn record(program: &str, path: &str, inputs: &[&str]) {
+  let inputs = parse_inputs(inputs);
+  let file = fs::File::create(path).unwrap_or_else(|err| fail(format!("could not create {}: {}", path, err)));
+  let writer = TraceWriter::new(BufWriter::new(file)).unwrap_or_else(|err| fail(err));
+  let writer = Arc::new(Mutex::new(writer));
+
+  let mut machine = Machine::new(&load(program));
+  machine.set_tracer(Box::new(writer.clone()));
+  machine.extend_input(inputs);
+  let state = machine.run().unwrap_or_else(|err| fail(err));
+  println!("{:?}", machine.drain_output());
+  if state == State::NeedsInput {
+    eprintln!("stopped waiting for input after {} instructions", machine.steps());
+  }
+  drop(machine);
+
+  let writer = Arc::try_unwrap(writer).ok().expect("the machine is gone");
+  writer.into_inner().unwrap().finish().unwrap_or_else(|err| fail(err));
+}
+
+fn profile(program: &[i64], inputs: &[&str], annotate: bool) {
+  let profiler = Profiler::new();
+  let mut machine = Machine::new(program);
+  profiler.attach(&mut machine);
+  machine.extend_input(parse_inputs(inputs));
+  let state = machine.run().unwrap_or_else(|err| fail(err));
+  if state == State::NeedsInput {
+    eprintln!("stopped waiting for input after {} instructions", machine.steps());
+  }
+
+  let profile = profiler.profile();
+  if annotate {
+    print!("{}", profile.annotate(program));
+  } else {
+    print!("{}", profile.table(program));
+  }
+}
+
+fn main() {
+  let args = env::args().skip(1).collect::<Vec<_>>();
+
+  match args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>().as_slice() {
+    ["debug", path] => {
+      let mut debugger = Debugger::new(Machine::new(&load(path)));
+      if let Err(err) = debugger.repl(stdin().lock(), stdout()) {
+        eprintln!("{}", err);
+        exit(1);
+      }
+    },
+    ["trace", program, path, inputs @ ..] => record(program, path, inputs),
+    ["show", path] => {
+      for entry in load_trace(path) {
+        println!("{}", entry);
+      }
+    },
+    ["replay", program, path] => {
+      let trace = load_trace(path);
+      match replay(&load(program), &trace).unwrap_or_else(|err| fail(err)) {
+        Some(divergence) => fail(divergence),
+        None => println!("replayed {} instructions", trace.len()),
+      }
+    },
+    ["diff", left, right] => {
+      if let Some(divergence) = diff(&load_trace(left), &load_trace(right)) {
+        print!("{}", divergence);
+        exit(1);
+      }
+    },
+    ["cfg", path] => print!("{}", analyze(&load(path)).to_dot()),
+    ["decompile", path] => print!("{}", decompile(&load(path))),
+    ["profile", path, inputs @ ..] => profile(&load(path), inputs, false),
+    ["coverage", path, inputs @ ..] => profile(&load(path), inputs, true),
+    ["pack", path, output] => {
+      fs::write(output, serialize(&load(path))).unwrap_or_else(|err| fail(format!("could not write {}: {}", output, err)))
+    },
+    ["fuzz", args @ ..] if args.len() <= 2 => {
+      let numbers = parse_inputs(args);
+      let (seed, runs) = (numbers.first().copied().unwrap_or(0), numbers.get(1).copied().unwrap_or(1000));
+      match fuzz(seed as u64, runs as u64, 40) {
+        Ok(()) => println!("the backends agree on {} programs", runs),
+        Err(failure) => fail(failure),
+      }
+    },
+    _ => {
+      eprintln!("{}", USAGE);
+      exit(2);
+    },
+  }
+}
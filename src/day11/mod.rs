@@ -1,37 +1,35 @@
-use super::intcode::{isa_interpreter_async, parse_instructions};
+use super::intcode::{load, IoDevice, LoadError, Machine, VmError};
 use std::cmp::{max, min};
 use std::collections::HashMap;
-use std::sync::mpsc::{channel, Receiver, Sender};
 
-type Channel<T> = (Sender<T>, Receiver<T>);
 type Coords = (i64, i64);
 
 #[aoc_generator(day11)]
-pub fn load(input: &str) -> Vec<i64> {
-    parse_instructions(input)
+fn load_program(input: &str) -> Result<Vec<i64>, LoadError> {
+    load(input)
 }
 
-fn robo_brain((send, recv): Channel<i64>, map: HashMap<Coords, i64>) -> HashMap<Coords, i64> {
-    let mut position = (0, 0);
-    let mut direction = (0, -1);
-    let mut map = map;
-
-    loop {
-        let color = map.entry(position).or_insert(0i64);
-        send.send(*color).unwrap();
-
-        let new_color = recv.recv().unwrap();
-        if new_color == 99 {
-            break;
-        }
-        let rotation = recv.recv().unwrap();
+struct Robot {
+    position: Coords,
+    direction: Coords,
+    map: HashMap<Coords, i64>,
+    new_color: Option<i64>,
+}
 
-        if rotation == 99 {
-            break;
+impl Robot {
+    fn new(map: HashMap<Coords, i64>) -> Robot {
+        Robot {
+            position: (0, 0),
+            direction: (0, -1),
+            map,
+            new_color: None,
         }
+    }
 
-        *color = new_color;
-        direction = match (direction, rotation) {
+    fn paint_and_move(&mut self, new_color: i64, rotation: i64) {
+        self.map.insert(self.position, new_color);
+        let direction = self.direction;
+        self.direction = match (direction, rotation) {
             ((0, -1), 0) => (-1, 0),
             ((0, 1), 0) => (1, 0),
             ((0, -1), 1) => (1, 0),
@@ -45,54 +43,44 @@ fn robo_brain((send, recv): Channel<i64>, map: HashMap<Coords, i64>) -> HashMap<
                 direction, rotation
             ),
         };
-        position = (position.0 + direction.0, position.1 + direction.1);
+        self.position = (self.position.0 + self.direction.0, self.position.1 + self.direction.1);
+    }
+}
+
+impl IoDevice for Robot {
+    fn input(&mut self) -> Option<i64> {
+        Some(*self.map.entry(self.position).or_insert(0i64))
+    }
+
+    fn output(&mut self, value: i64) {
+        match self.new_color.take() {
+            None => self.new_color = Some(value),
+            Some(new_color) => self.paint_and_move(new_color, value),
+        }
     }
+}
 
-    map
+fn robo_brain(instructions: &[i64], map: HashMap<Coords, i64>) -> Result<HashMap<Coords, i64>, VmError> {
+    let mut robot = Robot::new(map);
+    Machine::new(instructions).run_with(&mut robot)?;
+
+    Ok(robot.map)
 }
 
 #[aoc(day11, part1)]
-pub fn problem1(instructions: &Vec<i64>) -> usize {
-    let instructions = instructions.clone();
-    let (robo_send, robo_recv) = channel();
-    let (isa_send, isa_recv) = channel();
-
-    let map = HashMap::new();
-    let robo_thread = std::thread::spawn(move || robo_brain((isa_send, robo_recv), map));
-
-    let robo_send_for_isa = robo_send.clone();
-    let isa_thread = std::thread::spawn(move || {
-        isa_interpreter_async(instructions, isa_recv, robo_send_for_isa)
-    });
-
-    isa_thread.join().unwrap();
-    // terminate the robo brain thread
-    robo_send.send(99).unwrap();
-    robo_thread.join().unwrap().len()
+pub fn problem1(instructions: &Vec<i64>) -> Result<usize, VmError> {
+    let map = robo_brain(instructions, HashMap::new())?;
+
+    Ok(map.len())
 }
 
 #[aoc(day11, part2)]
-pub fn problem2(instructions: &Vec<i64>) -> usize {
-    let instructions = instructions.clone();
-
+pub fn problem2(instructions: &Vec<i64>) -> Result<usize, VmError> {
     println!("running 11-2");
 
-    let (robo_send, robo_recv) = channel();
-    let (isa_send, isa_recv) = channel();
-
     let mut map = HashMap::new();
     map.entry((0, 0)).or_insert(1i64);
-    let robo_thread = std::thread::spawn(move || robo_brain((isa_send, robo_recv), map));
-
-    let robo_send_for_isa = robo_send.clone();
-    let isa_thread = std::thread::spawn(move || {
-        isa_interpreter_async(instructions, isa_recv, robo_send_for_isa)
-    });
-
-    isa_thread.join().unwrap();
-    // terminate the robo brain thread
-    robo_send.send(99).unwrap();
-    let result = robo_thread.join().unwrap();
+    let result = robo_brain(instructions, map)?;
 
     let mut mac = (0, 0);
     let mut mic = (0, 0);
@@ -113,7 +101,7 @@ pub fn problem2(instructions: &Vec<i64>) -> usize {
         println!("");
     }
 
-    0
+    Ok(0)
 }
 
 #[cfg(test)]
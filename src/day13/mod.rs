@@ -1,30 +1,46 @@
-use super::intcode::{isa_interpreter_async, parse_instructions};
+use super::intcode::{load, IoDevice, LoadError, Machine, VmError};
 use std::cmp::{max, min};
 use std::collections::HashMap;
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::thread;
 
 type Coords = (i64, i64);
 
-fn render_thread(recv: Receiver<i64>) -> HashMap<Coords, u8> {
-    let mut map = HashMap::new();
-    loop {
-        let x = recv.recv().unwrap();
-        let y = recv.recv().unwrap();
-        let tile = recv.recv().unwrap();
+#[derive(Default)]
+struct Screen {
+    map: HashMap<Coords, u8>,
+    buffer: Vec<i64>,
+    ball: Coords,
+    paddle: Coords,
+    highscore: i64,
+}
 
-        if x == -1 && y == 0 {
-            // todo
+impl IoDevice for Screen {
+    // move the joystick towards the ball
+    fn input(&mut self) -> Option<i64> {
+        Some((self.ball.0 - self.paddle.0).signum())
+    }
+
+    fn output(&mut self, value: i64) {
+        self.buffer.push(value);
+        if self.buffer.len() < 3 {
+            return;
         }
 
-        if tile == 99 {
-            break;
+        let (x, y, tile) = (self.buffer[0], self.buffer[1], self.buffer[2]);
+        self.buffer.clear();
+
+        if x == -1 && y == 0 {
+            self.highscore = tile;
+            return;
         }
 
-        map.entry((x, y)).or_insert(tile as u8);
+        self.map.insert((x, y), tile as u8);
+        if tile == 4 {
+            self.ball = (x, y);
+        }
+        if tile == 3 {
+            self.paddle = (x, y);
+        }
     }
-
-    map
 }
 
 #[allow(dead_code)]
@@ -56,98 +72,27 @@ fn render_map(map: &HashMap<Coords, u8>) {
     }
 }
 
-#[aoc(day13, part1)]
-pub fn problem1(input: &str) -> usize {
-    let instructions = parse_instructions(input);
-
-    let (_isa_send, isa_recv) = channel();
-    let (render_send, render_recv) = channel();
-    let render_exit_send = render_send.clone();
-    let game_thread =
-        thread::spawn(move || isa_interpreter_async(instructions, isa_recv, render_send));
-    let render_thread = thread::spawn(move || render_thread(render_recv));
-
-    game_thread.join().unwrap();
-
-    // exit render thread
-    render_exit_send.send(0).unwrap();
-    render_exit_send.send(0).unwrap();
-    render_exit_send.send(99).unwrap();
-
-    let map = render_thread.join().unwrap();
-
-    map.iter().filter(|(_, v)| **v == 2).count()
-}
-
-fn send_command(send: &Sender<i64>, dx: i64) {
-    if let Err(err) = send.send(dx) {
-        println!("Error occurred while sending: {}", err);
-    }
+#[aoc_generator(day13)]
+fn load_program(input: &str) -> Result<Vec<i64>, LoadError> {
+    load(input)
 }
 
-fn play(recv: Receiver<i64>, send: Sender<i64>) -> (HashMap<Coords, u8>, i64) {
-    let mut map = HashMap::new();
-    let mut ball;
-    let mut paddle = (0, 0);
-    let mut paddle_initialized = false;
-    let mut highscore = 0;
-    let mut dx = 0;
-
-    send_command(&send, dx);
-    loop {
-        let x = recv.recv().unwrap();
-        let y = recv.recv().unwrap();
-        let tile = recv.recv().unwrap();
-
-        if x == -1 && y == 0 {
-            highscore = tile;
-            continue;
-        }
-
-        *map.entry((x, y)).or_insert(tile as u8) = tile as u8;
-
-        if tile == 4 {
-            ball = (x, y);
-            dx = -(paddle.0 - ball.0).signum();
-            if paddle_initialized {
-                send_command(&send, dx);
-            }
-        }
-
-        if tile == 3 {
-            paddle_initialized = true;
-            paddle = (x, y);
-        }
-
-        if tile == 99 {
-            break;
-        }
-    }
+#[aoc(day13, part1)]
+pub fn problem1(instructions: &[i64]) -> Result<usize, VmError> {
+    let mut screen = Screen::default();
+    Machine::new(instructions).run_with(&mut screen)?;
 
-    (map, highscore)
+    Ok(screen.map.iter().filter(|(_, v)| **v == 2).count())
 }
 
 #[aoc(day13, part2)]
-pub fn problem2(input: &str) -> i64 {
-    let mut instructions = parse_instructions(input);
+pub fn problem2(instructions: &[i64]) -> Result<i64, VmError> {
+    let mut instructions = instructions.to_vec();
     // insert coin
     instructions[0] = 2;
 
-    let (isa_send, isa_recv) = channel();
-    let (render_send, render_recv) = channel();
-    let play_exit_send = render_send.clone();
-    let game_thread =
-        thread::spawn(move || isa_interpreter_async(instructions, isa_recv, render_send));
-    let play_thread = thread::spawn(move || play(render_recv, isa_send));
-
-    game_thread.join().unwrap();
-
-    // exit render thread
-    play_exit_send.send(0).unwrap();
-    play_exit_send.send(0).unwrap();
-    play_exit_send.send(99).unwrap();
-
-    let (_, highscore) = play_thread.join().unwrap();
+    let mut screen = Screen::default();
+    Machine::new(&instructions).run_with(&mut screen)?;
 
-    highscore
+    Ok(screen.highscore)
 }
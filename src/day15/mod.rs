@@ -1,9 +1,8 @@
 use std::cmp::{max, min};
 use std::collections::HashMap;
 use std::iter::Iterator;
-use std::sync::mpsc::{channel, Receiver, Sender};
 
-use super::intcode::{isa_interpreter_async, parse_instructions};
+use super::intcode::{load, IoDevice, LoadError, Machine, VmError};
 use pathfinding::prelude::dijkstra;
 
 type Coords = (i64, i64);
@@ -66,6 +65,7 @@ impl Iterator for Neighbors {
 
 struct Robot {
     position: Coords,
+    next_coords: Coords,
     backlog: Vec<Coords>,
     map: HashMap<Coords, Tile>,
 }
@@ -80,6 +80,7 @@ impl Robot {
 
         Robot {
             position,
+            next_coords: position,
             backlog,
             map,
         }
@@ -232,39 +233,34 @@ fn isa_to_tile(isa_tile: i64) -> Tile {
     }
 }
 
-fn robot_brain(send: Sender<i64>, recv: Receiver<i64>) -> HashMap<Coords, Tile> {
-    let mut robot = Robot::new();
-    let mut next_coords;
+impl IoDevice for Robot {
+    // runs out of commands once the whole map has been explored, which
+    // pauses the droid
+    fn input(&mut self) -> Option<i64> {
+        let (next_coords, command) = self.find_suitable_command()?;
+        self.next_coords = next_coords;
+        Some(command_to_isa(command))
+    }
 
-    loop {
-        if let Some(command) = robot.find_suitable_command() {
-            next_coords = command.0;
-            send.send(command_to_isa(command.1)).unwrap();
-        } else {
-            println!("Could not find suitable command for robot");
-            break;
-        }
-
-        let status = recv.recv();
-        if let Ok(status) = status {
-            if status == 99 {
-                break;
-            }
-            if status == 0 {
-                *robot.map.entry(next_coords).or_insert(Tile::Wall) = Tile::Wall;
-            } else if status == 1 {
-                *robot.map.entry(next_coords).or_insert(Tile::Floor) = Tile::Floor;
-                robot.position = next_coords;
-            } else if status == 2 {
-                *robot.map.entry(next_coords).or_insert(Tile::Oxygen) = Tile::Oxygen;
-                robot.position = next_coords;
-            }
-        } else if let Err(err) = status {
-            println!("Error while receiving status update: {}", err);
+    fn output(&mut self, status: i64) {
+        let next_coords = self.next_coords;
+        if status == 0 {
+            *self.map.entry(next_coords).or_insert(Tile::Wall) = Tile::Wall;
+        } else if status == 1 {
+            *self.map.entry(next_coords).or_insert(Tile::Floor) = Tile::Floor;
+            self.position = next_coords;
+        } else if status == 2 {
+            *self.map.entry(next_coords).or_insert(Tile::Oxygen) = Tile::Oxygen;
+            self.position = next_coords;
         }
     }
+}
+
+fn explore(program: &[i64]) -> Result<HashMap<Coords, Tile>, VmError> {
+    let mut robot = Robot::new();
+    Machine::new(program).run_with(&mut robot)?;
 
-    robot.map
+    Ok(robot.map)
 }
 
 #[allow(dead_code)]
@@ -356,22 +352,14 @@ fn fill_with_oxygen(map: &mut HashMap<Coords, Tile>) -> usize {
     timer
 }
 
-#[aoc(day15, part1)]
-pub fn problem1(input: &str) -> i64 {
-    let instructions = parse_instructions(input);
-
-    let (isa_send, isa_recv) = channel();
-    let (robo_send, robo_recv) = channel();
-
-    let isa_thread =
-        std::thread::spawn(move || isa_interpreter_async(instructions, isa_recv, robo_send));
-    let robo_thread = std::thread::spawn(move || robot_brain(isa_send, robo_recv));
+#[aoc_generator(day15)]
+fn load_program(input: &str) -> Result<Vec<i64>, LoadError> {
+    load(input)
+}
 
-    let map = robo_thread.join().unwrap();
-    let isa_result = isa_thread.join();
-    if let Err(_) = isa_result {
-        println!("ISA thread closed with err.");
-    }
+#[aoc(day15, part1)]
+pub fn problem1(program: &[i64]) -> Result<i64, VmError> {
+    let map = explore(program)?;
 
     let mut oxygen = (0, 0);
     for (k, v) in map.iter() {
@@ -386,25 +374,12 @@ pub fn problem1(input: &str) -> i64 {
         |&p| p.0 == oxygen.0 && p.1 == oxygen.1,
     );
 
-    result.unwrap().1
+    Ok(result.unwrap().1)
 }
 
 #[aoc(day15, part2)]
-pub fn problem2(input: &str) -> usize {
-    let instructions = parse_instructions(input);
-
-    let (isa_send, isa_recv) = channel();
-    let (robo_send, robo_recv) = channel();
-
-    let isa_thread =
-        std::thread::spawn(move || isa_interpreter_async(instructions, isa_recv, robo_send));
-    let robo_thread = std::thread::spawn(move || robot_brain(isa_send, robo_recv));
-
-    let mut map = robo_thread.join().unwrap();
-    let isa_result = isa_thread.join();
-    if let Err(_) = isa_result {
-        println!("ISA thread closed with err.");
-    }
+pub fn problem2(program: &[i64]) -> Result<usize, VmError> {
+    let mut map = explore(program)?;
 
-    fill_with_oxygen(&mut map)
+    Ok(fill_with_oxygen(&mut map))
 }
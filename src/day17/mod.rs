@@ -1,20 +1,4 @@
-use super::intcode::{isa_interpreter_async, isa_interpreter_mi, parse_instructions};
-use std::sync::mpsc::{channel, Receiver};
-
-fn monitor(recv: Receiver<i64>) -> Vec<char> {
-    let mut output = vec![];
-    loop {
-        let value = recv.recv();
-        if let Err(err) = value {
-            println!("Err in monitor thread while reading data: {}", err);
-            break;
-        } else if let Ok(value) = value {
-            output.push(char::from(value as u8));
-        }
-    }
-
-    output
-}
+use super::intcode::{load, AsciiConsole, LoadError, VmError};
 
 fn hash(map: &str) -> usize {
     let lines: Vec<_> = map
@@ -48,31 +32,24 @@ fn hash(map: &str) -> usize {
     result
 }
 
-#[aoc(day17, part1)]
-pub fn problem1(input: &str) -> usize {
-    let instructions = parse_instructions(&input);
-
-    let (_, isa_recv) = channel();
-    let (mon_send, mon_recv) = channel();
-    let thread_monitor = std::thread::spawn(move || monitor(mon_recv));
-    let isa_thread =
-        std::thread::spawn(move || isa_interpreter_async(instructions, isa_recv, mon_send));
+#[aoc_generator(day17)]
+fn load_program(input: &str) -> Result<Vec<i64>, LoadError> {
+    load(input)
+}
 
-    let map = thread_monitor.join().unwrap();
-    isa_thread.join().unwrap();
+#[aoc(day17, part1)]
+pub fn problem1(instructions: &[i64]) -> Result<usize, VmError> {
+    let mut camera = AsciiConsole::new(instructions);
+    camera.run()?;
 
-    let map: String = map.iter().collect();
+    let map = camera.take_text();
     // println!("{}", map);
 
-    hash(&map)
-}
-
-fn str_to_ascii(input: &str) -> Vec<i64> {
-    input.chars().map(|v| v as i64).collect::<Vec<_>>()
+    Ok(hash(&map))
 }
 
 #[aoc(day17, part2)]
-pub fn problem2(code: &str) -> i64 {
+pub fn problem2(code: &[i64]) -> Result<i64, VmError> {
     // Solved manually by retracing the labyrinth
     // The recurring patterns emerge pretty quickly
     //
@@ -89,23 +66,22 @@ pub fn problem2(code: &str) -> i64 {
     //
     // Main: A,A,B,C,A,C,B,C,A,B
 
-    let main = "A,A,B,C,A,C,B,C,A,B\n";
-    let a = "L,4,L,10,L,6\n";
-    let b = "L,6,L,4,R,8,R,8\n";
-    let c = "L,6,R,8,L,10,L,8,L,8\n";
+    let main = "A,A,B,C,A,C,B,C,A,B";
+    let a = "L,4,L,10,L,6";
+    let b = "L,6,L,4,R,8,R,8";
+    let c = "L,6,R,8,L,10,L,8,L,8";
 
-    let debug = "n\n";
+    let debug = "n";
 
-    let mut input = vec![];
-    input.append(&mut str_to_ascii(main));
-    input.append(&mut str_to_ascii(a));
-    input.append(&mut str_to_ascii(b));
-    input.append(&mut str_to_ascii(c));
-    input.append(&mut str_to_ascii(debug));
-
-    let mut instructions = parse_instructions(&code);
+    let mut instructions = code.to_vec();
     // patch the code
     instructions[0] = 2;
 
-    isa_interpreter_mi(&mut instructions, &input)
+    let mut robot = AsciiConsole::new(&instructions);
+    for line in &[main, a, b, c, debug] {
+        robot.write_line(line);
+    }
+    robot.run()?;
+
+    Ok(robot.take_values().pop().unwrap_or(0))
 }
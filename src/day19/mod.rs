@@ -1,21 +1,34 @@
-use super::intcode::{isa_interpreter_mi, parse_instructions};
+use super::intcode::{load, LoadError, Machine, VmError};
 use std::cmp::{max, min};
 use std::collections::HashMap;
 
 type Coords = (i64, i64);
 
+fn is_tractored(drone: &Machine, x: i64, y: i64) -> Result<i64, VmError> {
+    let mut drone = drone.fork();
+    drone.extend_input(vec![x, y]);
+    drone.run()?;
+    Ok(drone.pop_output().unwrap_or(0))
+}
+
+#[aoc_generator(day19)]
+fn load_program(input: &str) -> Result<Vec<i64>, LoadError> {
+    load(input)
+}
+
 #[aoc(day19, part1)]
-pub fn problem1(input: &str) -> i64 {
-    let instructions = parse_instructions(&input);
+pub fn problem1(program: &[i64]) -> Result<i64, VmError> {
+    let mut drone = Machine::new(program);
+    drone.compile();
 
     let mut counter = 0;
     for x in 0..50i64 {
         for y in 0..50i64 {
-            counter += isa_interpreter_mi(&mut instructions.clone(), &vec![x, y]);
+            counter += is_tractored(&drone, x, y)?;
         }
     }
 
-    counter
+    Ok(counter)
 }
 
 #[allow(dead_code)]
@@ -46,8 +59,9 @@ fn print_map(map: &HashMap<Coords, i64>) {
 }
 
 #[aoc(day19, part2)]
-pub fn problem2(input: &str) -> i64 {
-    let instructions = parse_instructions(&input);
+pub fn problem2(program: &[i64]) -> Result<i64, VmError> {
+    let mut drone = Machine::new(program);
+    drone.compile();
     let mut beam_width = vec![];
 
     let mut start_pos = (0, 0);
@@ -58,7 +72,7 @@ pub fn problem2(input: &str) -> i64 {
         let mut count_x_100 = 0;
         let mut first_x = -1;
         for x in 600..1000i64 {
-            let is_tractored = isa_interpreter_mi(&mut instructions.clone(), &vec![x, y]);
+            let is_tractored = is_tractored(&drone, x, y)?;
             count_x_100 += is_tractored;
             if is_tractored == 1 && first_x == -1 {
                 first_x = x;
@@ -89,5 +103,5 @@ pub fn problem2(input: &str) -> i64 {
     // print_map(&map);
     // too low: 7720974
     // too high: 7721074
-    start_pos.0 * 10000 + start_pos.1
+    Ok(start_pos.0 * 10000 + start_pos.1)
 }
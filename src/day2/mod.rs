@@ -1,27 +1,26 @@
-use super::intcode::isa_interpreter;
-use std::num::ParseIntError;
+use super::intcode::{isa_interpreter, load, solve, Constraint, LoadError, Symbol, SymbolicError, SymbolicMachine, VmError};
+use std::collections::BTreeMap;
+
+/// How many paths through the program are explored for the noun and verb.
+/// Day 2 programs don't branch, so there is just one; the bound stops other
+/// inputs from splitting the search without end.
+const MAX_PATHS: usize = 16;
 
 #[aoc_generator(day2)]
-pub fn input_generator(input: &str) -> Result<Vec<i64>, ParseIntError> {
-    input
-        .split(",")
-        .filter(|v| *v != "")
-        .map(|v| v.parse::<i64>())
-        .collect::<Result<Vec<_>, ParseIntError>>()
+pub fn input_generator(input: &str) -> Result<Vec<i64>, LoadError> {
+    let program = load(input)?;
+    // the noun and verb are patched into cells 1 and 2, the result is read from cell 0
+    if program.len() < 3 {
+        return Err(LoadError::TooShort { length: program.len(), needed: 3 });
+    }
+    Ok(program)
 }
 
-fn isa_interpreter_wrap(instructions: &mut Vec<i64>) -> i64 {
+fn isa_interpreter_wrap(instructions: &mut Vec<i64>) -> Result<i64, VmError> {
     isa_interpreter(instructions, 0)
 }
 
-fn patch_and_interpret(mut instructions: &mut Vec<i64>, noun: i64, verb: i64) -> i64 {
-    instructions[1] = noun;
-    instructions[2] = verb;
-
-    isa_interpreter_wrap(&mut instructions)
-}
-
-fn patch_and_interpret_problem1(mut instructions: &mut Vec<i64>) -> i64 {
+fn patch_and_interpret_problem1(mut instructions: &mut Vec<i64>) -> Result<i64, VmError> {
     instructions[1] = 12;
     instructions[2] = 2;
 
@@ -29,25 +28,46 @@ fn patch_and_interpret_problem1(mut instructions: &mut Vec<i64>) -> i64 {
 }
 
 #[aoc(day2, part1)]
-pub fn problem1(instructions: &Vec<i64>) -> i64 {
+pub fn problem1(instructions: &Vec<i64>) -> Result<i64, VmError> {
     let mut instructions = instructions.clone();
     patch_and_interpret_problem1(&mut instructions)
 }
 
 #[aoc(day2, part2)]
-pub fn problem2(instructions: &Vec<i64>) -> i64 {
+pub fn problem2(instructions: &Vec<i64>) -> Result<i64, SymbolicError> {
     let expected_outcome: i64 = 19690720;
-    let mut instructions = instructions.clone();
-    let mut checksum = 0;
-
-    for n in 0..100 {
-        for v in 0..100 {
-            let result = patch_and_interpret(&mut instructions, n, v);
-            if result == expected_outcome {
-                checksum = 100 * n + v;
-            }
+    let (noun, verb) = (Symbol::Cell(1), Symbol::Cell(2));
+
+    let mut machine = SymbolicMachine::new(instructions);
+    machine.symbolize(1);
+    machine.symbolize(2);
+    let domains = BTreeMap::from([(noun, 0..=99), (verb, 0..=99)]);
+
+    for path in machine.explore(MAX_PATHS)? {
+        let mut constraints = path.constraints.clone();
+        constraints.push(Constraint::equals(path.cell(0), expected_outcome));
+        if let Some(solution) = solve(&constraints, &domains) {
+            return Ok(100 * solution[&noun] + solution[&verb]);
         }
     }
 
-    checksum
+    Err(SymbolicError::NoSolution)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn short_programs_are_rejected() {
+        assert_eq!(input_generator(""), Err(LoadError::TooShort { length: 0, needed: 3 }));
+        assert_eq!(input_generator("1,0").unwrap_err().to_string(), "program of 2 values is too short, needs 3");
+        assert_eq!(input_generator("99,0,0"), Ok(vec![99, 0, 0]));
+    }
+
+    #[test]
+    fn unreachable_outcomes_are_errors() {
+        // adds the noun and verb, which can't get anywhere near the outcome
+        assert_eq!(problem2(&vec![1101, 0, 0, 0, 99]), Err(SymbolicError::NoSolution));
+    }
 }
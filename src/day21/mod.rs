@@ -1,39 +1,30 @@
-use std::sync::mpsc::{channel, Receiver};
-use crate::intcode::{parse_instructions, isa_interpreter_async};
+use crate::intcode::{load, AsciiConsole, LoadError, VmError};
 
 #[derive(Debug)]
 enum Output {
-    Crash(Vec<char>),
+    Crash(String),
     Success(i64),
 }
 
-fn monitor(recv: Receiver<i64>) -> Output {
-    let mut output = vec![];
-    let mut success = 0;
-    loop {
-        let value = recv.recv();
-        if let Err(err) = value {
-            println!("Err in monitor thread while reading data: {}", err);
-            break;
-        } else if let Ok(value) = value {
-            if value < 256 {
-                output.push(char::from(value as u8));
-            } else {
-                success = value;
-            }
-        }
-    }
+fn run(instructions: &[i64], sprintcode: &str) -> Result<Output, VmError> {
+    let mut droid = AsciiConsole::new(instructions);
+    droid.write_str(sprintcode);
+    droid.run()?;
 
-    if success > 0 {
-        Output::Success(success)
+    if let Some(damage) = droid.take_values().pop() {
+        Ok(Output::Success(damage))
     } else {
-        Output::Crash(output)
+        Ok(Output::Crash(droid.take_text()))
     }
 }
 
+#[aoc_generator(day21)]
+fn load_program(input: &str) -> Result<Vec<i64>, LoadError> {
+    load(input)
+}
+
 #[aoc(day21, part1)]
-pub fn problem1(input: &str) -> i64 {
-    let instructions = parse_instructions(input);
+pub fn problem1(instructions: &[i64]) -> Result<i64, VmError> {
     let sprintcode = "NOT B J
 NOT C T
 OR T J
@@ -41,33 +32,20 @@ AND D J
 NOT A T
 OR T J
 WALK\n";
-    let (isa_send, isa_recv) = channel();
-    let (mon_send, mon_recv) = channel();
-    let thread_monitor = std::thread::spawn(move || monitor(mon_recv));
-    let isa_thread =
-        std::thread::spawn(move || isa_interpreter_async(instructions, isa_recv, mon_send));
-
-    for c in sprintcode.chars() {
-        isa_send.send(c as i64).unwrap();
-    }
-
-    let result = thread_monitor.join().unwrap();
-    isa_thread.join().unwrap();
+    let result = run(instructions, sprintcode)?;
 
     let mut damage = 0;
     if let Output::Success(d) = result {
         damage = d;
     } else if let Output::Crash(map) = result {
-        let map: String = map.iter().collect();
         println!("{}", map);
     }
 
-    damage
+    Ok(damage)
 }
 
 #[aoc(day21, part2)]
-pub fn problem2(input: &str) -> i64 {
-    let instructions = parse_instructions(input);
+pub fn problem2(instructions: &[i64]) -> Result<i64, VmError> {
     let sprintcode = "NOT B J
 NOT C T
 OR T J
@@ -76,26 +54,14 @@ AND H J
 NOT A T
 OR T J
 RUN\n";
-    let (isa_send, isa_recv) = channel();
-    let (mon_send, mon_recv) = channel();
-    let thread_monitor = std::thread::spawn(move || monitor(mon_recv));
-    let isa_thread =
-        std::thread::spawn(move || isa_interpreter_async(instructions, isa_recv, mon_send));
-
-    for c in sprintcode.chars() {
-        isa_send.send(c as i64).unwrap();
-    }
-
-    let result = thread_monitor.join().unwrap();
-    isa_thread.join().unwrap();
+    let result = run(instructions, sprintcode)?;
 
     let mut damage = 0;
     if let Output::Success(d) = result {
         damage = d;
     } else if let Output::Crash(map) = result {
-        let map: String = map.iter().collect();
         println!("{}", map);
     }
 
-    damage
+    Ok(damage)
 }
\ No newline at end of file
@@ -1,193 +1,191 @@
-use crate::intcode::isa_interpreter_async;
-use std::thread::spawn;
-use std::sync::mpsc::{channel, Sender, Receiver};
-use std::io::{Write, stdout, stdin};
-use crate::intcode::parse_instructions;
+use std::fmt;
+use std::io::{stdout, stdin};
+use crate::intcode::{load, AsciiConsole, LoadError, State as MachineState, VmError};
+use crate::search::{subsets, Subsets};
 
-fn is_waiting(buffer: &Vec<char>) -> bool {
-    let expected = "Command?";
-    let last = buffer.iter().rev().take(expected.len()).rev().collect::<String>();
+#[allow(dead_code)]
+fn ui(instructions: &[i64]) -> std::io::Result<()> {
+    let mut droid = AsciiConsole::new(instructions);
+    droid.interactive(stdin().lock(), stdout())?;
 
-    last == expected
+    Ok(())
 }
 
-fn flush(buffer: &Vec<char>) {
-    let s = buffer.iter().collect::<String>();
-    println!("{}", s);
-    stdout().flush().expect("Flush did not work");
+/// Why the droid didn't get through the checkpoint.
+#[derive(Debug)]
+enum DroidError {
+    Vm(VmError),
+    /// Every combination of the items was tried.
+    NoCombination,
 }
 
-fn send_command(sender: &Sender<i64>, msg: &str) {
-    for c in msg.chars() {
-        sender.send(c as i64).unwrap();
+impl From<VmError> for DroidError {
+    fn from(err: VmError) -> DroidError {
+        DroidError::Vm(err)
     }
-    sender.send('\n' as i64).unwrap();
 }
 
-#[allow(dead_code)]
-fn ui(sender: Sender<i64>, receiver: Receiver<i64>) {
-    let mut buffer = vec![];
-    let mut s = String::new();
-
-    loop {
-        let rec = receiver.recv();
-
-        if let Err(_) = rec {
-            flush(&buffer);
-            println!("ISA thread ended");
-            break;
-        }
-
-        let rec = rec.unwrap() as u8 as char;
-        buffer.push(rec);
-
-        if is_waiting(&buffer) {
-            flush(&buffer);
-            buffer = vec![];
-            stdin().read_line(&mut s).expect("Did not enter a string");
-
-            if s.trim() == "quit" {
-                break;
-            }
-
-            for c in s.trim().chars() {
-                sender.send(c as i64).unwrap();
-            }
-            sender.send('\n' as i64).unwrap();
-            s.clear();
+impl fmt::Display for DroidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DroidError::Vm(err) => write!(f, "{}", err),
+            DroidError::NoCombination => write!(f, "no item combination passed the checkpoint"),
         }
     }
 }
 
+impl std::error::Error for DroidError {}
+
 #[derive(PartialEq, Eq)]
 enum State {
     Drain,
     Fill,
-    Check,
 }
 
-fn auto(sender: Sender<i64>, receiver: Receiver<i64>) {
-    let mut buffer = vec![];
-    let commands = vec![
-        "east",
-        "take antenna",
-        "west",
-        "north",
-        "take weather machine",
-        "north",
-        "take klein bottle",
-        "east",
-        "take spool of cat6",
-        "east",
-        "south",
-        "take mug",
-        "north",
-        "north",
-        "west",
-        "north",
-        "take cake",
-        "south",
-        "east",
-        "east",
-        "north",
-        "north",
-        "take tambourine",
-        "south",
-        "south",
-        "south",
-        "take shell",
-        "north",
-        "west",
-        "south",
-        "west",
-        "south",
-        "south",
-    ];
-    let mut next = 0;
-
-    let mut state = State::Drain;
-    let items = vec!["shell",
-        "klein bottle",
-        "tambourine",
-        "weather machine",
-        "spool of cat6",
-        "mug",
-        "cake"];
-    let mut to_pack = 1;
-    let mut inventory = items.clone();
-
-    loop {
-        let rec = receiver.recv();
+const COMMANDS: [&str; 33] = [
+    "east",
+    "take antenna",
+    "west",
+    "north",
+    "take weather machine",
+    "north",
+    "take klein bottle",
+    "east",
+    "take spool of cat6",
+    "east",
+    "south",
+    "take mug",
+    "north",
+    "north",
+    "west",
+    "north",
+    "take cake",
+    "south",
+    "east",
+    "east",
+    "north",
+    "north",
+    "take tambourine",
+    "south",
+    "south",
+    "south",
+    "take shell",
+    "north",
+    "west",
+    "south",
+    "west",
+    "south",
+    "south",
+];
+
+const ITEMS: [&str; 7] = ["shell",
+    "klein bottle",
+    "tambourine",
+    "weather machine",
+    "spool of cat6",
+    "mug",
+    "cake"];
+
+struct Auto {
+    next: usize,
+    state: State,
+    candidates: Subsets<&'static str>,
+    to_pack: Option<Vec<&'static str>>,
+    inventory: Vec<&'static str>,
+}
 
-        if let Err(_) = rec {
-            flush(&buffer);
-            println!("ISA thread ended");
-            break;
+impl Auto {
+    fn new() -> Auto {
+        // carrying nothing is never heavy enough
+        let mut candidates = subsets(ITEMS);
+        candidates.next();
+        let to_pack = candidates.next();
+
+        Auto {
+            next: 0,
+            state: State::Drain,
+            candidates,
+            to_pack,
+            inventory: ITEMS.to_vec(),
         }
+    }
 
-        let rec = rec.unwrap() as u8 as char;
-        buffer.push(rec);
-
-        if is_waiting(&buffer) {
-            flush(&buffer);
-            buffer = vec![];
-
-            if next < commands.len() {
-                send_command(&sender, commands[next]);
-                next += 1;
-                continue;
-            }
+    /// The next command for the droid, `None` once every combination of
+    /// items was checked.
+    fn next_command(&mut self) -> Option<String> {
+        if self.next < COMMANDS.len() {
+            self.next += 1;
+            return Some(COMMANDS[self.next - 1].to_string());
+        }
+        let to_pack = self.to_pack.as_ref()?;
 
-            if state == State::Drain {
-                if let Some(item) = inventory.pop() {
-                    send_command(&sender, &format!("drop {}", item));
-                } else {
-                    state = State::Fill;
-                }
+        if self.state == State::Drain {
+            if let Some(item) = self.inventory.pop() {
+                return Some(format!("drop {}", item));
             }
+            self.state = State::Fill;
+        }
 
-            if state == State::Fill {
-                let mut take = Option::None;
-                for i in 0..items.len() {
-                    if ((1 << i) & to_pack != 0) && !inventory.contains(&items[i]) {
-                        take = Some(items[i]);
-                        break;
-                    }
-                }
-
-                if let Some(i) = take {
-                    inventory.push(i);
-                    send_command(&sender, &format!("take {}", i));
-                } else {
-                    state = State::Check;
-                }
-            }
+        if self.state == State::Fill {
+            let take = to_pack.iter().find(|item| !self.inventory.contains(item));
 
-            if state == State::Check {
-                send_command(&sender, "east");
-                state = State::Drain;
-                to_pack += 1;
+            if let Some(&i) = take {
+                self.inventory.push(i);
+                return Some(format!("take {}", i));
             }
         }
+
+        // check the current combination
+        self.state = State::Drain;
+        self.to_pack = self.candidates.next();
+        Some("east".to_string())
     }
 }
 
-#[aoc(day25, part1)]
-fn part1(input: &str) -> i64 {
-    let instructions = parse_instructions(input);
+#[aoc_generator(day25)]
+fn load_program(input: &str) -> Result<Vec<i64>, LoadError> {
+    load(input)
+}
 
-    let (ui_send, ui_recv) = channel();
-    let (isa_send, isa_recv) = channel();
+#[aoc(day25, part1)]
+fn part1(instructions: &[i64]) -> Result<i64, DroidError> {
+    let mut droid = AsciiConsole::new(instructions);
+    let mut auto = Auto::new();
 
-    let ui_thread = spawn(move || auto(isa_send, ui_recv));
-    let _isa_thread = spawn(move || isa_interpreter_async(instructions, isa_recv, ui_send));
+    loop {
+        let state = droid.run()?;
+        let waiting = droid.at_prompt("Command?");
+        println!("{}", droid.take_text());
 
-    ui_thread.join().unwrap();
+        if state == MachineState::Halted || !waiting {
+            break;
+        }
+        let command = auto.next_command().ok_or(DroidError::NoCombination)?;
+        droid.write_line(&command);
+    }
+    println!("Droid halted");
 
-    0
+    Ok(0)
 }
 
 #[aoc(day25, part2)]
-fn part2(_input: &str) -> i64 {
+fn part2(_instructions: &[i64]) -> i64 {
     0
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_combination_is_checked_once() {
+        let mut auto = Auto::new();
+        let mut commands = vec![];
+        while let Some(command) = auto.next_command() {
+            commands.push(command);
+        }
+        let checks = commands[COMMANDS.len()..].iter().filter(|command| *command == "east").count();
+        assert_eq!(checks, (1 << ITEMS.len()) - 1);
+        assert_eq!(auto.next_command(), None);
+    }
+}
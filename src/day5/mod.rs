@@ -1,26 +1,19 @@
-use super::intcode::isa_interpreter;
+use super::intcode::{isa_interpreter, load, LoadError, VmError};
 
 #[aoc_generator(day5)]
-fn load_code(input: &str) -> Vec<i64> {
-    let opcodes = input
-        .split(",")
-        .filter(|v| *v != "\n")
-        .filter(|v| *v != "")
-        .map(|v| v.parse::<i64>().unwrap())
-        .collect::<Vec<_>>();
-
-    opcodes
+fn load_code(input: &str) -> Result<Vec<i64>, LoadError> {
+    load(input)
 }
 
 #[aoc(day5, part1)]
-pub fn problem1(opcodes: &Vec<i64>) -> i64 {
+pub fn problem1(opcodes: &Vec<i64>) -> Result<i64, VmError> {
     let mut opcodes = opcodes.clone();
 
     isa_interpreter(&mut opcodes, 1)
 }
 
 #[aoc(day5, part2)]
-pub fn problem2(opcodes: &Vec<i64>) -> i64 {
+pub fn problem2(opcodes: &Vec<i64>) -> Result<i64, VmError> {
     let mut opcodes = opcodes.clone();
 
     isa_interpreter(&mut opcodes, 5)
@@ -34,35 +27,35 @@ mod test {
     fn problem2_example1() {
         let mut instructions = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
         let result = isa_interpreter(&mut instructions, 8);
-        assert_eq!(result, 1);
+        assert_eq!(result, Ok(1));
     }
 
     #[test]
     fn problem2_example2() {
         let mut instructions = vec![3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8];
         let result = isa_interpreter(&mut instructions, 4);
-        assert_eq!(result, 1);
+        assert_eq!(result, Ok(1));
     }
 
     #[test]
     fn problem2_example3() {
         let mut instructions = vec![3, 3, 1108, -1, 8, 3, 4, 3, 99];
         let result = isa_interpreter(&mut instructions, 8);
-        assert_eq!(result, 1);
+        assert_eq!(result, Ok(1));
     }
 
     #[test]
     fn problem2_example4() {
         let mut instructions = vec![3, 3, 1107, -1, 8, 3, 4, 3, 99];
         let result = isa_interpreter(&mut instructions, 4);
-        assert_eq!(result, 1);
+        assert_eq!(result, Ok(1));
     }
 
     #[test]
     fn problem2_example5() {
         let mut instructions = vec![3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9];
         let result = isa_interpreter(&mut instructions, 3);
-        assert_eq!(result, 1);
+        assert_eq!(result, Ok(1));
     }
 
     #[test]
@@ -73,6 +66,6 @@ mod test {
             20, 1105, 1, 46, 98, 99,
         ];
         let result = isa_interpreter(&mut instructions, 8);
-        assert_eq!(result, 1000);
+        assert_eq!(result, Ok(1000));
     }
 }
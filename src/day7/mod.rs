@@ -1,152 +1,78 @@
-use super::intcode::{isa_interpreter_async, isa_interpreter_mi};
-use std::sync::mpsc::channel;
-use std::thread;
-
-fn thruster_output(program: &Vec<i64>, phases: &Vec<i64>) -> i64 {
-    let mut input = 0;
-    for phase in phases {
-        let mut code = program.clone();
-        input = isa_interpreter_mi(&mut code, &vec![*phase, input]);
+use std::ops::Range;
+
+use super::intcode::{load, Budget, LoadError, Topology, VmError};
+use super::search::{maximize, permutations, Best};
+
+// an amplifier that doesn't wait for input after this many instructions is
+// broken
+const AMPLIFIER_BUDGET: Budget = Budget::unlimited().instructions(1_000_000);
+
+// the first amplifier also gets the input signal 0, the last one's output
+// goes back to the first with `feedback`
+fn amplifiers(program: &Vec<i64>, phases: &Vec<i64>, feedback: bool) -> Result<i64, VmError> {
+    let mut topology = Topology::new();
+    topology.set_budget(AMPLIFIER_BUDGET);
+    let nodes = phases
+        .iter()
+        .enumerate()
+        .map(|(index, phase)| {
+            let inputs = if index == 0 { vec![*phase, 0] } else { vec![*phase] };
+            topology.node(program, &inputs)
+        })
+        .collect::<Vec<_>>();
+    topology.chain(&nodes);
+    if feedback {
+        topology.edge(nodes[nodes.len() - 1], nodes[0]);
     }
 
-    input
+    let outputs = topology.run()?;
+    Ok(*outputs[nodes.len() - 1].last().unwrap_or(&0))
 }
 
-#[aoc_generator(day7)]
-fn load_input(input: &str) -> Vec<i64> {
-    let opcodes = input
-        .split(",")
-        .filter(|v| *v != "\n")
-        .filter(|v| *v != "")
-        .map(|v| v.parse::<i64>().unwrap())
-        .collect::<Vec<_>>();
+fn thruster_output(program: &Vec<i64>, phases: &Vec<i64>) -> Result<i64, VmError> {
+    amplifiers(program, phases, false)
+}
 
-    opcodes
+#[aoc_generator(day7)]
+fn load_input(input: &str) -> Result<Vec<i64>, LoadError> {
+    load(input)
 }
 
 #[aoc(day7, part1)]
-pub fn problem1(opcodes: &Vec<i64>) -> i64 {
-    let mut top = 0;
-    for a in 0..5 {
-        for b in 0..5 {
-            if b == a {
-                continue;
-            }
-            for c in 0..5 {
-                if c == a || c == b {
-                    continue;
-                }
-                for d in 0..5 {
-                    if d == a || d == b || d == c {
-                        continue;
-                    }
-                    for e in 0..5 {
-                        if e == d || e == b || e == c || e == a {
-                            continue;
-                        }
-                        let result = thruster_output(&opcodes, &vec![a, b, c, d, e]);
-                        top = std::cmp::max(top, result);
-                    }
-                }
-            }
-        }
-    }
-
-    top
+pub fn problem1(opcodes: &Vec<i64>) -> Result<Best<Vec<i64>, i64>, VmError> {
+    best_setting(opcodes, 0..5, thruster_output)
 }
 
-fn thruster_feedback_loop(program: &Vec<i64>, phases: &Vec<i64>) -> i64 {
-    let (send_a, recv_a) = channel();
-    send_a.send(phases[0]).unwrap();
-    send_a.send(0).unwrap();
-
-    let (send_b, recv_b) = channel();
-    send_b.send(phases[1]).unwrap();
-
-    let (send_c, recv_c) = channel();
-    send_c.send(phases[2]).unwrap();
-
-    let (send_d, recv_d) = channel();
-    send_d.send(phases[3]).unwrap();
-
-    let (send_e, recv_e) = channel();
-    send_e.send(phases[4]).unwrap();
-
-    let mut threads = vec![];
-    let code_a = program.clone();
-    threads.push(thread::spawn(move || {
-        isa_interpreter_async(code_a, recv_a, send_b)
-    }));
-
-    let code_b = program.clone();
-    threads.push(thread::spawn(move || {
-        isa_interpreter_async(code_b, recv_b, send_c)
-    }));
-
-    let code_c = program.clone();
-    threads.push(thread::spawn(move || {
-        isa_interpreter_async(code_c, recv_c, send_d)
-    }));
-
-    let code_d = program.clone();
-    threads.push(thread::spawn(move || {
-        isa_interpreter_async(code_d, recv_d, send_e)
-    }));
-
-    let code_e = program.clone();
-    threads.push(thread::spawn(move || {
-        isa_interpreter_async(code_e, recv_e, send_a)
-    }));
-
-    let mut results = vec![];
-    for thread in threads {
-        results.push(thread.join().unwrap());
-    }
+// the phase setting with the highest thruster signal
+fn best_setting(
+    program: &Vec<i64>,
+    phases: Range<i64>,
+    signal: fn(&Vec<i64>, &Vec<i64>) -> Result<i64, VmError>,
+) -> Result<Best<Vec<i64>, i64>, VmError> {
+    let best = maximize(permutations(phases), |phases| signal(program, phases))?;
+    Ok(best.expect("there are phase settings"))
+}
 
-    *results.last().unwrap()
+fn thruster_feedback_loop(program: &Vec<i64>, phases: &Vec<i64>) -> Result<i64, VmError> {
+    amplifiers(program, phases, true)
 }
 
 #[aoc(day7, part2)]
-pub fn problem2(opcodes: &Vec<i64>) -> i64 {
-    let mut top = 0;
-    for a in 5..10 {
-        for b in 5..10 {
-            if b == a {
-                continue;
-            }
-            for c in 5..10 {
-                if c == a || c == b {
-                    continue;
-                }
-                for d in 5..10 {
-                    if d == a || d == b || d == c {
-                        continue;
-                    }
-                    for e in 5..10 {
-                        if e == d || e == b || e == c || e == a {
-                            continue;
-                        }
-                        let result = thruster_feedback_loop(&opcodes, &vec![a, b, c, d, e]);
-                        top = std::cmp::max(top, result);
-                    }
-                }
-            }
-        }
-    }
-
-    top
+pub fn problem2(opcodes: &Vec<i64>) -> Result<Best<Vec<i64>, i64>, VmError> {
+    best_setting(opcodes, 5..10, thruster_feedback_loop)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::intcode::assemble;
 
     #[test]
     fn problem1_example1() {
         let program = vec![
             3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
         ];
-        assert_eq!(thruster_output(&program, &vec![4, 3, 2, 1, 0]), 43210);
+        assert_eq!(thruster_output(&program, &vec![4, 3, 2, 1, 0]), Ok(43210));
     }
 
     #[test]
@@ -155,7 +81,7 @@ mod test {
             3, 23, 3, 24, 1002, 24, 10, 24, 1002, 23, -1, 23, 101, 5, 23, 23, 1, 24, 23, 23, 4, 23,
             99, 0, 0,
         ];
-        assert_eq!(thruster_output(&program, &vec![0, 1, 2, 3, 4]), 54321);
+        assert_eq!(thruster_output(&program, &vec![0, 1, 2, 3, 4]), Ok(54321));
     }
 
     #[test]
@@ -164,7 +90,7 @@ mod test {
             3, 31, 3, 32, 1002, 32, 10, 32, 1001, 31, -2, 31, 1007, 31, 0, 33, 1002, 33, 7, 33, 1,
             33, 31, 31, 1, 32, 31, 31, 4, 31, 99, 0, 0, 0,
         ];
-        assert_eq!(thruster_output(&program, &vec![1, 0, 4, 3, 2]), 65210);
+        assert_eq!(thruster_output(&program, &vec![1, 0, 4, 3, 2]), Ok(65210));
     }
 
     #[test]
@@ -175,7 +101,32 @@ mod test {
         ];
         assert_eq!(
             thruster_feedback_loop(&program, &vec![9, 8, 7, 6, 5]),
-            139629729
+            Ok(139629729)
+        );
+    }
+
+    #[test]
+    fn problem2_example1_assembled() {
+        let program = assemble(
+            "
+                    in [phase]
+                    add [phase], -4, [phase]
+            loop:   in [signal]
+                    mul [signal], 2, [signal]
+                    add [signal], [phase], [signal]
+                    out [signal]
+                    add [rounds], -1, [rounds]
+                    jnz [rounds], loop
+                    hlt
+            phase:  .data 0
+            signal: .data 0
+            rounds: .data 5
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            thruster_feedback_loop(&program, &vec![9, 8, 7, 6, 5]),
+            Ok(139629729)
         );
     }
 
@@ -188,7 +139,7 @@ mod test {
         ];
         assert_eq!(
             thruster_feedback_loop(&program, &vec![9, 7, 8, 5, 6]),
-            18216
+            Ok(18216)
         );
     }
 }
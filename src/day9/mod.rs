@@ -1,27 +1,22 @@
-use super::intcode::isa_interpreter;
+use super::intcode::{isa_interpreter, load, LoadError, VmError};
 
 #[aoc_generator(day9)]
-fn parse_input(input: &str) -> Vec<i64> {
-    input
-        .split(",")
-        .filter(|v| *v != "\n")
-        .filter(|v| *v != "")
-        .map(|v| v.parse::<i64>().unwrap())
-        .collect::<Vec<_>>()
+fn parse_input(input: &str) -> Result<Vec<i64>, LoadError> {
+    load(input)
 }
 
-fn run_with_input(code: &Vec<i64>, input: i64) -> i64 {
+fn run_with_input(code: &Vec<i64>, input: i64) -> Result<i64, VmError> {
     let mut instructions = code.clone();
     isa_interpreter(&mut instructions, input)
 }
 
 #[aoc(day9, part1)]
-pub fn problem1(code: &Vec<i64>) -> i64 {
+pub fn problem1(code: &Vec<i64>) -> Result<i64, VmError> {
     run_with_input(code, 1)
 }
 
 #[aoc(day9, part2)]
-pub fn problem2(code: &Vec<i64>) -> i64 {
+pub fn problem2(code: &Vec<i64>) -> Result<i64, VmError> {
     run_with_input(code, 2)
 }
 
@@ -31,8 +26,8 @@ mod test {
 
     #[test]
     fn problem1_example1() {
-        let mut instructions = parse_input("1102,34915192,34915192,7,4,7,99,0");
-        assert_eq!(isa_interpreter(&mut instructions, 1), 1219070632396864);
+        let mut instructions = parse_input("1102,34915192,34915192,7,4,7,99,0").unwrap();
+        assert_eq!(isa_interpreter(&mut instructions, 1), Ok(1219070632396864));
     }
 
     #[test]
@@ -43,7 +38,7 @@ mod test {
 
     #[test]
     fn problem1_example3() {
-        let mut instructions = parse_input("104,1125899906842624,99");
-        assert_eq!(isa_interpreter(&mut instructions, 1), 1125899906842624);
+        let mut instructions = parse_input("104,1125899906842624,99").unwrap();
+        assert_eq!(isa_interpreter(&mut instructions, 1), Ok(1125899906842624));
     }
 }
@@ -0,0 +1,442 @@
+//! Static analysis of Intcode programs.
+//!
+//! `analyze` explores a program from address 0, following `jnz` and `jz`
+//! with immediate targets. Jumps whose condition is an immediate are taken
+//! for what they are, so nothing behind an unconditional jump is decoded
+//! unless something else leads there.
+//!
+//! Compiled programs call subroutines by pushing the return address to the
+//! stack and jumping, and return with a jump to that stack slot:
+//!
+//! ```text
+//!   mul 1, 347, [rb+0]
+//!   jz 0, 451
+//! 347: ...
+//!
+//! 451: arb 2
+//!   ...
+//!   arb -2
+//!   jnz 1, [rb+0]
+//! ```
+//!
+//! Both are recognized, so the code after a call is known to be reachable
+//! and returns lead back to the callers. Constants passed to a subroutine
+//! that point at an `arb` are taken to be function pointers, which makes the
+//! code they point to reachable as well.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::{Instruction, Mode, Opcode};
+
+/// How control leaves a basic block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+  /// Falling through to the next instruction, including branches not taken.
+  Next,
+  Jump,
+  Call,
+  Return,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Edge {
+  pub from: usize,
+  pub to: usize,
+  pub kind: EdgeKind,
+}
+
+/// Where execution goes after the last instruction of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+  /// Continues with the instruction after the block.
+  Next,
+  Halt,
+  /// A conditional jump, continuing after the block if it is not taken.
+  Branch(usize),
+  Jump(usize),
+  Call { target: usize, returns: usize },
+  /// A call through a function pointer.
+  CallIndirect { returns: usize },
+  Return,
+  /// A jump to a computed or invalid address, maybe conditional.
+  Indirect { conditional: bool },
+}
+
+impl Exit {
+  pub fn falls_through(self) -> bool {
+    matches!(self, Exit::Next | Exit::Branch(_) | Exit::Indirect { conditional: true })
+  }
+}
+
+/// A run of instructions that is only entered at its first instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+  pub start: usize,
+  pub instructions: Vec<Instruction>,
+  pub exit: Exit,
+}
+
+impl Block {
+  /// The address after the last instruction.
+  pub fn end(&self) -> usize {
+    self.instructions.last().map_or(self.start, |last| last.address + last.size())
+  }
+}
+
+/// The control-flow graph and the cells a program writes to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Analysis {
+  pub blocks: BTreeMap<usize, Block>,
+  pub edges: Vec<Edge>,
+  /// Entry points of called subroutines and function pointers.
+  pub subroutines: BTreeSet<usize>,
+  /// Cells written by instructions with a position mode target.
+  pub mutable: BTreeSet<usize>,
+  /// Known targets of computed jumps, see `analyze_with_targets`.
+  pub targets: BTreeSet<usize>,
+}
+
+/// Decodes the instruction at `address` if it can be printed and fits into
+/// the program.
+fn decode(program: &[i64], address: usize) -> Option<Instruction> {
+  let instruction = Instruction::decode(|a| program.get(a).copied().unwrap_or(0), address).ok()?;
+  if instruction.is_canonical() && address + instruction.size() <= program.len() {
+    Some(instruction)
+  } else {
+    None
+  }
+}
+
+/// Whether `value` points at an `arb`, the first instruction of compiled
+/// functions.
+fn is_function(program: &[i64], value: i64) -> bool {
+  value >= 0 && decode(program, value as usize).is_some_and(|instruction| instruction.opcode == Opcode::Arb)
+}
+
+/// The value an `add` or `mul` of two immediates pushes to the stack.
+fn pushed(instruction: &Instruction) -> Option<i64> {
+  let params = &instruction.params;
+  if params.len() != 3 || params[2].mode != Mode::Relative {
+    return None;
+  }
+  if params[0].mode != Mode::Immediate || params[1].mode != Mode::Immediate {
+    return None;
+  }
+  match instruction.opcode {
+    Opcode::Add => params[0].value.checked_add(params[1].value),
+    Opcode::Mul => params[0].value.checked_mul(params[1].value),
+    _ => None,
+  }
+}
+
+/// Where execution goes after `instruction`. `stack` holds the values pushed
+/// earlier in the same straight-line run.
+fn exit(instruction: &Instruction, stack: &[i64]) -> Exit {
+  let next = instruction.address + instruction.size();
+  let (condition, target) = match instruction.opcode {
+    Opcode::Hlt => return Exit::Halt,
+    Opcode::Jnz | Opcode::Jz => (instruction.params[0], instruction.params[1]),
+    _ => return Exit::Next,
+  };
+
+  let taken = match (condition.mode, instruction.opcode) {
+    (Mode::Immediate, Opcode::Jnz) => Some(condition.value != 0),
+    (Mode::Immediate, _) => Some(condition.value == 0),
+    _ => None,
+  };
+  match (taken, target.mode) {
+    (Some(false), _) => Exit::Next,
+    (taken, Mode::Immediate) if target.value < 0 => Exit::Indirect { conditional: taken.is_none() },
+    (None, Mode::Immediate) => Exit::Branch(target.value as usize),
+    (Some(true), Mode::Immediate) if stack.contains(&(next as i64)) => {
+      Exit::Call { target: target.value as usize, returns: next }
+    },
+    (Some(true), Mode::Immediate) => Exit::Jump(target.value as usize),
+    (Some(true), _) if stack.contains(&(next as i64)) => Exit::CallIndirect { returns: next },
+    (Some(true), Mode::Relative) => Exit::Return,
+    (taken, _) => Exit::Indirect { conditional: taken.is_none() },
+  }
+}
+
+/// Builds the control-flow graph of a program, see the module docs.
+pub fn analyze(program: &[i64]) -> Analysis {
+  analyze_with_targets(program, &[])
+}
+
+/// Like `analyze`, but with addresses computed jumps are known to go to, for
+/// example taken from a trace. Every computed jump is assumed to go to any of
+/// them.
+pub fn analyze_with_targets(program: &[i64], targets: &[usize]) -> Analysis {
+  let mut code: BTreeMap<usize, (Instruction, Exit)> = BTreeMap::new();
+  let mut covered = vec![false; program.len()];
+  let mut leaders = BTreeSet::new();
+  let mut subroutines = BTreeSet::new();
+  let mut todo = vec![0];
+  todo.extend(targets);
+
+  while let Some(start) = todo.pop() {
+    leaders.insert(start);
+    let mut address = start;
+    let mut stack = vec![];
+
+    while address < program.len() && !covered[address] {
+      let instruction = match decode(program, address) {
+        Some(instruction) => instruction,
+        None => break,
+      };
+      let span = address..address + instruction.size();
+      if covered[span.clone()].iter().any(|c| *c) {
+        break;
+      }
+      for cell in span.clone() {
+        covered[cell] = true;
+      }
+
+      let exit = exit(&instruction, &stack);
+      match exit {
+        Exit::Branch(target) | Exit::Jump(target) => todo.push(target),
+        Exit::Call { target, returns } => {
+          subroutines.insert(target);
+          todo.extend([target, returns]);
+        },
+        Exit::CallIndirect { returns } => todo.push(returns),
+        _ => {},
+      }
+      if let Exit::Call { returns, .. } | Exit::CallIndirect { returns } = exit {
+        let pointers = stack.iter().filter(|value| **value != returns as i64 && is_function(program, **value));
+        for &pointer in pointers {
+          subroutines.insert(pointer as usize);
+          todo.push(pointer as usize);
+        }
+      }
+      if let Exit::Branch(_) | Exit::Indirect { conditional: true } = exit {
+        todo.push(span.end);
+      }
+      stack.extend(pushed(&instruction));
+      code.insert(address, (instruction, exit));
+
+      if exit != Exit::Next {
+        break;
+      }
+      address = span.end;
+    }
+  }
+
+  let mut blocks: BTreeMap<usize, Block> = BTreeMap::new();
+  let mut current: Option<Block> = None;
+  for (address, (instruction, exit)) in code {
+    let joins = current.as_ref().is_some_and(|block| block.end() == address) && !leaders.contains(&address);
+    if !joins {
+      if let Some(block) = current.take() {
+        blocks.insert(block.start, block);
+      }
+      current = Some(Block { start: address, instructions: vec![], exit: Exit::Next });
+    }
+
+    let block = current.as_mut().unwrap();
+    block.instructions.push(instruction);
+    if exit != Exit::Next {
+      block.exit = exit;
+      blocks.insert(block.start, current.take().unwrap());
+    }
+  }
+  if let Some(block) = current {
+    blocks.insert(block.start, block);
+  }
+
+  subroutines.retain(|entry| blocks.contains_key(entry));
+  let targets = targets.iter().copied().filter(|target| blocks.contains_key(target)).collect::<BTreeSet<_>>();
+  let mut edges = vec![];
+  for block in blocks.values() {
+    let mut edge = |to: usize, kind: EdgeKind| {
+      if blocks.contains_key(&to) {
+        edges.push(Edge { from: block.start, to, kind });
+      }
+    };
+    match block.exit {
+      Exit::Branch(target) | Exit::Jump(target) => edge(target, EdgeKind::Jump),
+      Exit::Call { target, returns } => {
+        edge(target, EdgeKind::Call);
+        edge(returns, EdgeKind::Next);
+      },
+      Exit::CallIndirect { returns } => edge(returns, EdgeKind::Next),
+      Exit::Indirect { .. } => {
+        for &target in &targets {
+          edge(target, EdgeKind::Jump);
+        }
+      },
+      _ => {},
+    }
+    if block.exit.falls_through() {
+      edge(block.end(), EdgeKind::Next);
+    }
+  }
+
+  // a subroutine is everything reachable from its entry without following
+  // calls, every return in there goes back to all of its callers
+  for &entry in &subroutines {
+    let callers = edges
+      .iter()
+      .filter(|edge| edge.kind == EdgeKind::Call && edge.to == entry)
+      .filter_map(|edge| match blocks[&edge.from].exit {
+        Exit::Call { returns, .. } if blocks.contains_key(&returns) => Some(returns),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+
+    let mut seen = BTreeSet::new();
+    let mut todo = vec![entry];
+    while let Some(start) = todo.pop() {
+      if !seen.insert(start) {
+        continue;
+      }
+      todo.extend(edges.iter().filter(|edge| edge.from == start && edge.kind != EdgeKind::Call).map(|edge| edge.to));
+    }
+
+    for start in seen {
+      if blocks[&start].exit == Exit::Return {
+        for &to in &callers {
+          edges.push(Edge { from: start, to, kind: EdgeKind::Return });
+        }
+      }
+    }
+  }
+  edges.sort_by_key(|edge| (edge.from, edge.to));
+  edges.dedup();
+
+  let mut mutable = BTreeSet::new();
+  for instruction in blocks.values().flat_map(|block| &block.instructions) {
+    if let Some(index) = instruction.opcode.write_param() {
+      let target = instruction.params[index];
+      if target.mode == Mode::Position && target.value >= 0 {
+        mutable.insert(target.value as usize);
+      }
+    }
+  }
+
+  Analysis { blocks, edges, subroutines, mutable, targets }
+}
+
+impl Analysis {
+  /// The instruction starting at `address`, if it is reachable code.
+  pub fn instruction(&self, address: usize) -> Option<&Instruction> {
+    let (_, block) = self.blocks.range(..=address).next_back()?;
+    block.instructions.iter().find(|instruction| instruction.address == address)
+  }
+
+  /// Whether `address` belongs to a reachable instruction.
+  pub fn is_code(&self, address: usize) -> bool {
+    self.blocks
+      .range(..=address)
+      .next_back()
+      .is_some_and(|(_, block)| address < block.end())
+  }
+
+  /// Code cells the program writes to, i.e. where it modifies itself.
+  pub fn self_modified(&self) -> BTreeSet<usize> {
+    self.mutable.iter().copied().filter(|address| self.is_code(*address)).collect()
+  }
+
+  /// Renders the control-flow graph in Graphviz DOT. Subroutine entries are
+  /// drawn with a double border, calls bold and returns dashed.
+  pub fn to_dot(&self) -> String {
+    let mut dot = String::from("digraph cfg {\n  node [shape=box, fontname=\"monospace\"];\n");
+
+    for block in self.blocks.values() {
+      let mut label = String::new();
+      for instruction in &block.instructions {
+        write!(label, "{}: {}\\l", instruction.address, instruction).unwrap();
+      }
+      let border = if self.subroutines.contains(&block.start) { ", peripheries=2" } else { "" };
+      writeln!(dot, "  b{} [label=\"{}\"{}];", block.start, label, border).unwrap();
+    }
+
+    for edge in &self.edges {
+      let style = match edge.kind {
+        EdgeKind::Next => "",
+        EdgeKind::Jump => " [label=\"jump\"]",
+        EdgeKind::Call => " [label=\"call\", style=bold]",
+        EdgeKind::Return => " [label=\"return\", style=dashed]",
+      };
+      writeln!(dot, "  b{} -> b{}{};", edge.from, edge.to, style).unwrap();
+    }
+
+    dot.push_str("}\n");
+    dot
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{assemble, disassemble, parse_instructions, render};
+
+  // doubles its input in a subroutine; the data after `hlt` looks like code
+  fn doubler() -> Vec<i64> {
+    assemble("
+              arb stack
+              in [n]
+              call double
+              out [n]
+              hlt
+              .data 1, 2, 3, 4
+      double: mul [n], 2, [n]
+              ret
+      n:      .data 0
+      stack:  .data 0
+    ").unwrap()
+  }
+
+  #[test]
+  fn follows_calls_and_returns() {
+    let analysis = analyze(&doubler());
+
+    assert_eq!(analysis.blocks.keys().copied().collect::<Vec<_>>(), vec![0, 13, 20]);
+    assert_eq!(analysis.subroutines, BTreeSet::from([20]));
+    assert_eq!(analysis.edges, vec![
+      Edge { from: 0, to: 13, kind: EdgeKind::Next },
+      Edge { from: 0, to: 20, kind: EdgeKind::Call },
+      Edge { from: 20, to: 13, kind: EdgeKind::Return },
+    ]);
+    assert_eq!(analysis.mutable, BTreeSet::from([29]));
+    assert!(analysis.self_modified().is_empty());
+  }
+
+  #[test]
+  fn code_behind_unconditional_jumps_is_data() {
+    let listing = render(&disassemble(&doubler()));
+
+    assert!(listing.contains("16: .data 1\n17: .data 2\n18: .data 3\n19: .data 4\n"));
+    assert!(listing.contains("13: out [29]\n15: hlt\n"));
+    assert!(analysis_of("1106,0,7,1,0,0,3,99").instruction(3).is_none());
+  }
+
+  fn analysis_of(program: &str) -> Analysis {
+    analyze(&parse_instructions(program))
+  }
+
+  #[test]
+  fn finds_self_modifying_writes() {
+    let analysis = analysis_of("1,0,0,3,1002,7,3,7,99");
+
+    assert_eq!(analysis.mutable, BTreeSet::from([3, 7]));
+    assert_eq!(analysis.self_modified(), BTreeSet::from([3, 7]));
+  }
+
+  #[test]
+  fn exports_dot() {
+    let dot = analysis_of("3,9,1005,9,8,104,0,99,104,1,99").to_dot();
+
+    assert_eq!(dot, concat!(
+      "digraph cfg {\n",
+      "  node [shape=box, fontname=\"monospace\"];\n",
+      "  b0 [label=\"0: in [9]\\l2: jnz [9], 8\\l\"];\n",
+      "  b5 [label=\"5: out 0\\l7: hlt\\l\"];\n",
+      "  b8 [label=\"8: out 1\\l10: hlt\\l\"];\n",
+      "  b0 -> b5;\n",
+      "  b0 -> b8 [label=\"jump\"];\n",
+      "}\n",
+    ));
+  }
+}
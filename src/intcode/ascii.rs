@@ -0,0 +1,215 @@
+use std::io::{self, BufRead, Write};
+
+use super::{Machine, Memory, PagedMemory, State, VmError};
+
+/// Whether an output value is a character. Everything else, like the hull
+/// damage in day 21, is a numeric result.
+fn is_char(value: i64) -> bool {
+  (0..=255).contains(&value)
+}
+
+/// Line based text I/O for Intcode programs that talk ASCII.
+///
+/// Output values between 0 and 255 are collected as text, all other values
+/// are kept apart as numeric results.
+#[derive(Debug, Clone)]
+pub struct AsciiConsole<M: Memory = PagedMemory> {
+  machine: Machine<M>,
+  text: String,
+  values: Vec<i64>,
+}
+
+impl AsciiConsole {
+  pub fn new(program: &[i64]) -> AsciiConsole {
+    AsciiConsole::with_machine(Machine::new(program))
+  }
+}
+
+impl<M: Memory> AsciiConsole<M> {
+  pub fn with_machine(machine: Machine<M>) -> AsciiConsole<M> {
+    AsciiConsole { machine, text: String::new(), values: vec![] }
+  }
+
+  pub fn machine(&self) -> &Machine<M> {
+    &self.machine
+  }
+
+  pub fn machine_mut(&mut self) -> &mut Machine<M> {
+    &mut self.machine
+  }
+
+  pub fn into_machine(self) -> Machine<M> {
+    self.machine
+  }
+
+  pub fn state(&self) -> State {
+    self.machine.state()
+  }
+
+  /// Queues the characters of `text` as input.
+  pub fn write_str(&mut self, text: &str) {
+    self.machine.extend_input(text.chars().map(|c| c as i64));
+  }
+
+  /// Queues `line` followed by a newline as input.
+  pub fn write_line(&mut self, line: &str) {
+    self.write_str(line);
+    self.machine.push_input('\n' as i64);
+  }
+
+  fn collect(&mut self) {
+    while let Some(value) = self.machine.pop_output() {
+      if is_char(value) {
+        self.text.push(value as u8 as char);
+      } else {
+        self.values.push(value);
+      }
+    }
+  }
+
+  /// Runs the program until it halts or waits for input. Its output can be
+  /// collected with `take_text` and `take_values` afterwards.
+  pub fn run(&mut self) -> Result<State, VmError> {
+    let state = self.machine.run()?;
+    self.collect();
+    Ok(state)
+  }
+
+  /// Runs the program until it printed a complete line and returns the line
+  /// without the newline. Returns `None` if the program halted or waits for
+  /// input first; a partial line is left in the text buffer.
+  pub fn read_line(&mut self) -> Result<Option<String>, VmError> {
+    loop {
+      if let Some(end) = self.text.find('\n') {
+        let line = self.text[..end].to_string();
+        self.text.drain(..=end);
+        return Ok(Some(line));
+      }
+
+      match self.machine.run_until_io()? {
+        State::HasOutput => self.collect(),
+        _ => return Ok(None),
+      }
+    }
+  }
+
+  /// Whether the program waits for input and the text printed last, ignoring
+  /// trailing whitespace, is `prompt`.
+  pub fn at_prompt(&self, prompt: &str) -> bool {
+    self.machine.state() == State::NeedsInput && self.text.trim_end().ends_with(prompt)
+  }
+
+  /// Removes and returns the text collected so far.
+  pub fn take_text(&mut self) -> String {
+    std::mem::take(&mut self.text)
+  }
+
+  /// Removes and returns the numeric results collected so far.
+  pub fn take_values(&mut self) -> Vec<i64> {
+    std::mem::take(&mut self.values)
+  }
+
+  /// Connects the program to a terminal: everything it prints is written to
+  /// `output`, numeric results on lines of their own, and whenever it waits
+  /// for input a line is read from `input`. Returns when the program halts or
+  /// `input` is exhausted.
+  pub fn interactive<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> io::Result<State> {
+    write!(output, "{}", self.take_text())?;
+    for value in self.take_values() {
+      writeln!(output, "{}", value)?;
+    }
+
+    loop {
+      let state = self.machine.run().map_err(io::Error::other)?;
+      while let Some(value) = self.machine.pop_output() {
+        if is_char(value) {
+          write!(output, "{}", value as u8 as char)?;
+        } else {
+          writeln!(output, "{}", value)?;
+        }
+      }
+      output.flush()?;
+
+      if state == State::Halted {
+        return Ok(state);
+      }
+
+      let mut line = String::new();
+      if input.read_line(&mut line)? == 0 {
+        return Ok(state);
+      }
+      self.write_line(line.trim_end_matches(['\r', '\n']));
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::assemble;
+
+  // greets, counts the characters of the name it reads and says goodbye
+  fn greeter() -> Vec<i64> {
+    assemble("
+              arb hello
+      hi:     jz [rb+0], read
+              out [rb+0]
+              arb 1
+              jz 0, hi
+      read:   in [c]
+              eq [c], 10, [t]
+              jnz [t], done
+              add [n], 1, [n]
+              jz 0, read
+      done:   mul [n], 1000, [n]
+              out [n]
+              arb 1
+      bye:    jz [rb+0], end
+              out [rb+0]
+              arb 1
+              jz 0, bye
+      end:    hlt
+
+      c:      .data 0
+      t:      .data 0
+      n:      .data 0
+      hello:  .data \"Hello\", 10, \"Name?\", 10, 0
+              .data \"Bye\", 10, 0
+    ").unwrap()
+  }
+
+  #[test]
+  fn reads_lines_and_detects_prompts() {
+    let mut console = AsciiConsole::new(&greeter());
+    assert_eq!(console.read_line(), Ok(Some("Hello".to_string())));
+    assert!(!console.at_prompt("Name?"));
+    assert_eq!(console.read_line(), Ok(Some("Name?".to_string())));
+    assert_eq!(console.read_line(), Ok(None));
+    assert_eq!(console.state(), State::NeedsInput);
+
+    let mut console = AsciiConsole::new(&greeter());
+    assert_eq!(console.run(), Ok(State::NeedsInput));
+    assert!(console.at_prompt("Name?"));
+    assert_eq!(console.take_text(), "Hello\nName?\n");
+  }
+
+  #[test]
+  fn separates_numeric_results() {
+    let mut console = AsciiConsole::new(&greeter());
+    console.write_line("Ada");
+    assert_eq!(console.run(), Ok(State::Halted));
+    assert_eq!(console.take_values(), vec![3000]);
+    assert_eq!(console.take_text(), "Hello\nName?\nBye\n");
+  }
+
+  #[test]
+  fn interactive_passthrough() {
+    let mut console = AsciiConsole::new(&greeter());
+    let mut output = vec![];
+    assert_eq!(console.interactive(&b"Grace\r\n"[..], &mut output).unwrap(), State::Halted);
+    assert_eq!(String::from_utf8(output).unwrap(), "Hello\nName?\n5000\nBye\n");
+
+    let mut console = AsciiConsole::new(&greeter());
+    assert_eq!(console.interactive(&b""[..], vec![]).unwrap(), State::NeedsInput);
+  }
+}
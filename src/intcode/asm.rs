@@ -0,0 +1,522 @@
+//! A two-pass assembler for the syntax printed by `render`.
+//!
+//! ```text
+//! ; comments start with a semicolon
+//!         in [n]
+//! loop:   out [n]                 ; labels end with a colon
+//!         add [n], -1, [n]
+//!         jnz [n], loop
+//!         hlt
+//! n:      .data 0
+//! ```
+//!
+//! Operands are immediate (`5`, `loop`, `n+1`), position (`[12]`, `[n]`) or
+//! relative to the relative base (`[rb+3]`, `[rb-1]`). A leading number with
+//! a colon, like the addresses in a disassembly listing, asserts that the line
+//! starts at that address. `.data` and its alias `db` emit numbers, label
+//! addresses and the characters of double quoted strings.
+//!
+//! The relative base can be used as a stack pointer with the macros
+//! `push x`, `pop [x]`, `call target` and `ret`. The stack grows upwards and
+//! the relative base points at the next free cell. Relative operands of the
+//! macros refer to the base before the macro adjusts it.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{Instruction, Mode, Opcode, Param};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+  pub line: usize,
+  pub what: String,
+}
+
+impl AsmError {
+  fn new(line: usize, what: &str) -> AsmError {
+    AsmError { line, what: what.to_string() }
+  }
+}
+
+impl fmt::Display for AsmError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.what)
+  }
+}
+
+impl std::error::Error for AsmError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+  Number(i64),
+  Symbol(String),
+}
+
+/// A sum of signed numbers and labels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Expr(Vec<(i64, Term)>);
+
+impl Expr {
+  fn number(value: i64) -> Expr {
+    Expr(vec![(1, Term::Number(value))])
+  }
+
+  fn parse(text: &str) -> Result<Expr, String> {
+    let invalid = || format!("invalid expression '{}'", text.trim());
+    let mut terms = vec![];
+    let mut rest = text.trim();
+    if rest.is_empty() {
+      return Err(invalid());
+    }
+
+    while !rest.is_empty() {
+      let mut sign = 1;
+      while let Some(c) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        if c == '-' {
+          sign = -sign;
+        }
+        rest = rest[1..].trim_start();
+      }
+
+      let end = rest.find(['+', '-']).unwrap_or(rest.len());
+      let token = rest[..end].trim();
+      let term = if token.starts_with(|c: char| c.is_ascii_digit()) {
+        // parsed with its sign, -9223372036854775808 has no positive counterpart
+        let signed = if sign < 0 { format!("-{}", token) } else { token.to_string() };
+        sign = 1;
+        Term::Number(signed.parse::<i64>().map_err(|_| format!("invalid number '{}'", signed))?)
+      } else if is_identifier(token) {
+        Term::Symbol(token.to_string())
+      } else {
+        return Err(invalid());
+      };
+      terms.push((sign, term));
+      rest = &rest[end..];
+    }
+
+    Ok(Expr(terms))
+  }
+
+  fn eval(&self, symbols: &HashMap<String, i64>) -> Result<i64, String> {
+    let mut value = 0i64;
+    for (sign, term) in &self.0 {
+      let term = match term {
+        Term::Number(n) => *n,
+        Term::Symbol(name) => *symbols.get(name).ok_or(format!("undefined label '{}'", name))?,
+      };
+      value = sign
+        .checked_mul(term)
+        .and_then(|term| value.checked_add(term))
+        .ok_or_else(|| "expression overflows".to_string())?;
+    }
+    Ok(value)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Operand {
+  mode: Mode,
+  value: Expr,
+}
+
+impl Operand {
+  fn immediate(value: i64) -> Operand {
+    Operand { mode: Mode::Immediate, value: Expr::number(value) }
+  }
+
+  fn relative(offset: i64) -> Operand {
+    Operand { mode: Mode::Relative, value: Expr::number(offset) }
+  }
+
+  fn parse(text: &str) -> Result<Operand, String> {
+    let text = text.trim();
+    if let Some(inner) = text.strip_prefix('[') {
+      let inner = inner
+        .strip_suffix(']')
+        .ok_or(format!("missing ']' in '{}'", text))?
+        .trim();
+      if let Some(offset) = inner.strip_prefix("rb") {
+        let offset = offset.trim();
+        if offset.is_empty() {
+          return Ok(Operand::relative(0));
+        }
+        if offset.starts_with('+') || offset.starts_with('-') {
+          return Ok(Operand { mode: Mode::Relative, value: Expr::parse(offset)? });
+        }
+      }
+      Ok(Operand { mode: Mode::Position, value: Expr::parse(inner)? })
+    } else {
+      Ok(Operand { mode: Mode::Immediate, value: Expr::parse(text)? })
+    }
+  }
+
+  /// The same operand as seen after the relative base moved by `delta`.
+  fn rebased(&self, delta: i64) -> Operand {
+    if self.mode == Mode::Relative {
+      let mut value = self.value.clone();
+      value.0.push((-1, Term::Number(delta)));
+      Operand { mode: Mode::Relative, value }
+    } else {
+      self.clone()
+    }
+  }
+}
+
+fn is_identifier(text: &str) -> bool {
+  let mut chars = text.chars();
+  match chars.next() {
+    Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => (),
+    _ => return false,
+  }
+  text != "rb" && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Splits at commas that are not part of a string literal.
+fn split_operands(text: &str) -> Vec<String> {
+  let mut operands = vec![];
+  let mut current = String::new();
+  let mut in_string = false;
+  let mut escaped = false;
+
+  for c in text.chars() {
+    if in_string {
+      current.push(c);
+      if escaped {
+        escaped = false;
+      } else if c == '\\' {
+        escaped = true;
+      } else if c == '"' {
+        in_string = false;
+      }
+    } else if c == ',' {
+      operands.push(current.trim().to_string());
+      current.clear();
+    } else {
+      if c == '"' {
+        in_string = true;
+      }
+      current.push(c);
+    }
+  }
+
+  if !current.trim().is_empty() || !operands.is_empty() {
+    operands.push(current.trim().to_string());
+  }
+  operands
+}
+
+/// Removes a trailing comment that is not part of a string literal.
+fn strip_comment(line: &str) -> &str {
+  let mut in_string = false;
+  let mut escaped = false;
+  for (index, c) in line.char_indices() {
+    if in_string {
+      if escaped {
+        escaped = false;
+      } else if c == '\\' {
+        escaped = true;
+      } else if c == '"' {
+        in_string = false;
+      }
+    } else if c == '"' {
+      in_string = true;
+    } else if c == ';' {
+      return &line[..index];
+    }
+  }
+  line
+}
+
+fn parse_string(text: &str) -> Result<Vec<i64>, String> {
+  let inner = text
+    .strip_prefix('"')
+    .and_then(|t| t.strip_suffix('"'))
+    .ok_or(format!("unterminated string {}", text))?;
+
+  let mut values = vec![];
+  let mut chars = inner.chars();
+  while let Some(c) = chars.next() {
+    let c = if c == '\\' {
+      match chars.next() {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('\\') => '\\',
+        Some('"') => '"',
+        Some('0') => '\0',
+        other => return Err(format!("invalid escape sequence \\{}", other.map_or(String::new(), |c| c.to_string()))),
+      }
+    } else {
+      c
+    };
+    values.push(c as i64);
+  }
+  Ok(values)
+}
+
+enum Item {
+  Instructions(Vec<(Opcode, Vec<Operand>)>),
+  Data(Vec<Expr>),
+}
+
+impl Item {
+  fn size(&self) -> usize {
+    match self {
+      Item::Instructions(instructions) => instructions.iter().map(|(op, _)| op.size()).sum(),
+      Item::Data(values) => values.len(),
+    }
+  }
+}
+
+/// Turns a mnemonic or macro with its operands into instructions. `address`
+/// is where the expansion will be placed.
+fn expand(mnemonic: &str, operands: Vec<Operand>, address: usize) -> Result<Vec<(Opcode, Vec<Operand>)>, String> {
+  let expect = |count: usize| {
+    if operands.len() == count {
+      Ok(())
+    } else {
+      Err(format!("'{}' expects {} operand(s), got {}", mnemonic, count, operands.len()))
+    }
+  };
+  let zero = Operand::immediate(0);
+
+  let instructions = match mnemonic {
+    "push" => {
+      expect(1)?;
+      vec![
+        (Opcode::Add, vec![operands[0].clone(), zero, Operand::relative(0)]),
+        (Opcode::Arb, vec![Operand::immediate(1)]),
+      ]
+    },
+    "pop" => {
+      expect(1)?;
+      vec![
+        (Opcode::Add, vec![Operand::relative(-1), zero, operands[0].clone()]),
+        (Opcode::Arb, vec![Operand::immediate(-1)]),
+      ]
+    },
+    "call" => {
+      expect(1)?;
+      let size = Opcode::Add.size() + Opcode::Arb.size() + Opcode::Jz.size();
+      vec![
+        (Opcode::Add, vec![Operand::immediate((address + size) as i64), zero.clone(), Operand::relative(0)]),
+        (Opcode::Arb, vec![Operand::immediate(1)]),
+        (Opcode::Jz, vec![zero, operands[0].rebased(1)]),
+      ]
+    },
+    "ret" => {
+      expect(0)?;
+      vec![
+        (Opcode::Arb, vec![Operand::immediate(-1)]),
+        (Opcode::Jz, vec![zero, Operand::relative(0)]),
+      ]
+    },
+    _ => {
+      let opcode = Opcode::from_mnemonic(mnemonic).ok_or(format!("unknown mnemonic '{}'", mnemonic))?;
+      expect(opcode.arity())?;
+      if let Some(index) = opcode.write_param() {
+        if operands[index].mode == Mode::Immediate {
+          return Err(format!("'{}' cannot write to an immediate operand", mnemonic));
+        }
+      }
+      vec![(opcode, operands)]
+    },
+  };
+
+  Ok(instructions)
+}
+
+fn parse_item(statement: &str, address: usize) -> Result<Item, String> {
+  let (mnemonic, rest) = match statement.find(char::is_whitespace) {
+    Some(index) => (&statement[..index], statement[index..].trim()),
+    None => (statement, ""),
+  };
+  let operands = split_operands(rest);
+
+  if mnemonic == ".data" || mnemonic == "db" {
+    if operands.is_empty() {
+      return Err(format!("'{}' expects at least one value", mnemonic));
+    }
+    let mut values = vec![];
+    for operand in operands {
+      if operand.starts_with('"') {
+        values.extend(parse_string(&operand)?.into_iter().map(Expr::number));
+      } else {
+        values.push(Expr::parse(&operand)?);
+      }
+    }
+    return Ok(Item::Data(values));
+  }
+
+  let operands = operands
+    .iter()
+    .map(|operand| Operand::parse(operand))
+    .collect::<Result<Vec<_>, _>>()?;
+  Ok(Item::Instructions(expand(mnemonic, operands, address)?))
+}
+
+/// Assembles a program. Errors report the 1-based source line.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+  let mut symbols: HashMap<String, i64> = HashMap::new();
+  let mut items = vec![];
+  let mut address = 0;
+
+  for (index, line) in source.lines().enumerate() {
+    let line_number = index + 1;
+    let mut statement = strip_comment(line).trim();
+
+    while let Some(colon) = statement.find(':') {
+      let prefix = statement[..colon].trim();
+      if let Ok(expected) = prefix.parse::<usize>() {
+        if expected != address {
+          return Err(AsmError::new(line_number, &format!("expected address {}, but line starts at {}", expected, address)));
+        }
+      } else if is_identifier(prefix) {
+        if symbols.insert(prefix.to_string(), address as i64).is_some() {
+          return Err(AsmError::new(line_number, &format!("duplicate label '{}'", prefix)));
+        }
+      } else {
+        break;
+      }
+      statement = statement[colon + 1..].trim();
+    }
+
+    if statement.is_empty() {
+      continue;
+    }
+
+    let item = parse_item(statement, address).map_err(|what| AsmError::new(line_number, &what))?;
+    address += item.size();
+    items.push((line_number, item));
+  }
+
+  let mut program = Vec::with_capacity(address);
+  for (line_number, item) in items {
+    let error = |what: String| AsmError::new(line_number, &what);
+    match item {
+      Item::Instructions(instructions) => {
+        for (opcode, operands) in instructions {
+          let mut params = vec![];
+          for operand in operands {
+            params.push(Param { mode: operand.mode, value: operand.value.eval(&symbols).map_err(error)? });
+          }
+          let instruction = Instruction { address: program.len(), raw: 0, opcode, params };
+          program.push(instruction.encode());
+          program.extend(instruction.params.iter().map(|param| param.value));
+        }
+      },
+      Item::Data(values) => {
+        for value in values {
+          program.push(value.eval(&symbols).map_err(error)?);
+        }
+      },
+    }
+  }
+
+  Ok(program)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{disassemble, isa_interpreter, parse_instructions, render, Machine, State};
+
+  #[test]
+  fn verify_203_works_assembled() {
+    let mut program = assemble("
+      arb 3
+      in [rb+4]       ; writes to 7
+      out [7]
+      hlt
+      .data 0
+    ").unwrap();
+
+    assert_eq!(program, vec![109, 3, 203, 4, 4, 7, 99, 0]);
+    assert_eq!(isa_interpreter(&mut program, 1), Ok(1));
+  }
+
+  #[test]
+  fn assembles_labels_and_data() {
+    let program = assemble("
+              in [n]
+      loop:   out [n]
+              add [n], -1, [n]
+              jnz [n], loop
+              out greeting+1
+              hlt
+      n:      .data 0
+      greeting: db \"hi\", 10
+    ").unwrap();
+
+    assert_eq!(program, vec![3, 14, 4, 14, 1001, 14, -1, 14, 1005, 14, 2, 104, 16, 99, 0, 104, 105, 10]);
+
+    let mut machine = Machine::new(&program);
+    machine.push_input(3);
+    assert_eq!(machine.run(), Ok(State::Halted));
+    assert_eq!(machine.drain_output(), vec![3, 2, 1, 16]);
+  }
+
+  #[test]
+  fn stack_macros() {
+    // factorial(5) computed recursively on the relative base stack
+    let program = assemble("
+              arb stack
+              push 5
+              call fac
+              pop [result]
+              out [result]
+              hlt
+
+      ; replaces the argument on top of the stack with its factorial
+      fac:    jz [rb-2], base       ; [rb-1] is the return address
+              push [rb-2]
+              add [rb-1], -1, [rb-1]
+              call fac
+              pop [tmp]
+              mul [tmp], [rb-2], [rb-2]
+              ret
+      base:   add 1, 0, [rb-2]
+              ret
+
+      result: .data 0
+      tmp:    .data 0
+      stack:  .data 0
+    ").unwrap();
+
+    let mut machine = Machine::new(&program);
+    assert_eq!(machine.run(), Ok(State::Halted));
+    assert_eq!(machine.drain_output(), vec![120]);
+  }
+
+  #[test]
+  fn assembler_errors() {
+    assert_eq!(assemble("jz 0, nowhere"), Err(AsmError { line: 1, what: "undefined label 'nowhere'".to_string() }));
+    assert_eq!(assemble("hlt\n2: hlt"), Err(AsmError { line: 2, what: "expected address 2, but line starts at 1".to_string() }));
+    assert_eq!(assemble("\nadd 1, 2, 3"), Err(AsmError { line: 2, what: "'add' cannot write to an immediate operand".to_string() }));
+    assert_eq!(assemble("out 1, 2"), Err(AsmError { line: 1, what: "'out' expects 1 operand(s), got 2".to_string() }));
+    assert_eq!(assemble("x: hlt\nx: hlt"), Err(AsmError { line: 2, what: "duplicate label 'x'".to_string() }));
+  }
+
+  #[test]
+  fn assemble_disassemble_round_trip() {
+    let inputs = [
+      include_str!("../day2/data/input-1.txt"),
+      include_str!("../day5/data/input-1.txt"),
+      include_str!("../day7/data/input-1.txt"),
+      include_str!("../day9/data/input-1.txt"),
+      include_str!("../day11/data/input-1.txt"),
+      include_str!("../day13/data/input-1.txt"),
+      include_str!("../day15/data/input-1.txt"),
+      include_str!("../day17/data/input-1.txt"),
+      include_str!("../day19/data/input-1.txt"),
+    ];
+
+    for input in inputs.iter() {
+      let program = parse_instructions(input.trim());
+      assert_eq!(assemble(&render(&disassemble(&program))), Ok(program));
+    }
+
+    // the extremes as operands and data
+    let program = vec![1101, i64::MIN, i64::MAX, 9, 104, i64::MIN, 99, i64::MIN, i64::MAX, 0];
+    assert_eq!(assemble(&render(&disassemble(&program))), Ok(program));
+  }
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Limits for a single call to one of the `run` methods of a machine. A run
+/// that hits one fails with `VmError::BudgetExhausted` before executing the
+/// next instruction, so the machine can be resumed with another run.
+///
+/// ```
+/// # use y2019::intcode::{Budget, Limit, Machine, VmError};
+/// let mut machine = Machine::new(&[1105, 1, 0]);
+/// machine.set_budget(Budget::unlimited().instructions(1000));
+/// assert!(matches!(machine.run(), Err(VmError::BudgetExhausted { limit: Limit::Instructions(1000), .. })));
+/// assert_eq!(machine.steps(), 1000);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+  instructions: Option<u64>,
+  time: Option<Duration>,
+  outputs: Option<u64>,
+}
+
+/// The limit of a budget that was reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+  Instructions(u64),
+  Time(Duration),
+  Outputs(u64),
+}
+
+impl fmt::Display for Limit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Limit::Instructions(count) => write!(f, "{} instructions", count),
+      Limit::Time(time) => write!(f, "{:?}", time),
+      Limit::Outputs(count) => write!(f, "{} outputs", count),
+    }
+  }
+}
+
+impl Budget {
+  pub const fn unlimited() -> Budget {
+    Budget { instructions: None, time: None, outputs: None }
+  }
+
+  /// Executes at most `count` instructions.
+  pub const fn instructions(mut self, count: u64) -> Budget {
+    self.instructions = Some(count);
+    self
+  }
+
+  /// Runs for at most `time`. The clock is only looked at every few thousand
+  /// instructions, so a run may take slightly longer.
+  pub const fn time(mut self, time: Duration) -> Budget {
+    self.time = Some(time);
+    self
+  }
+
+  /// Produces at most `count` output values.
+  pub const fn outputs(mut self, count: u64) -> Budget {
+    self.outputs = Some(count);
+    self
+  }
+
+  pub fn is_unlimited(&self) -> bool {
+    *self == Budget::unlimited()
+  }
+
+  /// A meter for a run, `None` if there's nothing to measure.
+  pub(crate) fn meter(&self) -> Option<Meter> {
+    if self.is_unlimited() {
+      return None;
+    }
+    Some(Meter { budget: *self, started: self.time.map(|_| Instant::now()), steps: 0, outputs: 0 })
+  }
+}
+
+/// What a run used of its budget so far.
+pub(crate) struct Meter {
+  budget: Budget,
+  started: Option<Instant>,
+  steps: u64,
+  outputs: u64,
+}
+
+const CLOCK_INTERVAL: u64 = 4096;
+
+impl Meter {
+  /// The limit that keeps the run from executing another instruction.
+  pub(crate) fn exhausted(&self) -> Option<Limit> {
+    if let Some(count) = self.budget.instructions.filter(|count| self.steps >= *count) {
+      return Some(Limit::Instructions(count));
+    }
+    if let Some(count) = self.budget.outputs.filter(|count| self.outputs >= *count) {
+      return Some(Limit::Outputs(count));
+    }
+    match (self.budget.time, self.started) {
+      (Some(time), Some(started)) if self.steps.is_multiple_of(CLOCK_INTERVAL) && started.elapsed() >= time => Some(Limit::Time(time)),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn charge(&mut self, output: bool) {
+    self.steps += 1;
+    if output {
+      self.outputs += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{assemble, Machine, State, VmError};
+
+  #[test]
+  fn budget_stops_runs_and_they_can_be_resumed() {
+    // counts down from 1000, printing every value
+    let program = assemble("
+              in [n]
+      loop:   out [n]
+              add [n], -1, [n]
+              jnz [n], loop
+              hlt
+      n:      .data 0
+    ").unwrap();
+    let mut machine = Machine::new(&program);
+    machine.push_input(1000);
+    machine.set_budget(Budget::unlimited().instructions(10));
+    assert_eq!(machine.run(), Err(VmError::BudgetExhausted { ip: 2, opcode: 4, limit: Limit::Instructions(10) }));
+    assert_eq!(machine.steps(), 10);
+
+    let mut output = vec![];
+    machine.set_budget(Budget::unlimited().outputs(100));
+    let result = machine.run_with(&mut ((), &mut output));
+    assert!(matches!(result, Err(VmError::BudgetExhausted { limit: Limit::Outputs(100), .. })));
+    assert_eq!(output.len(), 103);
+
+    machine.set_budget(Budget::unlimited().time(Duration::from_secs(60)));
+    assert_eq!(machine.run_with(&mut ((), &mut output)), Ok(State::Halted));
+    assert_eq!(output, (1..=1000).rev().collect::<Vec<_>>());
+    assert_eq!(machine.run(), Ok(State::Halted));
+  }
+
+  #[test]
+  fn time_budget_stops_endless_loops() {
+    let mut machine = Machine::new(&[1105, 1, 0]);
+    machine.set_budget(Budget::unlimited().time(Duration::from_millis(20)));
+
+    let result = machine.run();
+    assert!(matches!(result, Err(VmError::BudgetExhausted { ip: 0, opcode: 1105, limit: Limit::Time(_) })));
+    assert_eq!(result.unwrap_err().to_string(), "budget of 20ms exhausted (ip 0, opcode 1105)");
+  }
+}
@@ -0,0 +1,173 @@
+//! Ahead-of-time decoding for `Machine::compile`.
+//!
+//! Every instruction `analyze` finds is decoded once into an `Op` whose
+//! parameters are already split into their modes, so executing it doesn't
+//! need to look at mode digits again. Instructions that are only reached
+//! through computed jumps are left to the interpreter, and so are the ones
+//! the program writes to, or that were overwritten after compiling.
+
+use super::{analyze, Mode, Opcode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Operand {
+  Immediate(i64),
+  Position(i64),
+  Relative(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Op {
+  pub(super) raw: i64,
+  pub(super) opcode: Opcode,
+  pub(super) args: [Operand; 3],
+  pub(super) next: usize,
+}
+
+/// The decoded instructions of a program, indexed by address.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Compiled {
+  ops: Vec<Option<Op>>,
+  // the address of the instruction each cell belongs to
+  owner: Vec<Option<usize>>,
+}
+
+impl Compiled {
+  pub(super) fn new(program: &[i64]) -> Compiled {
+    let mut ops = vec![None; program.len()];
+    let mut owner = vec![None; program.len()];
+
+    let analysis = analyze(program);
+    let modified = analysis.self_modified();
+    for block in analysis.blocks.values() {
+      for instruction in &block.instructions {
+        let span = instruction.address..instruction.address + instruction.size();
+        if modified.range(span.clone()).next().is_some() {
+          continue;
+        }
+
+        let mut args = [Operand::Immediate(0); 3];
+        for (arg, param) in args.iter_mut().zip(&instruction.params) {
+          *arg = match param.mode {
+            Mode::Immediate => Operand::Immediate(param.value),
+            Mode::Position => Operand::Position(param.value),
+            Mode::Relative => Operand::Relative(param.value),
+          };
+        }
+
+        for cell in span.clone() {
+          owner[cell] = Some(instruction.address);
+        }
+        ops[instruction.address] = Some(Op {
+          raw: instruction.raw,
+          opcode: instruction.opcode,
+          args,
+          next: span.end,
+        });
+      }
+    }
+
+    Compiled { ops, owner }
+  }
+
+  #[inline]
+  pub(super) fn op(&self, address: usize) -> Option<&Op> {
+    self.ops.get(address).and_then(Option::as_ref)
+  }
+
+  /// Whether `address` belongs to a compiled instruction.
+  #[inline]
+  pub(super) fn covers(&self, address: usize) -> bool {
+    self.owner.get(address).is_some_and(Option::is_some)
+  }
+
+  /// Drops the instruction `address` belongs to, it is interpreted from now
+  /// on.
+  pub(super) fn invalidate(&mut self, address: usize) {
+    if let Some(start) = self.owner.get(address).copied().flatten() {
+      let end = self.ops[start].take().map_or(start, |op| op.next);
+      for cell in start..end {
+        self.owner[cell] = None;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::intcode::{parse_instructions, Machine, State, VmError};
+
+  // runs a program with the same made-up input on both backends
+  fn run_both(program: &[i64], inputs: &[i64]) -> [(Machine, Vec<i64>); 2] {
+    let interpreted = Machine::new(program);
+    let mut compiled = interpreted.fork();
+    compiled.compile();
+
+    [interpreted, compiled].map(|mut machine| {
+      let mut inputs = inputs.iter().copied().cycle().take(2000);
+      let mut output = vec![];
+      machine.run_with(&mut (|| inputs.next(), &mut output)).unwrap();
+      (machine, output)
+    })
+  }
+
+  #[test]
+  fn compiled_code_behaves_like_the_interpreter() {
+    let programs = [
+      (include_str!("../day5/data/input-1.txt"), vec![5]),
+      (include_str!("../day7/data/input-1.txt"), vec![4, 0]),
+      (include_str!("../day9/data/input-1.txt"), vec![1]),
+      (include_str!("../day11/data/input-1.txt"), vec![0, 1]),
+      (include_str!("../day13/data/input-1.txt"), vec![]),
+      (include_str!("../day15/data/input-1.txt"), vec![1, 4, 2, 3]),
+    ];
+
+    for (program, inputs) in programs.iter() {
+      let [(interpreted, expected), (compiled, output)] = run_both(&parse_instructions(program.trim()), inputs);
+      assert!(compiled.is_compiled());
+      assert!(!expected.is_empty());
+      assert_eq!(output, expected);
+      assert_eq!(compiled.steps(), interpreted.steps());
+      assert_eq!((compiled.ip(), compiled.state()), (interpreted.ip(), interpreted.state()));
+    }
+  }
+
+  #[test]
+  fn self_modifying_writes_fall_back_to_the_interpreter() {
+    // the add turns `out 7` into `out 4` through the relative base, which
+    // the analysis can't see
+    let [(_, expected), (compiled, output)] = run_both(&[109, 5, 21101, 4, 0, 2, 104, 7, 99], &[]);
+    assert!(compiled.is_compiled());
+    assert_eq!((expected, output), (vec![4], vec![4]));
+
+    // ... and here it becomes a faulting instruction
+    let mut compiled = Machine::new(&[109, 4, 21101, 42, 0, 2, 1101, 1, 1, 9, 99, 0]);
+    compiled.compile();
+    assert_eq!(compiled.run(), Err(VmError::InvalidOpcode { ip: 6, opcode: 42 }));
+
+    let mut compiled = Machine::new(&[104, 7, 99]);
+    compiled.compile();
+    compiled.poke(1, 8);
+    assert_eq!(compiled.run(), Ok(State::Halted));
+    assert_eq!(compiled.pop_output(), Some(8));
+  }
+
+  #[test]
+  fn forks_share_compiled_code() {
+    let program = parse_instructions(include_str!("../day2/data/input-1.txt").trim());
+    let mut machine = Machine::new(&program);
+    machine.compile();
+
+    let mut fork = machine.fork();
+    fork.poke(1, 12);
+    fork.poke(2, 2);
+    assert!(fork.is_compiled());
+    assert_eq!(fork.run(), Ok(State::Halted));
+    assert_eq!(fork.peek(0), 6568671);
+
+    let mut interpreted = Machine::new(&program);
+    interpreted.run().unwrap();
+    assert_eq!((machine.peek(1), machine.steps()), (0, 0));
+    assert_eq!(machine.run(), Ok(State::Halted));
+    assert_eq!(machine.peek(0), interpreted.peek(0));
+  }
+}
@@ -0,0 +1,661 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, Write};
+
+use super::history::History;
+use super::{Instruction, Machine, Memory, PagedMemory, State, VmError};
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+  /// A single instruction was executed.
+  Stepped,
+  /// The instruction pointer reached a breakpoint. The instruction has not
+  /// been executed yet.
+  Breakpoint(usize),
+  /// The last instruction wrote to a watched cell.
+  Watchpoint { address: usize, old: i64, new: i64 },
+  /// The machine is blocked on an `in` instruction.
+  NeedsInput,
+  Halted,
+  /// Going back reached the start of the recorded history.
+  Start,
+}
+
+/// The most cells `hexdump` formats at once.
+const MAX_DUMP: usize = 4096;
+
+const HELP: &str = "\
+commands:
+  s, step [n]         execute n instructions (default 1)
+  n, next             step over subroutine calls
+  c, continue         run until a breakpoint, watchpoint, input request or halt
+  b, break <addr>     break before executing the instruction at addr
+  w, watch <addr>     break after a write to addr
+  record [n] [count]  record history with a checkpoint every n instructions
+                      (default 1000), keeping count checkpoints (default 1000)
+  rs, rstep [n]       undo n instructions (default 1)
+  rc, rcontinue       go back to the previous breakpoint or watched write
+  goto <count>        go back or forward to an instruction count
+  d, delete <addr>    remove the breakpoint and watchpoint at addr
+  info                list breakpoints and watchpoints
+  r, regs             show ip, relative base and state
+  steps               show the instruction count
+  x <addr> [count]    dump count cells starting at addr (default 16)
+  l, list [addr] [n]  disassemble n instructions starting at addr (default ip)
+  i, input <values>   queue numbers or a \"quoted string\" (sent with a newline)
+  q, quit             leave the debugger";
+
+/// An interactive debugger around a `Machine`.
+///
+/// The debugger can be scripted through its methods or with the same text
+/// commands the terminal frontend understands, see `execute`.
+///
+/// Once `record_history` was called the debugger can also go back in time:
+///
+/// ```
+/// # use y2019::intcode::{Debugger, Machine, Stop};
+/// let mut debugger = Debugger::new(Machine::new(&[1001, 7, 1, 7, 1105, 1, 0, 0]));
+/// debugger.record_history(100, 10);
+/// debugger.add_breakpoint(0);
+/// for _ in 0..500 {
+///   debugger.step().unwrap();
+/// }
+/// assert_eq!(debugger.machine().peek(7), 250);
+///
+/// assert_eq!(debugger.reverse_cont(), Ok(Stop::Breakpoint(0)));
+/// assert_eq!(debugger.machine().peek(7), 249);
+/// assert_eq!(debugger.goto(10), Ok(Stop::Stepped));
+/// assert_eq!(debugger.machine().peek(7), 5);
+/// ```
+pub struct Debugger<M: Memory = PagedMemory> {
+  machine: Machine<M>,
+  breakpoints: BTreeSet<usize>,
+  watchpoints: BTreeSet<usize>,
+  history: Option<History>,
+}
+
+impl<M: Memory> Debugger<M> {
+  pub fn new(machine: Machine<M>) -> Debugger<M> {
+    Debugger {
+      machine,
+      breakpoints: BTreeSet::new(),
+      watchpoints: BTreeSet::new(),
+      history: None,
+    }
+  }
+
+  pub fn machine(&self) -> &Machine<M> {
+    &self.machine
+  }
+
+  pub fn machine_mut(&mut self) -> &mut Machine<M> {
+    &mut self.machine
+  }
+
+  pub fn into_machine(self) -> Machine<M> {
+    self.machine
+  }
+
+  pub fn add_breakpoint(&mut self, address: usize) {
+    self.breakpoints.insert(address);
+  }
+
+  pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+    self.breakpoints.remove(&address)
+  }
+
+  pub fn breakpoints(&self) -> impl Iterator<Item = &usize> {
+    self.breakpoints.iter()
+  }
+
+  pub fn add_watchpoint(&mut self, address: usize) {
+    self.watchpoints.insert(address);
+  }
+
+  pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+    self.watchpoints.remove(&address)
+  }
+
+  pub fn watchpoints(&self) -> impl Iterator<Item = &usize> {
+    self.watchpoints.iter()
+  }
+
+  /// Records an undo log from now on, so `step_back`, `reverse_cont` and
+  /// `goto` can go back to earlier instructions. The machine is saved every
+  /// `interval` instructions and the last `checkpoints` saves are kept, which
+  /// bounds how far back it can go. Instructions executed through
+  /// `machine_mut` are not recorded.
+  pub fn record_history(&mut self, interval: u64, checkpoints: usize) {
+    self.history = Some(History::new(&self.machine, interval, checkpoints));
+  }
+
+  /// The earliest instruction count the debugger can go back to.
+  pub fn history_start(&self) -> Option<u64> {
+    self.history.as_ref().map(|history| history.start())
+  }
+
+  /// Queues values for the next `in` instructions.
+  pub fn inject_input<I: IntoIterator<Item = i64>>(&mut self, values: I) {
+    self.machine.extend_input(values);
+  }
+
+  /// Executes a single instruction.
+  pub fn step(&mut self) -> Result<Stop, VmError> {
+    let target = if self.watchpoints.is_empty() {
+      None
+    } else {
+      self.machine.write_target()?.filter(|address| self.watchpoints.contains(address))
+    };
+    let old = target.map(|address| self.machine.peek(address));
+
+    let state = match &mut self.history {
+      Some(history) => history.step(&mut self.machine)?,
+      None => self.machine.step()?,
+    };
+    match state {
+      State::Halted => return Ok(Stop::Halted),
+      State::NeedsInput => return Ok(Stop::NeedsInput),
+      State::Running | State::HasOutput => (),
+    }
+
+    match (target, old) {
+      (Some(address), Some(old)) => Ok(Stop::Watchpoint { address, old, new: self.machine.peek(address) }),
+      _ => Ok(Stop::Stepped),
+    }
+  }
+
+  /// Executes instructions until something other than a plain step happens.
+  /// A breakpoint at the current instruction does not stop the machine, so
+  /// execution can be continued from a breakpoint.
+  pub fn cont(&mut self) -> Result<Stop, VmError> {
+    loop {
+      match self.step()? {
+        Stop::Stepped => (),
+        stop => return Ok(stop),
+      }
+      let ip = self.machine.ip();
+      if self.breakpoints.contains(&ip) {
+        return Ok(Stop::Breakpoint(ip));
+      }
+    }
+  }
+
+  /// Executes the current instruction. If it jumps away, execution continues
+  /// until it comes back to the following instruction, which steps over
+  /// subroutine calls. A recursive call made from the same place stops at the
+  /// innermost return.
+  pub fn step_over(&mut self) -> Result<Stop, VmError> {
+    let return_ip = self.machine.ip() + self.machine.current_instruction()?.size();
+
+    loop {
+      match self.step()? {
+        Stop::Stepped => (),
+        stop => return Ok(stop),
+      }
+      let ip = self.machine.ip();
+      if ip == return_ip {
+        return Ok(Stop::Stepped);
+      }
+      if self.breakpoints.contains(&ip) {
+        return Ok(Stop::Breakpoint(ip));
+      }
+    }
+  }
+
+  /// Undoes the last instruction.
+  pub fn step_back(&mut self) -> Result<Stop, VmError> {
+    let steps = self.machine.steps();
+    match &mut self.history {
+      Some(history) if steps > history.start() => {
+        history.rewind(&mut self.machine, steps - 1)?;
+        Ok(Stop::Stepped)
+      },
+      _ => Ok(Stop::Start),
+    }
+  }
+
+  /// Goes back until the instruction pointer is at a breakpoint or the
+  /// instruction that wrote to a watched cell is undone. A watchpoint reports
+  /// the write the instruction at the instruction pointer is about to make.
+  pub fn reverse_cont(&mut self) -> Result<Stop, VmError> {
+    loop {
+      let watched = self.watchpoints.iter().map(|address| (*address, self.machine.peek(*address))).collect::<Vec<_>>();
+      match self.step_back()? {
+        Stop::Stepped => (),
+        stop => return Ok(stop),
+      }
+      for (address, new) in watched {
+        let old = self.machine.peek(address);
+        if old != new || self.machine.write_target() == Ok(Some(address)) {
+          return Ok(Stop::Watchpoint { address, old, new });
+        }
+      }
+      let ip = self.machine.ip();
+      if self.breakpoints.contains(&ip) {
+        return Ok(Stop::Breakpoint(ip));
+      }
+    }
+  }
+
+  /// Goes back or forward to the instruction count `steps`. Going forward
+  /// ignores breakpoints and watchpoints but stops when the machine needs
+  /// input or halts.
+  pub fn goto(&mut self, steps: u64) -> Result<Stop, VmError> {
+    while self.machine.steps() < steps {
+      match self.step()? {
+        Stop::Stepped | Stop::Watchpoint { .. } => (),
+        stop => return Ok(stop),
+      }
+    }
+    if self.machine.steps() == steps {
+      return Ok(Stop::Stepped);
+    }
+    let reached = match &mut self.history {
+      Some(history) => history.rewind(&mut self.machine, steps)?,
+      None => false,
+    };
+    Ok(if reached { Stop::Stepped } else { Stop::Start })
+  }
+
+  pub fn registers(&self) -> String {
+    format!("ip {}  rb {}  state {:?}", self.machine.ip(), self.machine.relative_base(), self.machine.state())
+  }
+
+  /// Formats `count` cells starting at `address`, four per line, in hex with
+  /// the printable ASCII characters on the right. Stops after `MAX_DUMP`
+  /// cells and at the memory limit.
+  pub fn hexdump(&self, address: usize, count: usize) -> String {
+    let mut dump = String::new();
+    let end = address.saturating_add(count.min(MAX_DUMP)).min(self.machine.memory_limit());
+    for row in (address..end).step_by(4) {
+      let cells = (row..row.saturating_add(4).min(end)).map(|a| self.machine.peek(a)).collect::<Vec<_>>();
+      let hex = cells.iter().map(|value| format!("{:016x}", value)).collect::<Vec<_>>().join(" ");
+      let ascii = cells
+        .iter()
+        .map(|value| match *value {
+          32..=126 => *value as u8 as char,
+          _ => '.',
+        })
+        .collect::<String>();
+      writeln!(dump, "{:>6}: {:<67} |{}|", row, hex, ascii).unwrap();
+    }
+    dump
+  }
+
+  /// Disassembles `count` instructions starting at `address`. Cells that do
+  /// not decode are shown as data.
+  pub fn listing(&self, address: usize, count: usize) -> String {
+    let mut listing = String::new();
+    let mut address = address;
+    for _ in 0..count {
+      let marker = if address == self.machine.ip() { "=>" } else { "  " };
+      let breakpoint = if self.breakpoints.contains(&address) { "*" } else { " " };
+      match Instruction::decode(|a| self.machine.peek(a), address) {
+        Ok(instruction) => {
+          writeln!(listing, "{}{}{:>6}: {}", marker, breakpoint, address, instruction).unwrap();
+          address += instruction.size();
+        },
+        Err(_) => {
+          writeln!(listing, "{}{}{:>6}: .data {}", marker, breakpoint, address, self.machine.peek(address)).unwrap();
+          address += 1;
+        },
+      }
+    }
+    listing
+  }
+
+  fn describe(&mut self, stop: Result<Stop, VmError>) -> String {
+    let mut text = String::new();
+    let output = self.machine.drain_output();
+    if !output.is_empty() {
+      writeln!(text, "output: {:?}", output).unwrap();
+    }
+    match stop {
+      Ok(Stop::Stepped) => (),
+      Ok(Stop::Breakpoint(address)) => writeln!(text, "breakpoint at {}", address).unwrap(),
+      Ok(Stop::Watchpoint { address, old, new }) => {
+        writeln!(text, "watchpoint: [{}] {} -> {}", address, old, new).unwrap()
+      },
+      Ok(Stop::NeedsInput) => writeln!(text, "waiting for input").unwrap(),
+      Ok(Stop::Halted) => writeln!(text, "halted").unwrap(),
+      Ok(Stop::Start) if self.history.is_none() => writeln!(text, "no history, see 'record'").unwrap(),
+      Ok(Stop::Start) => writeln!(text, "start of history").unwrap(),
+      Err(err) => writeln!(text, "error: {}", err).unwrap(),
+    }
+    text.push_str(&self.listing(self.machine.ip(), 1));
+    text
+  }
+
+  /// Runs a single debugger command and returns what it prints.
+  pub fn execute(&mut self, command: &str) -> String {
+    let mut words = command.split_whitespace();
+    let name = match words.next() {
+      Some(name) => name,
+      None => return String::new(),
+    };
+    let args = words.collect::<Vec<_>>();
+    let number = |index: usize| args.get(index).and_then(|arg| arg.parse::<usize>().ok());
+
+    let text = match (name, number(0)) {
+      ("s", _) | ("step", _) => {
+        let mut stop = Ok(Stop::Stepped);
+        for _ in 0..number(0).unwrap_or(1) {
+          stop = self.step();
+          if stop != Ok(Stop::Stepped) {
+            break;
+          }
+        }
+        self.describe(stop)
+      },
+      ("n", _) | ("next", _) => {
+        let stop = self.step_over();
+        self.describe(stop)
+      },
+      ("c", _) | ("continue", _) => {
+        let stop = self.cont();
+        self.describe(stop)
+      },
+      ("record", _) => {
+        let (interval, checkpoints) = (number(0).unwrap_or(1000), number(1).unwrap_or(1000));
+        if interval == 0 || checkpoints == 0 {
+          "usage: record [interval] [checkpoints], both at least 1\n".to_string()
+        } else {
+          self.record_history(interval as u64, checkpoints);
+          format!("recording from instruction {}\n", self.machine.steps())
+        }
+      },
+      ("rs", _) | ("rstep", _) => {
+        let mut stop = Ok(Stop::Stepped);
+        for _ in 0..number(0).unwrap_or(1) {
+          stop = self.step_back();
+          if stop != Ok(Stop::Stepped) {
+            break;
+          }
+        }
+        self.describe(stop)
+      },
+      ("rc", _) | ("rcontinue", _) => {
+        let stop = self.reverse_cont();
+        self.describe(stop)
+      },
+      ("goto", Some(steps)) => {
+        let stop = self.goto(steps as u64);
+        self.describe(stop)
+      },
+      ("steps", _) => format!("{}\n", self.machine.steps()),
+      ("b", Some(address)) | ("break", Some(address)) => {
+        self.add_breakpoint(address);
+        format!("breakpoint at {}\n", address)
+      },
+      ("w", Some(address)) | ("watch", Some(address)) => {
+        self.add_watchpoint(address);
+        format!("watchpoint at {}\n", address)
+      },
+      ("d", Some(address)) | ("delete", Some(address)) => {
+        let removed = self.remove_breakpoint(address) | self.remove_watchpoint(address);
+        if removed {
+          format!("deleted {}\n", address)
+        } else {
+          format!("nothing to delete at {}\n", address)
+        }
+      },
+      ("info", _) => format!("breakpoints: {:?}\nwatchpoints: {:?}\n", self.breakpoints, self.watchpoints),
+      ("r", _) | ("regs", _) => format!("{}\n", self.registers()),
+      ("x", Some(address)) => self.hexdump(address, number(1).unwrap_or(16)),
+      ("l", _) | ("list", _) => self.listing(number(0).unwrap_or_else(|| self.machine.ip()), number(1).unwrap_or(10)),
+      ("i", _) | ("input", _) => {
+        let raw = command.trim_start()[name.len()..].trim();
+        match parse_input(raw) {
+          Some(values) => {
+            let text = format!("queued {} value(s)\n", values.len());
+            self.inject_input(values);
+            text
+          },
+          None => "usage: input <numbers> or input \"text\"\n".to_string(),
+        }
+      },
+      ("help", _) | ("h", _) => format!("{}\n", HELP),
+      _ => format!("unknown command '{}', try 'help'\n", command.trim()),
+    };
+
+    text
+  }
+
+  /// Reads commands line by line until `quit` or the end of `input`.
+  pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+    write!(output, "{}(icdb) ", self.listing(self.machine.ip(), 1))?;
+    output.flush()?;
+
+    for line in input.lines() {
+      let line = line?;
+      let command = line.trim();
+      if command == "q" || command == "quit" {
+        break;
+      }
+      write!(output, "{}(icdb) ", self.execute(command))?;
+      output.flush()?;
+    }
+
+    Ok(())
+  }
+}
+
+/// Parses whitespace separated numbers or a double quoted string, which is
+/// sent as ASCII followed by a newline.
+fn parse_input(raw: &str) -> Option<Vec<i64>> {
+  if let Some(text) = raw.strip_prefix('"') {
+    let text = text.strip_suffix('"')?;
+    return Some(text.chars().chain(std::iter::once('\n')).map(|c| c as i64).collect());
+  }
+
+  let values = raw
+    .split_whitespace()
+    .map(|value| value.parse::<i64>().ok())
+    .collect::<Option<Vec<_>>>()?;
+  if values.is_empty() {
+    None
+  } else {
+    Some(values)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{assemble, parse_instructions, Cpu, Definition, Flow, InstructionSet};
+
+  fn countdown() -> Machine {
+    Machine::new(&assemble("
+              in [n]
+      loop:   out [n]
+              add [n], -1, [n]
+              jnz [n], loop
+              hlt
+      n:      .data 0
+    ").unwrap())
+  }
+
+  #[test]
+  fn breakpoints_and_watchpoints() {
+    let mut debugger = Debugger::new(countdown());
+
+    assert_eq!(debugger.cont(), Ok(Stop::NeedsInput));
+    debugger.inject_input(vec![2]);
+    debugger.add_breakpoint(8);
+    assert_eq!(debugger.cont(), Ok(Stop::Breakpoint(8)));
+    assert_eq!(debugger.machine_mut().drain_output(), vec![2]);
+
+    debugger.add_watchpoint(12);
+    assert_eq!(debugger.cont(), Ok(Stop::Watchpoint { address: 12, old: 1, new: 0 }));
+    assert_eq!(debugger.machine().ip(), 8);
+    assert_eq!(debugger.step(), Ok(Stop::Stepped));
+    assert_eq!(debugger.machine().ip(), 11);
+
+    debugger.remove_breakpoint(8);
+    debugger.remove_watchpoint(12);
+    assert_eq!(debugger.cont(), Ok(Stop::Halted));
+    assert_eq!(debugger.machine_mut().drain_output(), vec![1]);
+  }
+
+  // 12: square the first parameter into the second
+  fn square(cpu: &mut Cpu) -> Result<Flow, VmError> {
+    let value = cpu.operand(0).checked_mul(cpu.operand(0)).ok_or(cpu.overflow())?;
+    cpu.write(value);
+    Ok(Flow::Next)
+  }
+
+  // sq 7, [9]; out [9]; hlt
+  fn squaring() -> Machine {
+    let mut isa = InstructionSet::standard();
+    isa.register(Definition { code: 12, mnemonic: "sq", arity: 2, write_param: Some(1), execute: square });
+    let mut machine = Machine::new(&[112, 7, 9, 4, 9, 99, 0, 0, 0, 0]);
+    machine.set_instruction_set(isa);
+    machine
+  }
+
+  #[test]
+  fn watchpoints_see_defined_instructions() {
+    let mut debugger = Debugger::new(squaring());
+    debugger.add_watchpoint(9);
+
+    assert_eq!(debugger.step(), Ok(Stop::Watchpoint { address: 9, old: 0, new: 49 }));
+    assert_eq!(debugger.cont(), Ok(Stop::Halted));
+    assert_eq!(debugger.machine_mut().drain_output(), vec![49]);
+  }
+
+  #[test]
+  fn next_steps_over_calls() {
+    let program = assemble("
+              arb stack
+              call double
+              out [x]
+              hlt
+      double: mul [x], 2, [x]
+              ret
+      x:      .data 21
+      stack:  .data 0
+    ").unwrap();
+    let mut debugger = Debugger::new(Machine::new(&program));
+
+    debugger.step().unwrap();
+    // the call macro pushes the return address before jumping
+    assert_eq!(debugger.step_over(), Ok(Stop::Stepped));
+    assert_eq!(debugger.step_over(), Ok(Stop::Stepped));
+    assert_eq!(debugger.step_over(), Ok(Stop::Stepped));
+    assert_eq!(debugger.machine().ip(), 11);
+    assert_eq!(debugger.machine().peek(23), 42);
+  }
+
+  #[test]
+  fn goes_back_in_time() {
+    let mut debugger = Debugger::new(countdown());
+    debugger.record_history(3, 100);
+    debugger.inject_input(vec![3]);
+    assert_eq!(debugger.cont(), Ok(Stop::Halted));
+    assert_eq!(debugger.machine_mut().drain_output(), vec![3, 2, 1]);
+    assert_eq!(debugger.machine().steps(), 11);
+
+    assert_eq!(debugger.step_back(), Ok(Stop::Stepped));
+    assert_eq!(debugger.machine().state(), State::Running);
+    assert_eq!(debugger.machine().ip(), 11);
+
+    debugger.add_watchpoint(12);
+    assert_eq!(debugger.reverse_cont(), Ok(Stop::Watchpoint { address: 12, old: 1, new: 0 }));
+    assert_eq!((debugger.machine().ip(), debugger.machine().steps()), (4, 8));
+
+    // the input is consumed again after going back before the `in`
+    assert_eq!(debugger.goto(0), Ok(Stop::Stepped));
+    assert_eq!((debugger.machine().ip(), debugger.machine().peek(12)), (0, 0));
+    assert_eq!(debugger.reverse_cont(), Ok(Stop::Start));
+    debugger.remove_watchpoint(12);
+    assert_eq!(debugger.cont(), Ok(Stop::Halted));
+    assert_eq!(debugger.machine_mut().drain_output(), vec![3, 2, 1]);
+  }
+
+  #[test]
+  fn defined_instructions_are_undone() {
+    let mut debugger = Debugger::new(squaring());
+    debugger.record_history(10, 10);
+    assert_eq!(debugger.step(), Ok(Stop::Stepped));
+    assert_eq!(debugger.machine().peek(9), 49);
+
+    assert_eq!(debugger.step_back(), Ok(Stop::Stepped));
+    assert_eq!((debugger.machine().ip(), debugger.machine().peek(9)), (0, 0));
+    assert_eq!(debugger.cont(), Ok(Stop::Halted));
+    assert_eq!(debugger.machine_mut().drain_output(), vec![49]);
+  }
+
+  #[test]
+  fn history_is_bounded() {
+    let mut debugger = Debugger::new(countdown());
+    debugger.record_history(2, 3);
+    debugger.inject_input(vec![3]);
+    debugger.cont().unwrap();
+
+    assert_eq!(debugger.history_start(), Some(6));
+    assert_eq!(debugger.goto(5), Ok(Stop::Start));
+    assert_eq!(debugger.goto(7), Ok(Stop::Stepped));
+    assert_eq!((debugger.machine().ip(), debugger.machine().peek(12)), (2, 1));
+    assert_eq!(debugger.goto(6), Ok(Stop::Stepped));
+    assert_eq!(debugger.step_back(), Ok(Stop::Start));
+    assert_eq!(debugger.goto(11), Ok(Stop::Halted));
+  }
+
+  #[test]
+  fn going_back_matches_running_forward() {
+    let program = parse_instructions(include_str!("../day15/data/input-1.txt").trim());
+    let state = |machine: &Machine| (machine.ip(), machine.relative_base(), (0..1100).map(|a| machine.peek(a)).collect::<Vec<_>>());
+    let mut debugger = Debugger::new(Machine::new(&program));
+    debugger.record_history(50, 100);
+    debugger.inject_input((0..100).map(|direction| direction % 4 + 1));
+
+    let mut states = vec![];
+    while debugger.machine().steps() < 2000 && debugger.step() != Ok(Stop::NeedsInput) {
+      states.push((debugger.machine().steps(), state(debugger.machine())));
+    }
+    assert_eq!(states.len(), 2000);
+    for (steps, expected) in states.iter().rev().step_by(37) {
+      assert_eq!(debugger.goto(*steps), Ok(Stop::Stepped));
+      assert_eq!(state(debugger.machine()), *expected);
+    }
+    for (steps, expected) in states.iter().step_by(91) {
+      assert_eq!(debugger.goto(*steps), Ok(Stop::Stepped));
+      assert_eq!(state(debugger.machine()), *expected);
+    }
+  }
+
+  #[test]
+  fn scripted_commands() {
+    let mut debugger = Debugger::new(countdown());
+
+    assert_eq!(debugger.execute("break 8"), "breakpoint at 8\n");
+    assert_eq!(debugger.execute("c"), "waiting for input\n=>      0: in [12]\n");
+    assert_eq!(debugger.execute("input 3"), "queued 1 value(s)\n");
+    assert_eq!(debugger.execute("continue"), "output: [3]\nbreakpoint at 8\n=>*     8: jnz [12], 2\n");
+    assert_eq!(debugger.execute("regs"), "ip 8  rb 0  state Running\n");
+    assert_eq!(debugger.execute("x 12 1"), format!("{:>6}: {:<67} |.|\n", 12, "0000000000000002"));
+    assert_eq!(debugger.execute("x 18446744073709551615 8"), "");
+    assert_eq!(debugger.execute(&format!("x 0 {}", usize::MAX)).lines().count(), MAX_DUMP / 4);
+    assert_eq!(debugger.execute("rs"), "no history, see 'record'\n=>*     8: jnz [12], 2\n");
+    assert_eq!(debugger.execute("record 10"), "recording from instruction 3\n");
+    assert_eq!(debugger.execute("c"), "output: [2]\nbreakpoint at 8\n=>*     8: jnz [12], 2\n");
+    assert_eq!(debugger.execute("rc"), "breakpoint at 8\n=>*     8: jnz [12], 2\n");
+    assert_eq!(debugger.execute("steps"), "3\n");
+    assert_eq!(debugger.execute("rstep"), "start of history\n=>*     8: jnz [12], 2\n");
+    assert_eq!(debugger.execute("frobnicate"), "unknown command 'frobnicate', try 'help'\n");
+  }
+
+  #[test]
+  fn repl_reads_until_quit() {
+    let mut debugger = Debugger::new(countdown());
+    let mut output = vec![];
+
+    debugger.repl("i \"A\"\nstep 2\nquit\nstep\n".as_bytes(), &mut output).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("queued 2 value(s)"));
+    assert!(output.contains("output: [65]"));
+    assert_eq!(debugger.machine().ip(), 4);
+  }
+}
@@ -0,0 +1,1061 @@
+//! A decompiler lifting Intcode programs to C-like pseudo-code.
+//!
+//! Every subroutine `analyze` finds becomes a function. Its blocks are
+//! structured into `if`/`else` and `while` statements, with `goto` for the
+//! jumps that don't fit. Cells of the stack frame are named after their
+//! offset to the relative base the function was called with, `local1` is
+//! the first cell after the return address in `local0`. Code cells the
+//! program writes to are read from memory, so `add 0, [8], [29]` followed by
+//! `eq [8], 1, [10]` at 27 becomes
+//!
+//! ```text
+//!   mem[29] = mem[8];
+//!   mem[10] = mem[8] == mem[29];
+//! ```
+//!
+//! The pseudo-code can be run with `Decompiled::run_with`, which is how the
+//! decompiler is tested against the machine.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::{analyze_with_targets, Analysis, Exit, Instruction, IoDevice, Memory, Mode, Opcode, PagedMemory, State, DEFAULT_MEMORY_LIMIT};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+  Add,
+  Mul,
+  Lt,
+  Eq,
+}
+
+impl BinOp {
+  fn symbol(self) -> &'static str {
+    match self {
+      BinOp::Add => "+",
+      BinOp::Mul => "*",
+      BinOp::Lt => "<",
+      BinOp::Eq => "==",
+    }
+  }
+
+  /// The result, or `None` on overflow.
+  fn apply(self, a: i64, b: i64) -> Option<i64> {
+    match self {
+      BinOp::Add => a.checked_add(b),
+      BinOp::Mul => a.checked_mul(b),
+      BinOp::Lt => Some((a < b) as i64),
+      BinOp::Eq => Some((a == b) as i64),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+  Const(i64),
+  /// The relative base.
+  Base,
+  /// The memory cell at an address.
+  Mem(Box<Expr>),
+  /// A cell of the stack frame, relative to the relative base at the call.
+  Local(i64),
+  Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl fmt::Display for Expr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Expr::Const(value) => write!(f, "{}", value),
+      Expr::Base => write!(f, "rb"),
+      Expr::Mem(address) => write!(f, "mem[{}]", address),
+      Expr::Local(offset) if *offset < 0 => write!(f, "outer{}", -offset),
+      Expr::Local(offset) => write!(f, "local{}", offset),
+      Expr::Binary(op, a, b) => {
+        for (index, operand) in [a, b].iter().enumerate() {
+          if index == 1 {
+            write!(f, " {} ", op.symbol())?;
+          }
+          match operand.as_ref() {
+            Expr::Binary(..) => write!(f, "({})", operand)?,
+            _ => write!(f, "{}", operand)?,
+          }
+        }
+        Ok(())
+      },
+    }
+  }
+}
+
+/// A branch condition: `expr` is checked to be non-zero, or zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cond {
+  pub expr: Expr,
+  pub nonzero: bool,
+}
+
+impl Cond {
+  fn negate(self) -> Cond {
+    Cond { expr: self.expr, nonzero: !self.nonzero }
+  }
+}
+
+impl fmt::Display for Cond {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} {} 0", self.expr, if self.nonzero { "!=" } else { "==" })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stmt {
+  Assign(Expr, Expr),
+  Input(Expr),
+  Output(Expr),
+  AdjustBase(Expr),
+  /// A call of the function at `target`, which has to return to `returns`.
+  Call { target: usize, returns: usize },
+  CallIndirect { target: Expr, returns: usize },
+  /// Returns to the address in the given cell.
+  Return(Expr),
+  Halt,
+  If { cond: Cond, then: Vec<Stmt>, otherwise: Vec<Stmt> },
+  /// Loops while `cond` holds, or forever.
+  While { cond: Option<Cond>, body: Vec<Stmt> },
+  Break,
+  Continue,
+  /// Marks the start of the block at an address.
+  Label(usize),
+  Goto(usize),
+  /// A jump to a computed address.
+  GotoIndirect(Expr),
+}
+
+/// A decompiled subroutine, or `main` for the code starting at 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+  pub entry: usize,
+  pub body: Vec<Stmt>,
+  // labels computed jumps may go to
+  targets: BTreeSet<usize>,
+}
+
+impl Function {
+  pub fn name(&self) -> String {
+    if self.entry == 0 {
+      "main".to_string()
+    } else {
+      format!("f{}", self.entry)
+    }
+  }
+}
+
+fn gotos(stmts: &[Stmt], targets: &mut BTreeSet<usize>) {
+  for stmt in stmts {
+    match stmt {
+      Stmt::Goto(target) => {
+        targets.insert(*target);
+      },
+      Stmt::If { then, otherwise, .. } => {
+        gotos(then, targets);
+        gotos(otherwise, targets);
+      },
+      Stmt::While { body, .. } => gotos(body, targets),
+      _ => {},
+    }
+  }
+}
+
+fn write_stmts(f: &mut fmt::Formatter, stmts: &[Stmt], depth: usize, labels: &BTreeSet<usize>) -> fmt::Result {
+  let indent = "  ".repeat(depth);
+  for stmt in stmts {
+    match stmt {
+      Stmt::Assign(target, value) => writeln!(f, "{}{} = {};", indent, target, value)?,
+      Stmt::Input(target) => writeln!(f, "{}{} = input();", indent, target)?,
+      Stmt::Output(value) => writeln!(f, "{}output({});", indent, value)?,
+      Stmt::AdjustBase(Expr::Const(value)) if *value < 0 => writeln!(f, "{}rb -= {};", indent, -value)?,
+      Stmt::AdjustBase(value) => writeln!(f, "{}rb += {};", indent, value)?,
+      Stmt::Call { target, .. } => writeln!(f, "{}f{}();", indent, target)?,
+      Stmt::CallIndirect { target, .. } => writeln!(f, "{}(*{})();", indent, target)?,
+      Stmt::Return(_) => writeln!(f, "{}return;", indent)?,
+      Stmt::Halt => writeln!(f, "{}halt();", indent)?,
+      Stmt::If { cond, then, otherwise } => {
+        writeln!(f, "{}if ({}) {{", indent, cond)?;
+        write_stmts(f, then, depth + 1, labels)?;
+        if !otherwise.is_empty() {
+          writeln!(f, "{}}} else {{", indent)?;
+          write_stmts(f, otherwise, depth + 1, labels)?;
+        }
+        writeln!(f, "{}}}", indent)?;
+      },
+      Stmt::While { cond, body } => {
+        match cond {
+          Some(cond) => writeln!(f, "{}while ({}) {{", indent, cond)?,
+          None => writeln!(f, "{}while (1) {{", indent)?,
+        }
+        write_stmts(f, body, depth + 1, labels)?;
+        writeln!(f, "{}}}", indent)?;
+      },
+      Stmt::Break => writeln!(f, "{}break;", indent)?,
+      Stmt::Continue => writeln!(f, "{}continue;", indent)?,
+      Stmt::Label(address) if labels.contains(address) => writeln!(f, "{}L{}:", "  ".repeat(depth - 1), address)?,
+      Stmt::Label(_) => {},
+      Stmt::Goto(address) => writeln!(f, "{}goto L{};", indent, address)?,
+      Stmt::GotoIndirect(target) => writeln!(f, "{}goto *{};", indent, target)?,
+    }
+  }
+  Ok(())
+}
+
+impl fmt::Display for Function {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut labels = self.targets.clone();
+    gotos(&self.body, &mut labels);
+
+    writeln!(f, "void {}() {{", self.name())?;
+    write_stmts(f, &self.body, 1, &labels)?;
+    writeln!(f, "}}")
+  }
+}
+
+/// The functions of a decompiled program.
+#[derive(Debug, Clone)]
+pub struct Decompiled {
+  pub functions: BTreeMap<usize, Function>,
+  program: Vec<i64>,
+  // code cells whose values are part of the pseudo-code
+  fixed: BTreeSet<usize>,
+}
+
+impl fmt::Display for Decompiled {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for (index, function) in self.functions.values().enumerate() {
+      if index > 0 {
+        writeln!(f)?;
+      }
+      write!(f, "{}", function)?;
+    }
+    Ok(())
+  }
+}
+
+/// Lifts `program` to pseudo-code, see the module docs.
+pub fn decompile(program: &[i64]) -> Decompiled {
+  decompile_with_targets(program, &[])
+}
+
+/// Lifts `program` to pseudo-code, with known targets of computed jumps like
+/// `analyze_with_targets`. They become labels a `goto *` can go to.
+pub fn decompile_with_targets(program: &[i64], targets: &[usize]) -> Decompiled {
+  let analysis = analyze_with_targets(program, targets);
+  let dynamic = analysis.self_modified();
+
+  let entries = std::iter::once(0)
+    .chain(analysis.subroutines.iter().copied())
+    .filter(|entry| analysis.blocks.contains_key(entry))
+    .collect::<Vec<_>>();
+
+  // how much each function moves the relative base, calls in the functions
+  // it calls included
+  let mut deltas = BTreeMap::new();
+  for _ in 0..=entries.len() {
+    let next = entries
+      .iter()
+      .map(|entry| (*entry, Lifter::new(&analysis, &dynamic, &deltas, *entry).delta()))
+      .collect();
+    if next == deltas {
+      break;
+    }
+    deltas = next;
+  }
+
+  let mut functions = BTreeMap::new();
+  for &entry in &entries {
+    let lifter = Lifter::new(&analysis, &dynamic, &deltas, entry);
+    let targets = lifter.nodes.intersection(&analysis.targets).copied().collect();
+    functions.insert(entry, Function { entry, body: lifter.lift(), targets });
+  }
+
+  // parameters the program changes are read from memory, opcodes can't be
+  let fixed = analysis.blocks
+    .values()
+    .flat_map(|block| &block.instructions)
+    .flat_map(|instruction| {
+      let params = instruction.address + 1..instruction.address + instruction.size();
+      std::iter::once(instruction.address).chain(params.filter(|cell| !dynamic.contains(cell)))
+    })
+    .collect();
+
+  Decompiled { functions, program: program.to_vec(), fixed }
+}
+
+#[derive(Clone, Default)]
+struct Context {
+  // innermost loop header and the block after the loop
+  looping: Option<(usize, Option<usize>)>,
+  // where the enclosing `if` continues, and all outer ones
+  stop: Option<usize>,
+  pending: Vec<usize>,
+}
+
+/// Structures the blocks of one function.
+struct Lifter<'a> {
+  analysis: &'a Analysis,
+  dynamic: &'a BTreeSet<usize>,
+  deltas: &'a BTreeMap<usize, Option<i64>>,
+  entry: usize,
+  nodes: BTreeSet<usize>,
+  order: BTreeMap<usize, usize>,
+  loops: BTreeMap<usize, BTreeSet<usize>>,
+  ipdom: BTreeMap<usize, usize>,
+  frames: BTreeMap<usize, Option<i64>>,
+  emitted: BTreeSet<usize>,
+}
+
+impl<'a> Lifter<'a> {
+  fn new(
+    analysis: &'a Analysis,
+    dynamic: &'a BTreeSet<usize>,
+    deltas: &'a BTreeMap<usize, Option<i64>>,
+    entry: usize,
+  ) -> Lifter<'a> {
+    let mut lifter = Lifter {
+      analysis,
+      dynamic,
+      deltas,
+      entry,
+      nodes: BTreeSet::new(),
+      order: BTreeMap::new(),
+      loops: BTreeMap::new(),
+      ipdom: BTreeMap::new(),
+      frames: BTreeMap::new(),
+      emitted: BTreeSet::new(),
+    };
+    lifter.find_loops();
+    lifter.find_post_dominators();
+    lifter.find_frames();
+    lifter
+  }
+
+  /// How a block is left. Jumps to targets the program changes are computed
+  /// jumps.
+  fn exit(&self, node: usize) -> Exit {
+    let block = &self.analysis.blocks[&node];
+    let last = block.instructions.last().unwrap();
+    if !matches!(last.opcode, Opcode::Jnz | Opcode::Jz) || !self.dynamic.contains(&(last.address + 2)) {
+      return block.exit;
+    }
+    match block.exit {
+      Exit::Call { returns, .. } => Exit::CallIndirect { returns },
+      Exit::Jump(_) => Exit::Indirect { conditional: false },
+      Exit::Branch(_) => Exit::Indirect { conditional: true },
+      exit => exit,
+    }
+  }
+
+  /// Successors within the function: calls are stepped over.
+  fn successors(&self, node: usize) -> Vec<usize> {
+    let block = &self.analysis.blocks[&node];
+    let mut targets = match self.exit(node) {
+      Exit::Next | Exit::Indirect { conditional: true } => vec![block.end()],
+      Exit::Branch(target) => vec![target, block.end()],
+      Exit::Jump(target) => vec![target],
+      Exit::Call { returns, .. } | Exit::CallIndirect { returns } => vec![returns],
+      Exit::Halt | Exit::Return | Exit::Indirect { conditional: false } => vec![],
+    };
+    if self.jumps_indirectly(node) {
+      targets.extend(&self.analysis.targets);
+    }
+    targets.into_iter().filter(|target| self.analysis.blocks.contains_key(target)).collect()
+  }
+
+  /// Whether a block ends with a computed jump. There's nothing to return
+  /// to from `main`, so what looks like a return is one as well.
+  fn jumps_indirectly(&self, node: usize) -> bool {
+    match self.exit(node) {
+      Exit::Indirect { .. } => true,
+      Exit::Return => self.entry == 0,
+      _ => false,
+    }
+  }
+
+  /// Numbers the blocks in reverse postorder and collects the natural loops.
+  fn find_loops(&mut self) {
+    let mut postorder = vec![];
+    let mut back_edges = vec![];
+    let mut on_stack = BTreeSet::new();
+    let mut stack = vec![(self.entry, 0)];
+    self.nodes.insert(self.entry);
+    on_stack.insert(self.entry);
+
+    while let Some((node, index)) = stack.pop() {
+      let successors = self.successors(node);
+      if let Some(&next) = successors.get(index) {
+        stack.push((node, index + 1));
+        if on_stack.contains(&next) {
+          back_edges.push((node, next));
+        } else if self.nodes.insert(next) {
+          on_stack.insert(next);
+          stack.push((next, 0));
+        }
+      } else {
+        on_stack.remove(&node);
+        postorder.push(node);
+      }
+    }
+    for (index, node) in postorder.iter().rev().enumerate() {
+      self.order.insert(*node, index);
+    }
+
+    let mut predecessors: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for &node in &self.nodes {
+      for next in self.successors(node) {
+        predecessors.entry(next).or_default().push(node);
+      }
+    }
+    for (tail, header) in back_edges {
+      let body = self.loops.entry(header).or_default();
+      body.insert(header);
+      let mut todo = vec![tail];
+      while let Some(node) = todo.pop() {
+        if body.insert(node) {
+          todo.extend(predecessors.get(&node).into_iter().flatten());
+        }
+      }
+    }
+  }
+
+  fn find_post_dominators(&mut self) {
+    let mut pdom: BTreeMap<usize, BTreeSet<usize>> = self.nodes.iter().map(|node| (*node, self.nodes.clone())).collect();
+    let mut changed = true;
+    while changed {
+      changed = false;
+      for &node in self.order.keys().rev() {
+        let mut set = self.successors(node)
+          .iter()
+          .map(|next| pdom[next].clone())
+          .reduce(|a, b| a.intersection(&b).copied().collect())
+          .unwrap_or_default();
+        set.insert(node);
+        if set != pdom[&node] {
+          pdom.insert(node, set);
+          changed = true;
+        }
+      }
+    }
+
+    for (&node, set) in &pdom {
+      let strict = set.len() - 1;
+      let closest = set.iter().find(|other| **other != node && pdom[*other].len() == strict);
+      if let Some(&closest) = closest {
+        self.ipdom.insert(node, closest);
+      }
+    }
+  }
+
+  /// The offset of the relative base to its value at the call, at the start
+  /// of every block. Functions called through pointers are assumed to
+  /// restore it.
+  fn find_frames(&mut self) {
+    let mut frames: BTreeMap<usize, Option<i64>> = BTreeMap::new();
+    frames.insert(self.entry, Some(0));
+    let mut todo = vec![self.entry];
+
+    while let Some(node) = todo.pop() {
+      let mut frame = self.statements(node, frames[&node]).1;
+      if let Exit::Call { target, .. } = self.exit(node) {
+        frame = frame.zip(self.deltas.get(&target).copied().unwrap_or(Some(0))).map(|(a, b)| a + b);
+      }
+
+      for next in self.successors(node) {
+        let merged = match frames.get(&next) {
+          None => frame,
+          Some(known) if *known == frame => continue,
+          Some(_) => None,
+        };
+        if frames.get(&next) != Some(&merged) {
+          frames.insert(next, merged);
+          todo.push(next);
+        }
+      }
+    }
+    self.frames = frames;
+  }
+
+  /// How far the relative base is moved when the function returns.
+  fn delta(&self) -> Option<i64> {
+    let mut deltas = self.nodes
+      .iter()
+      .filter(|node| self.analysis.blocks[*node].exit == Exit::Return)
+      .map(|node| self.statements(*node, self.frames.get(node).copied().flatten()).1);
+    let first = deltas.next().unwrap_or(Some(0));
+    if deltas.all(|delta| delta == first) {
+      first
+    } else {
+      None
+    }
+  }
+
+  fn lift(mut self) -> Vec<Stmt> {
+    let mut body = self.node(self.entry, &Context::default());
+    // code only reached through computed jumps
+    let mut order = self.order.iter().map(|(node, index)| (*index, *node)).collect::<Vec<_>>();
+    order.sort();
+    for (_, node) in order {
+      if !self.emitted.contains(&node) {
+        body.extend(self.node(node, &Context::default()));
+      }
+    }
+    tidy(&mut body);
+    body
+  }
+
+  /// The parameter itself, read from memory if the program changes it.
+  fn param(&self, instruction: &Instruction, index: usize) -> Expr {
+    let cell = instruction.address + index + 1;
+    if self.dynamic.contains(&cell) {
+      Expr::Mem(Box::new(Expr::Const(cell as i64)))
+    } else {
+      Expr::Const(instruction.params[index].value)
+    }
+  }
+
+  /// The cell a position or relative parameter refers to.
+  fn cell(&self, instruction: &Instruction, index: usize, frame: Option<i64>) -> Expr {
+    let param = self.param(instruction, index);
+    match (instruction.params[index].mode, param, frame) {
+      (Mode::Relative, Expr::Const(offset), Some(frame)) => Expr::Local(frame + offset),
+      (Mode::Relative, param, _) => Expr::Mem(Box::new(Expr::Binary(BinOp::Add, Box::new(Expr::Base), Box::new(param)))),
+      (_, param, _) => Expr::Mem(Box::new(param)),
+    }
+  }
+
+  fn operand(&self, instruction: &Instruction, index: usize, frame: Option<i64>) -> Expr {
+    match instruction.params[index].mode {
+      Mode::Immediate => self.param(instruction, index),
+      _ => self.cell(instruction, index, frame),
+    }
+  }
+
+  /// The statements of a block, without its jump, and the frame offset
+  /// after them.
+  fn statements(&self, node: usize, mut frame: Option<i64>) -> (Vec<Stmt>, Option<i64>) {
+    let mut stmts = vec![];
+    for instruction in &self.analysis.blocks[&node].instructions {
+      let operand = |index| self.operand(instruction, index, frame);
+      match instruction.opcode {
+        Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => {
+          let op = match instruction.opcode {
+            Opcode::Add => BinOp::Add,
+            Opcode::Mul => BinOp::Mul,
+            Opcode::Lt => BinOp::Lt,
+            _ => BinOp::Eq,
+          };
+          let value = match (op, operand(0), operand(1)) {
+            (BinOp::Add, Expr::Const(0), value) | (BinOp::Add, value, Expr::Const(0)) => value,
+            (BinOp::Mul, Expr::Const(1), value) | (BinOp::Mul, value, Expr::Const(1)) => value,
+            (op, a, b) => Expr::Binary(op, Box::new(a), Box::new(b)),
+          };
+          stmts.push(Stmt::Assign(self.cell(instruction, 2, frame), value));
+        },
+        Opcode::In => stmts.push(Stmt::Input(self.cell(instruction, 0, frame))),
+        Opcode::Out => stmts.push(Stmt::Output(operand(0))),
+        Opcode::Arb => {
+          let value = operand(0);
+          frame = match (&value, frame) {
+            (Expr::Const(value), Some(frame)) => Some(frame + value),
+            _ => None,
+          };
+          stmts.push(Stmt::AdjustBase(value));
+        },
+        Opcode::Jnz | Opcode::Jz | Opcode::Hlt => {},
+      }
+    }
+    (stmts, frame)
+  }
+
+  fn node(&mut self, node: usize, context: &Context) -> Vec<Stmt> {
+    self.emitted.insert(node);
+    let mut stmts = vec![Stmt::Label(node)];
+
+    if let Some(body) = self.loops.get(&node).cloned() {
+      let follow = body
+        .iter()
+        .flat_map(|member| self.successors(*member))
+        .filter(|next| !body.contains(next))
+        .min_by_key(|next| self.order[next]);
+      let mut inner = Context { looping: Some((node, follow)), stop: None, pending: context.pending.clone() };
+      inner.pending.extend(context.stop);
+      stmts.push(Stmt::While { cond: None, body: self.block(node, &inner) });
+      if let Some(follow) = follow {
+        stmts.extend(self.transfer(follow, context));
+      }
+    } else {
+      stmts.extend(self.block(node, context));
+    }
+    stmts
+  }
+
+  /// A block followed by the statements that lead to its successors.
+  fn block(&mut self, node: usize, context: &Context) -> Vec<Stmt> {
+    let (mut stmts, frame) = self.statements(node, self.frames.get(&node).copied().flatten());
+    let block = &self.analysis.blocks[&node];
+    let last = block.instructions.last().unwrap().clone();
+    let end = block.end();
+
+    match self.exit(node) {
+      Exit::Next => stmts.extend(self.transfer(end, context)),
+      Exit::Halt => stmts.push(Stmt::Halt),
+      Exit::Jump(target) => stmts.extend(self.transfer(target, context)),
+      Exit::Call { target, returns } => {
+        stmts.push(Stmt::Call { target, returns });
+        stmts.extend(self.transfer(returns, context));
+      },
+      Exit::CallIndirect { returns } => {
+        stmts.push(Stmt::CallIndirect { target: self.operand(&last, 1, frame), returns });
+        stmts.extend(self.transfer(returns, context));
+      },
+      Exit::Return if self.entry == 0 => stmts.push(Stmt::GotoIndirect(self.operand(&last, 1, frame))),
+      Exit::Return => stmts.push(Stmt::Return(self.operand(&last, 1, frame))),
+      Exit::Indirect { conditional: false } => stmts.push(Stmt::GotoIndirect(self.operand(&last, 1, frame))),
+      Exit::Indirect { conditional: true } => {
+        let cond = Cond { expr: self.operand(&last, 0, frame), nonzero: last.opcode == Opcode::Jnz };
+        let then = vec![Stmt::GotoIndirect(self.operand(&last, 1, frame))];
+        stmts.push(Stmt::If { cond, then, otherwise: vec![] });
+        stmts.extend(self.transfer(end, context));
+      },
+      Exit::Branch(target) => {
+        let cond = Cond { expr: self.operand(&last, 0, frame), nonzero: last.opcode == Opcode::Jnz };
+        let follow = self.ipdom.get(&node).copied().filter(|follow| match context.looping {
+          Some((header, _)) => self.loops[&header].contains(follow),
+          None => true,
+        });
+
+        let mut inner = context.clone();
+        inner.pending.extend(context.stop);
+        inner.stop = follow;
+        let then = self.transfer(target, &inner);
+        let otherwise = self.transfer(end, &inner);
+        stmts.push(Stmt::If { cond, then, otherwise });
+        if let Some(follow) = follow {
+          stmts.extend(self.transfer(follow, context));
+        }
+      },
+    }
+    stmts
+  }
+
+  /// The statements that continue execution at `target`.
+  fn transfer(&mut self, target: usize, context: &Context) -> Vec<Stmt> {
+    if !self.nodes.contains(&target) {
+      return vec![Stmt::GotoIndirect(Expr::Const(target as i64))];
+    }
+    if context.stop == Some(target) {
+      return vec![];
+    }
+    if let Some((header, follow)) = context.looping {
+      if target == header {
+        return vec![Stmt::Continue];
+      }
+      if follow == Some(target) {
+        return vec![Stmt::Break];
+      }
+      let leaves = !self.loops[&header].contains(&target);
+      if leaves && (self.emitted.contains(&target) || !self.successors(target).is_empty()) {
+        return vec![Stmt::Goto(target)];
+      }
+    }
+    if self.emitted.contains(&target) || context.pending.contains(&target) {
+      return vec![Stmt::Goto(target)];
+    }
+    self.node(target, context)
+  }
+}
+
+/// Turns loops that start or end with a conditional `break` into `while`
+/// loops and drops redundant `continue`s and empty branches.
+fn tidy(stmts: &mut [Stmt]) {
+  for stmt in stmts.iter_mut() {
+    match stmt {
+      Stmt::If { cond, then, otherwise } => {
+        tidy(then);
+        tidy(otherwise);
+        if then.is_empty() && !otherwise.is_empty() {
+          *cond = cond.clone().negate();
+          std::mem::swap(then, otherwise);
+        }
+      },
+      Stmt::While { cond, body } => {
+        tidy(body);
+        if cond.is_none() {
+          if let Some(Stmt::If { cond: check, then, otherwise }) = body.first() {
+            let rest = match (then.as_slice(), otherwise.as_slice()) {
+              ([Stmt::Break], rest) => Some((check.clone().negate(), rest.to_vec())),
+              (rest, [Stmt::Break]) => Some((check.clone(), rest.to_vec())),
+              _ => None,
+            };
+            if let Some((check, rest)) = rest {
+              let tail = body.split_off(1);
+              *body = rest;
+              body.extend(tail);
+              *cond = Some(check);
+            }
+          }
+        }
+        if body.last() == Some(&Stmt::Continue) {
+          body.pop();
+        }
+      },
+      _ => {},
+    }
+  }
+}
+
+/// Why running pseudo-code failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunError {
+  pub what: String,
+}
+
+impl fmt::Display for RunError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.what)
+  }
+}
+
+impl std::error::Error for RunError {}
+
+fn fail<T>(what: String) -> Result<T, RunError> {
+  Err(RunError { what })
+}
+
+enum Signal {
+  Normal,
+  Break,
+  Continue,
+  Goto(usize),
+  Return(i64),
+  Halt,
+  Blocked,
+}
+
+fn contains_label(stmt: &Stmt, label: usize) -> bool {
+  match stmt {
+    Stmt::Label(address) => *address == label,
+    Stmt::If { then, otherwise, .. } => then.iter().chain(otherwise).any(|stmt| contains_label(stmt, label)),
+    Stmt::While { body, .. } => body.iter().any(|stmt| contains_label(stmt, label)),
+    _ => false,
+  }
+}
+
+/// Runs pseudo-code on a memory initialized with the program.
+struct Runner<'a, D: ?Sized> {
+  decompiled: &'a Decompiled,
+  memory: PagedMemory,
+  rb: i64,
+  device: &'a mut D,
+}
+
+impl<D: IoDevice + ?Sized> Runner<'_, D> {
+  fn address(&self, address: i64) -> Result<usize, RunError> {
+    if address < 0 || address as usize >= DEFAULT_MEMORY_LIMIT {
+      return fail(format!("access to invalid address {}", address));
+    }
+    Ok(address as usize)
+  }
+
+  fn location(&self, expr: &Expr, frame: i64) -> Result<usize, RunError> {
+    match expr {
+      Expr::Mem(address) => self.address(self.eval(address, frame)?),
+      Expr::Local(offset) => match frame.checked_add(*offset) {
+        Some(address) => self.address(address),
+        None => fail(format!("arithmetic overflow in the address of {}", expr)),
+      },
+      _ => fail(format!("cannot assign to {}", expr)),
+    }
+  }
+
+  fn eval(&self, expr: &Expr, frame: i64) -> Result<i64, RunError> {
+    Ok(match expr {
+      Expr::Const(value) => *value,
+      Expr::Base => self.rb,
+      Expr::Mem(_) | Expr::Local(_) => self.memory.get(self.location(expr, frame)?),
+      Expr::Binary(op, a, b) => match op.apply(self.eval(a, frame)?, self.eval(b, frame)?) {
+        Some(value) => value,
+        None => return fail(format!("arithmetic overflow in {}", expr)),
+      },
+    })
+  }
+
+  fn store(&mut self, target: &Expr, value: i64, frame: i64) -> Result<(), RunError> {
+    let address = self.location(target, frame)?;
+    if self.decompiled.fixed.contains(&address) && self.memory.get(address) != value {
+      return fail(format!("{} = {} changes code at {}", target, value, address));
+    }
+    self.memory.set(address, value);
+    Ok(())
+  }
+
+  fn call(&mut self, target: i64, returns: usize) -> Result<Signal, RunError> {
+    let function = match self.decompiled.functions.get(&(target as usize)) {
+      Some(function) if target >= 0 => function,
+      _ => return fail(format!("call of {}, which is not a function", target)),
+    };
+    match self.function(function)? {
+      Signal::Return(address) if address == returns as i64 => Ok(Signal::Normal),
+      Signal::Return(address) => fail(format!("{} returned to {} instead of {}", function.name(), address, returns)),
+      signal => Ok(signal),
+    }
+  }
+
+  fn function(&mut self, function: &Function) -> Result<Signal, RunError> {
+    let frame = self.rb;
+    let mut resume = None;
+    loop {
+      match self.stmts(&function.body, frame, &mut resume)? {
+        Signal::Goto(label) if function.body.iter().any(|stmt| contains_label(stmt, label)) => resume = Some(label),
+        Signal::Goto(label) => return fail(format!("{} jumps to {}, which is not part of it", function.name(), label)),
+        Signal::Normal | Signal::Break | Signal::Continue => {
+          return fail(format!("{} ended without returning", function.name()));
+        },
+        signal => return Ok(signal),
+      }
+    }
+  }
+
+  /// Runs `stmts`, starting at the label in `resume` if there is one.
+  fn stmts(&mut self, stmts: &[Stmt], frame: i64, resume: &mut Option<usize>) -> Result<Signal, RunError> {
+    let start = match *resume {
+      Some(label) => match stmts.iter().position(|stmt| contains_label(stmt, label)) {
+        Some(start) => start,
+        None => return Ok(Signal::Normal),
+      },
+      None => 0,
+    };
+
+    for stmt in &stmts[start..] {
+      match self.stmt(stmt, frame, resume)? {
+        Signal::Normal => {},
+        signal => return Ok(signal),
+      }
+    }
+    Ok(Signal::Normal)
+  }
+
+  fn stmt(&mut self, stmt: &Stmt, frame: i64, resume: &mut Option<usize>) -> Result<Signal, RunError> {
+    match stmt {
+      Stmt::Assign(target, value) => {
+        let value = self.eval(value, frame)?;
+        self.store(target, value, frame)?;
+      },
+      Stmt::Input(target) => match self.device.input() {
+        Some(value) => self.store(target, value, frame)?,
+        None => return Ok(Signal::Blocked),
+      },
+      Stmt::Output(value) => {
+        let value = self.eval(value, frame)?;
+        self.device.output(value);
+      },
+      Stmt::AdjustBase(value) => {
+        let value = self.eval(value, frame)?;
+        self.rb = match self.rb.checked_add(value) {
+          Some(rb) => rb,
+          None => return fail(format!("arithmetic overflow in rb += {}", value)),
+        };
+      },
+      Stmt::Call { target, returns } => return self.call(*target as i64, *returns),
+      Stmt::CallIndirect { target, returns } => {
+        let target = self.eval(target, frame)?;
+        return self.call(target, *returns);
+      },
+      Stmt::Return(target) => return Ok(Signal::Return(self.eval(target, frame)?)),
+      Stmt::Halt => return Ok(Signal::Halt),
+      Stmt::If { cond, then, otherwise } => {
+        let branch = match *resume {
+          Some(label) if then.iter().any(|stmt| contains_label(stmt, label)) => then,
+          Some(_) => otherwise,
+          None if (self.eval(&cond.expr, frame)? != 0) == cond.nonzero => then,
+          None => otherwise,
+        };
+        return self.stmts(branch, frame, resume);
+      },
+      Stmt::While { cond, body } => loop {
+        if let (None, Some(cond)) = (*resume, cond) {
+          if (self.eval(&cond.expr, frame)? != 0) != cond.nonzero {
+            break;
+          }
+        }
+        match self.stmts(body, frame, resume)? {
+          Signal::Normal | Signal::Continue => {},
+          Signal::Break => break,
+          signal => return Ok(signal),
+        }
+      },
+      Stmt::Break => return Ok(Signal::Break),
+      Stmt::Continue => return Ok(Signal::Continue),
+      Stmt::Label(label) => {
+        if *resume == Some(*label) {
+          *resume = None;
+        }
+      },
+      Stmt::Goto(label) => return Ok(Signal::Goto(*label)),
+      Stmt::GotoIndirect(target) => {
+        let target = self.eval(target, frame)?;
+        if target < 0 {
+          return fail(format!("jump to invalid address {}", target));
+        }
+        return Ok(Signal::Goto(target as usize));
+      },
+    }
+    Ok(Signal::Normal)
+  }
+}
+
+impl Decompiled {
+  /// Runs the pseudo-code like `Machine::run_with` runs the program. Unlike a
+  /// machine it can't be resumed: it stops for good at the first input the
+  /// device can't provide, returning `State::NeedsInput`.
+  pub fn run_with<D: IoDevice + ?Sized>(&self, device: &mut D) -> Result<State, RunError> {
+    let main = match self.functions.get(&0) {
+      Some(main) => main,
+      None => return fail("there is no code at 0".to_string()),
+    };
+
+    let mut runner = Runner { decompiled: self, memory: PagedMemory::load(&self.program), rb: 0, device };
+    match runner.function(main)? {
+      Signal::Halt => Ok(State::Halted),
+      Signal::Blocked => Ok(State::NeedsInput),
+      _ => fail("main returned".to_string()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{assemble, parse_instructions, Machine, Opcode, TraceEntry, VmError};
+  use std::sync::{Arc, Mutex};
+
+  // runs the program and its pseudo-code with the same made-up input, the
+  // targets of computed jumps are taken from a trace
+  fn compare(program: &[i64], inputs: &[i64]) {
+    let trace = Arc::new(Mutex::new(Vec::<TraceEntry>::new()));
+    let mut machine = Machine::new(program);
+    machine.set_tracer(Box::new(trace.clone()));
+    let mut expected = vec![];
+    let mut feed = inputs.iter().copied().cycle().take(2000);
+    let state = machine.run_with(&mut (|| feed.next(), &mut expected)).unwrap();
+
+    let targets = trace
+      .lock()
+      .unwrap()
+      .windows(2)
+      .filter(|pair| matches!(Opcode::from_raw(pair[0].raw), Some(Opcode::Jnz | Opcode::Jz)))
+      .map(|pair| pair[1].ip)
+      .collect::<Vec<_>>();
+
+    let mut output = vec![];
+    let mut feed = inputs.iter().copied().cycle().take(2000);
+    let decompiled = decompile_with_targets(program, &targets);
+    assert_eq!(decompiled.run_with(&mut (|| feed.next(), &mut output)), Ok(state));
+    assert!(!expected.is_empty());
+    assert_eq!(output, expected);
+  }
+
+  #[test]
+  fn structures_loops_conditions_and_calls() {
+    let program = assemble("
+              arb stack
+              in [n]
+      loop:   jz [n], done
+              call show
+              add [n], -1, [n]
+              jz 0, loop
+      done:   hlt
+      show:   lt [n], 3, [rb]
+              jnz [rb], small
+              out [n]
+              ret
+      small:  mul [n], 10, [rb]
+              out [rb]
+              ret
+      n:      .data 0
+      stack:  .data 0
+    ").unwrap();
+
+    assert_eq!(decompile(&program).to_string(), concat!(
+      "void main() {\n",
+      "  rb += 50;\n",
+      "  mem[49] = input();\n",
+      "  while (mem[49] != 0) {\n",
+      "    local50 = 16;\n",
+      "    rb += 1;\n",
+      "    f24();\n",
+      "    mem[49] = mem[49] + -1;\n",
+      "  }\n",
+      "  halt();\n",
+      "}\n",
+      "\n",
+      "void f24() {\n",
+      "  local0 = mem[49] < 3;\n",
+      "  if (local0 != 0) {\n",
+      "    local0 = mem[49] * 10;\n",
+      "    output(local0);\n",
+      "    rb -= 1;\n",
+      "    return;\n",
+      "  } else {\n",
+      "    output(mem[49]);\n",
+      "    rb -= 1;\n",
+      "    return;\n",
+      "  }\n",
+      "}\n",
+    ));
+    compare(&program, &[4]);
+  }
+
+  #[test]
+  fn changing_code_is_an_error() {
+    // day 5 style: the input is written into an opcode
+    let program = assemble("
+              in [patch]
+      patch:  add 0, 0, [7]
+              hlt
+    ").unwrap();
+    let decompiled = decompile(&program);
+
+    let result = decompiled.run_with(&mut (|| Some(99), ()));
+    assert!(result.unwrap_err().what.contains("changes code"));
+  }
+
+  #[test]
+  fn faults_are_errors() {
+    let program = [1102, i64::MAX, 2, 9, 4, 9, 99, 0, 0, 0];
+    assert_eq!(Machine::new(&program).run(), Err(VmError::Overflow { ip: 0, opcode: 1102 }));
+    let result = decompile(&program).run_with(&mut ((), ()));
+    assert!(result.unwrap_err().what.contains("overflow"));
+
+    let program = assemble("
+              in [target]
+              jnz 1, [target]
+      target: .data 0
+    ").unwrap();
+    assert!(matches!(Machine::new(&program).run_with(&mut (|| Some(-5), ())), Err(VmError::IpOutOfBounds { target: -5, .. })));
+    let result = decompile(&program).run_with(&mut (|| Some(-5), ()));
+    assert_eq!(result.unwrap_err().what, "jump to invalid address -5");
+  }
+
+  #[test]
+  fn pseudo_code_behaves_like_the_program() {
+    let programs = [
+      (include_str!("../day7/data/input-1.txt"), vec![3, 7]),
+      (include_str!("../day9/data/input-1.txt"), vec![1]),
+      (include_str!("../day11/data/input-1.txt"), vec![0, 1, 1]),
+      (include_str!("../day13/data/input-1.txt"), vec![]),
+      (include_str!("../day15/data/input-1.txt"), vec![1, 4, 2, 3]),
+      (include_str!("../day17/data/input-1.txt"), vec![]),
+      (include_str!("../day19/data/input-1.txt"), vec![10, 20]),
+    ];
+
+    for (program, inputs) in programs.iter() {
+      compare(&parse_instructions(program.trim()), inputs);
+    }
+  }
+}
@@ -0,0 +1,203 @@
+//! Synchronous I/O for `Machine::run_with`.
+//!
+//! A device is asked for a value whenever the machine executes `in` with an
+//! empty input queue and is handed every value the machine outputs. Drivers
+//! that react to output implement `IoDevice` directly; simple ones combine an
+//! `InputProvider` and an `OutputSink` in a tuple. Collections can be passed
+//! by value or by mutable reference:
+//!
+//! ```
+//! # use std::collections::VecDeque;
+//! # use y2019::intcode::{Machine, State};
+//! let mut machine = Machine::new(&[3, 0, 4, 0, 99]);
+//! let mut output = vec![];
+//! assert_eq!(machine.run_with(&mut (VecDeque::from(vec![7]), &mut output)), Ok(State::Halted));
+//! assert_eq!(output, vec![7]);
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender};
+use std::vec;
+
+/// Input provider and output sink of a machine.
+pub trait IoDevice {
+  /// The next input value. Returning `None` pauses the machine with
+  /// `State::NeedsInput`.
+  fn input(&mut self) -> Option<i64>;
+
+  fn output(&mut self, value: i64);
+}
+
+pub trait InputProvider {
+  fn next_input(&mut self) -> Option<i64>;
+}
+
+pub trait OutputSink {
+  fn write_output(&mut self, value: i64);
+}
+
+impl<I: InputProvider, O: OutputSink> IoDevice for (I, O) {
+  fn input(&mut self) -> Option<i64> {
+    self.0.next_input()
+  }
+
+  fn output(&mut self, value: i64) {
+    self.1.write_output(value);
+  }
+}
+
+impl<D: IoDevice + ?Sized> IoDevice for &mut D {
+  fn input(&mut self) -> Option<i64> {
+    (**self).input()
+  }
+
+  fn output(&mut self, value: i64) {
+    (**self).output(value);
+  }
+}
+
+/// Provides no input at all.
+impl InputProvider for () {
+  fn next_input(&mut self) -> Option<i64> {
+    None
+  }
+}
+
+impl InputProvider for VecDeque<i64> {
+  fn next_input(&mut self) -> Option<i64> {
+    self.pop_front()
+  }
+}
+
+impl InputProvider for &mut VecDeque<i64> {
+  fn next_input(&mut self) -> Option<i64> {
+    self.pop_front()
+  }
+}
+
+impl InputProvider for vec::IntoIter<i64> {
+  fn next_input(&mut self) -> Option<i64> {
+    self.next()
+  }
+}
+
+/// Blocks until a value arrives. A closed channel has no more input.
+impl InputProvider for Receiver<i64> {
+  fn next_input(&mut self) -> Option<i64> {
+    self.recv().ok()
+  }
+}
+
+impl<F: FnMut() -> Option<i64>> InputProvider for F {
+  fn next_input(&mut self) -> Option<i64> {
+    self()
+  }
+}
+
+/// Discards all output.
+impl OutputSink for () {
+  fn write_output(&mut self, _value: i64) {}
+}
+
+impl OutputSink for Vec<i64> {
+  fn write_output(&mut self, value: i64) {
+    self.push(value);
+  }
+}
+
+impl OutputSink for &mut Vec<i64> {
+  fn write_output(&mut self, value: i64) {
+    self.push(value);
+  }
+}
+
+impl OutputSink for VecDeque<i64> {
+  fn write_output(&mut self, value: i64) {
+    self.push_back(value);
+  }
+}
+
+impl OutputSink for &mut VecDeque<i64> {
+  fn write_output(&mut self, value: i64) {
+    self.push_back(value);
+  }
+}
+
+/// Values sent after the receiver was dropped are discarded.
+impl OutputSink for Sender<i64> {
+  fn write_output(&mut self, value: i64) {
+    let _ = self.send(value);
+  }
+}
+
+impl<F: FnMut(i64)> OutputSink for F {
+  fn write_output(&mut self, value: i64) {
+    self(value);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{Machine, State};
+  use std::sync::mpsc::channel;
+
+  // adds pairs of inputs until it reads a 0
+  fn adder() -> Vec<i64> {
+    vec![3, 20, 1006, 20, 16, 3, 21, 1, 20, 21, 22, 4, 22, 1105, 1, 0, 99]
+  }
+
+  #[test]
+  fn tuples_of_providers_and_sinks() {
+    let mut output = vec![];
+    let mut machine = Machine::new(&adder());
+    let state = machine.run_with(&mut (VecDeque::from(vec![1, 2, 3, 4, 0]), &mut output));
+    assert_eq!(state, Ok(State::Halted));
+    assert_eq!(output, vec![3, 7]);
+
+    let mut machine = Machine::new(&adder());
+    let mut sink = VecDeque::new();
+    assert_eq!(machine.run_with(&mut (vec![5, 6].into_iter(), &mut sink)), Ok(State::NeedsInput));
+    assert_eq!(machine.run_with(&mut (vec![0].into_iter(), &mut sink)), Ok(State::Halted));
+    assert_eq!(sink, VecDeque::from(vec![11]));
+  }
+
+  #[test]
+  fn closures_and_channels() {
+    let mut inputs = vec![0, 9, 1].into_iter();
+    let mut sum = 0;
+    let mut machine = Machine::new(&adder());
+    assert_eq!(machine.run_with(&mut (|| inputs.next_back(), |value| sum += value)), Ok(State::Halted));
+    assert_eq!(sum, 10);
+
+    let (input, recv) = channel();
+    let (send, output) = channel();
+    input.send(20).unwrap();
+    input.send(22).unwrap();
+    drop(input);
+    let mut machine = Machine::new(&adder());
+    assert_eq!(machine.run_with(&mut (recv, send)), Ok(State::NeedsInput));
+    assert_eq!(output.try_iter().collect::<Vec<_>>(), vec![42]);
+  }
+
+  struct Countdown(i64, Vec<i64>);
+
+  impl IoDevice for Countdown {
+    fn input(&mut self) -> Option<i64> {
+      self.0 -= 1;
+      Some(self.0)
+    }
+
+    fn output(&mut self, value: i64) {
+      self.1.push(value);
+    }
+  }
+
+  #[test]
+  fn devices_react_to_output() {
+    let mut device = Countdown(5, vec![]);
+    let mut machine = Machine::new(&adder());
+    assert_eq!(machine.run_with(&mut device), Ok(State::Halted));
+    assert_eq!(device.1, vec![7, 3]);
+  }
+}
@@ -0,0 +1,105 @@
+use std::fmt::Write;
+
+use super::{analyze_with_targets, Instruction};
+
+/// A line of a disassembly listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+  Code(Instruction),
+  Data { address: usize, value: i64 },
+}
+
+impl Line {
+  pub fn address(&self) -> usize {
+    match self {
+      Line::Code(instruction) => instruction.address,
+      Line::Data { address, .. } => *address,
+    }
+  }
+}
+
+/// Disassembles a program. Everything `analyze` finds to be reachable code
+/// is listed as instructions, all other words as data.
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+  disassemble_with_targets(program, &[])
+}
+
+/// Disassembles a program with known targets of computed jumps, see
+/// `analyze_with_targets`.
+pub fn disassemble_with_targets(program: &[i64], targets: &[usize]) -> Vec<Line> {
+  let analysis = analyze_with_targets(program, targets);
+  let mut lines = vec![];
+  let mut address = 0;
+  while address < program.len() {
+    if let Some(instruction) = analysis.instruction(address) {
+      address += instruction.size();
+      lines.push(Line::Code(instruction.clone()));
+    } else {
+      lines.push(Line::Data { address, value: program[address] });
+      address += 1;
+    }
+  }
+
+  lines
+}
+
+/// Renders a listing with one instruction or data word per line, each
+/// prefixed with its address.
+pub fn render(lines: &[Line]) -> String {
+  let width = lines.last().map_or(1, |line| line.address().to_string().len());
+  let mut listing = String::new();
+
+  for line in lines {
+    match line {
+      Line::Code(instruction) => writeln!(listing, "{:>width$}: {}", instruction.address, instruction, width = width),
+      Line::Data { address, value } => writeln!(listing, "{:>width$}: .data {}", address, value, width = width),
+    }.unwrap();
+  }
+
+  listing
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{Mode, Opcode, Param};
+
+  #[test]
+  fn disassembles_code_and_data() {
+    // the day 5 "compare to 8" example: 9 and 10 are never executed
+    let lines = disassemble(&[3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[1], Line::Code(Instruction {
+      address: 2,
+      raw: 8,
+      opcode: Opcode::Eq,
+      params: vec![
+        Param { mode: Mode::Position, value: 9 },
+        Param { mode: Mode::Position, value: 10 },
+        Param { mode: Mode::Position, value: 9 },
+      ],
+    }));
+    assert_eq!(lines[4], Line::Data { address: 9, value: -1 });
+  }
+
+  #[test]
+  fn renders_listing() {
+    let listing = render(&disassemble(&[109, -3, 21101, 4, 5, 3, 1105, 1, 11, 204, 2, 99]));
+
+    assert_eq!(listing, concat!(
+      " 0: arb -3\n",
+      " 2: add 4, 5, [rb+3]\n",
+      " 6: jnz 1, 11\n",
+      " 9: out [rb+2]\n",
+      "11: hlt\n",
+    ));
+  }
+
+  #[test]
+  fn undecodable_words_are_data() {
+    let listing = render(&disassemble(&[1106, 0, 4, 1234, 99, 1, 0]));
+
+    assert_eq!(listing, "0: jz 0, 4\n3: .data 1234\n4: hlt\n5: .data 1\n6: .data 0\n");
+  }
+}
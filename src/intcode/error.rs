@@ -0,0 +1,76 @@
+use std::fmt;
+
+use super::Limit;
+
+/// A fault raised while executing an Intcode program.
+///
+/// Every variant carries the instruction pointer and the raw opcode of the
+/// instruction that caused the fault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+  InvalidOpcode { ip: usize, opcode: i64 },
+  InvalidParamMode { ip: usize, opcode: i64, mode: i64 },
+  NegativeAddress { ip: usize, opcode: i64, address: i64 },
+  WriteInImmediateMode { ip: usize, opcode: i64 },
+  InputClosed { ip: usize, opcode: i64 },
+  IpOutOfBounds { ip: usize, opcode: i64, target: i64 },
+  MemoryLimitExceeded { ip: usize, opcode: i64, address: i64 },
+  /// A result doesn't fit into the machine's words.
+  Overflow { ip: usize, opcode: i64 },
+  /// Raised by an instruction on purpose, see `Cpu::trap`.
+  Trap { ip: usize, opcode: i64 },
+  /// The run stopped before the instruction at `ip`, see `Budget`.
+  BudgetExhausted { ip: usize, opcode: i64, limit: Limit },
+}
+
+impl VmError {
+  pub fn ip(&self) -> usize {
+    match *self {
+      VmError::InvalidOpcode { ip, .. }
+      | VmError::InvalidParamMode { ip, .. }
+      | VmError::NegativeAddress { ip, .. }
+      | VmError::WriteInImmediateMode { ip, .. }
+      | VmError::InputClosed { ip, .. }
+      | VmError::IpOutOfBounds { ip, .. }
+      | VmError::MemoryLimitExceeded { ip, .. }
+      | VmError::Overflow { ip, .. }
+      | VmError::Trap { ip, .. }
+      | VmError::BudgetExhausted { ip, .. } => ip,
+    }
+  }
+
+  pub fn opcode(&self) -> i64 {
+    match *self {
+      VmError::InvalidOpcode { opcode, .. }
+      | VmError::InvalidParamMode { opcode, .. }
+      | VmError::NegativeAddress { opcode, .. }
+      | VmError::WriteInImmediateMode { opcode, .. }
+      | VmError::InputClosed { opcode, .. }
+      | VmError::IpOutOfBounds { opcode, .. }
+      | VmError::MemoryLimitExceeded { opcode, .. }
+      | VmError::Overflow { opcode, .. }
+      | VmError::Trap { opcode, .. }
+      | VmError::BudgetExhausted { opcode, .. } => opcode,
+    }
+  }
+}
+
+impl fmt::Display for VmError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      VmError::InvalidOpcode { .. } => write!(f, "invalid opcode")?,
+      VmError::InvalidParamMode { mode, .. } => write!(f, "invalid parameter mode {}", mode)?,
+      VmError::NegativeAddress { address, .. } => write!(f, "access to negative address {}", address)?,
+      VmError::WriteInImmediateMode { .. } => write!(f, "write parameter in immediate mode")?,
+      VmError::InputClosed { .. } => write!(f, "input closed while waiting for a value")?,
+      VmError::IpOutOfBounds { target, .. } => write!(f, "instruction pointer out of bounds: {}", target)?,
+      VmError::MemoryLimitExceeded { address, .. } => write!(f, "address {} exceeds the memory limit", address)?,
+      VmError::Overflow { .. } => write!(f, "arithmetic overflow")?,
+      VmError::Trap { .. } => write!(f, "trap")?,
+      VmError::BudgetExhausted { limit, .. } => write!(f, "budget of {} exhausted", limit)?,
+    }
+    write!(f, " (ip {}, opcode {})", self.ip(), self.opcode())
+  }
+}
+
+impl std::error::Error for VmError {}
@@ -0,0 +1,464 @@
+//! Differential testing of the execution backends.
+//!
+//! `generate_program` writes random programs that halt by construction: jumps only go
+//! forward, except for the back edge of loops that count down a counter no
+//! other instruction writes to, and all writes go to a data area behind the
+//! code. `check_backends` runs a program on every backend and compares the results
+//! with the ones of the plain interpreter, and `minimize` shrinks a program
+//! that makes `check_backends` fail to a small reproducer:
+//!
+//! ```
+//! # use rand::{rngs::StdRng, SeedableRng};
+//! # use y2019::intcode::{check_backends, generate_program, minimize};
+//! let mut rng = StdRng::seed_from_u64(7);
+//! let (program, inputs) = generate_program(&mut rng, 40);
+//! if check_backends(&program, &inputs).is_err() {
+//!   let reproducer = minimize(&program, |program| check_backends(program, &inputs).is_err());
+//!   panic!("backends differ on {:?}", reproducer);
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::{Budget, DenseMemory, InstructionSet, Machine, Memory, Opcode, State, VmError, WideMachine};
+
+/// Keeps programs mutated by `minimize` from running forever.
+const BUDGET: Budget = Budget::unlimited().instructions(100_000);
+
+/// Number of cells of the data area.
+const DATA: usize = 32;
+
+/// Largest step `arb` takes in a generated program.
+const ARB_STEP: i64 = 3;
+
+/// A backend to run programs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+  /// `Machine` with its default memory.
+  Interpreter,
+  /// `Machine` with compiled code.
+  Compiled,
+  /// `Machine` with `DenseMemory`.
+  Dense,
+  /// `Machine` with the standard `InstructionSet`.
+  InstructionSet,
+  /// `WideMachine<i64>`.
+  Wide,
+}
+
+impl Backend {
+  pub const ALL: [Backend; 5] = [
+    Backend::Interpreter, Backend::Compiled, Backend::Dense, Backend::InstructionSet, Backend::Wide,
+  ];
+}
+
+/// Everything observable about a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+  pub result: Result<State, VmError>,
+  pub output: Vec<i64>,
+  pub ip: usize,
+  pub relative_base: i64,
+  pub steps: u64,
+  /// All cells that are not 0 at the end of the run.
+  pub memory: BTreeMap<usize, i64>,
+}
+
+fn nonzero<'a, I: IntoIterator<Item = (usize, &'a [i64])>>(chunks: I) -> BTreeMap<usize, i64> {
+  chunks
+    .into_iter()
+    .flat_map(|(address, cells)| cells.iter().enumerate().map(move |(offset, cell)| (address + offset, *cell)))
+    .filter(|(_, cell)| *cell != 0)
+    .collect()
+}
+
+fn run_machine<M: Memory>(mut machine: Machine<M>, inputs: &[i64]) -> Outcome {
+  machine.set_budget(BUDGET);
+  machine.extend_input(inputs.iter().copied());
+  let result = machine.run();
+  Outcome {
+    result,
+    output: machine.drain_output(),
+    ip: machine.ip(),
+    relative_base: machine.relative_base(),
+    steps: machine.steps(),
+    memory: nonzero(machine.memory().chunks()),
+  }
+}
+
+/// Runs `program` on `backend` until it halts, faults, runs out of `inputs`
+/// or has executed 100000 instructions.
+pub fn run_backend(backend: Backend, program: &[i64], inputs: &[i64]) -> Outcome {
+  match backend {
+    Backend::Interpreter => run_machine(Machine::new(program), inputs),
+    Backend::Compiled => {
+      let mut machine = Machine::new(program);
+      machine.compile();
+      run_machine(machine, inputs)
+    },
+    Backend::Dense => run_machine(Machine::with_memory(DenseMemory::load(program)), inputs),
+    Backend::InstructionSet => {
+      let mut machine = Machine::new(program);
+      machine.set_instruction_set(InstructionSet::standard());
+      run_machine(machine, inputs)
+    },
+    Backend::Wide => {
+      let mut machine = WideMachine::<i64>::new(program);
+      machine.set_budget(BUDGET);
+      for input in inputs {
+        machine.push_input(*input);
+      }
+      let result = machine.run();
+      Outcome {
+        result,
+        output: machine.drain_output(),
+        ip: machine.ip(),
+        relative_base: machine.relative_base(),
+        steps: machine.steps(),
+        memory: nonzero(std::iter::once((0, machine.memory()))),
+      }
+    },
+  }
+}
+
+/// A backend that disagrees with the interpreter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+  pub backend: Backend,
+  pub expected: Outcome,
+  pub actual: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let (expected, actual) = (&self.expected, &self.actual);
+    write!(f, "{:?} differs from the interpreter:", self.backend)?;
+    if expected.result != actual.result {
+      write!(f, " result {:?} instead of {:?};", actual.result, expected.result)?;
+    }
+    if expected.output != actual.output {
+      write!(f, " output {:?} instead of {:?};", actual.output, expected.output)?;
+    }
+    if (expected.ip, expected.relative_base, expected.steps) != (actual.ip, actual.relative_base, actual.steps) {
+      write!(
+        f,
+        " ip {}, rb {} after {} steps instead of ip {}, rb {} after {} steps;",
+        actual.ip, actual.relative_base, actual.steps, expected.ip, expected.relative_base, expected.steps,
+      )?;
+    }
+    let cells = expected.memory.keys().chain(actual.memory.keys()).copied().collect::<std::collections::BTreeSet<_>>();
+    for cell in cells {
+      let (expected, actual) = (expected.memory.get(&cell), actual.memory.get(&cell));
+      if expected != actual {
+        write!(f, " [{}] = {} instead of {};", cell, actual.copied().unwrap_or(0), expected.copied().unwrap_or(0))?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Runs `program` on every backend and compares the outcomes with the one of
+/// the interpreter.
+pub fn check_backends(program: &[i64], inputs: &[i64]) -> Result<(), Box<Mismatch>> {
+  let expected = run_backend(Backend::Interpreter, program, inputs);
+  for backend in Backend::ALL.iter().skip(1) {
+    let actual = run_backend(*backend, program, inputs);
+    if actual != expected {
+      return Err(Box::new(Mismatch { backend: *backend, expected, actual }));
+    }
+  }
+  Ok(())
+}
+
+/// A parameter before the program is laid out.
+#[derive(Debug, Clone, Copy)]
+enum Param {
+  Immediate(i64),
+  Position(usize),
+  Relative(i64),
+  /// The address of an item of the enclosing block, or its end.
+  Label(usize),
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+  Instruction(Opcode, Vec<Param>),
+  /// A body executed `count` times, counting down the counter at `counter`.
+  Loop { count: i64, counter: usize, body: Vec<Item> },
+}
+
+struct Generator<'a, R: Rng> {
+  rng: &'a mut R,
+  /// Start of the data area. `rb` stays within `drift` cells of it, as only
+  /// straight-line code adjusts it.
+  base: usize,
+  drift: usize,
+  counters: usize,
+  /// Instructions left to generate.
+  length: usize,
+}
+
+impl<R: Rng> Generator<'_, R> {
+  fn value(&mut self) -> i64 {
+    match self.rng.gen_range(0..20) {
+      0 => [i64::MAX, i64::MIN, 1 << 32, -(1 << 32)][self.rng.gen_range(0..4)],
+      1..=4 => self.rng.gen_range(-1000..1000),
+      _ => self.rng.gen_range(-3..10),
+    }
+  }
+
+  fn read(&mut self) -> Param {
+    match self.rng.gen_range(0..8) {
+      0..=2 => Param::Immediate(self.value()),
+      3 => Param::Position(self.rng.gen_range(0..self.base + DATA)),
+      4..=5 => Param::Position(self.base + self.rng.gen_range(0..DATA)),
+      _ => Param::Relative(self.rng.gen_range(0..DATA as i64)),
+    }
+  }
+
+  fn write(&mut self) -> Param {
+    if self.rng.gen_bool(0.5) {
+      Param::Position(self.base + self.rng.gen_range(0..DATA))
+    } else {
+      Param::Relative(self.rng.gen_range(0..DATA as i64))
+    }
+  }
+
+  fn instruction(&mut self, labels: usize, index: usize, top: bool) -> Item {
+    let opcode = match self.rng.gen_range(0..30) {
+      0..=3 => Opcode::Add,
+      4..=7 => Opcode::Mul,
+      8..=10 => Opcode::Lt,
+      11..=13 => Opcode::Eq,
+      14..=15 => Opcode::In,
+      16..=19 => Opcode::Out,
+      20..=22 => Opcode::Jnz,
+      23..=25 => Opcode::Jz,
+      26..=28 if top => Opcode::Arb,
+      29 if top => Opcode::Hlt,
+      _ => Opcode::Out,
+    };
+    let params = match opcode {
+      Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => vec![self.read(), self.read(), self.write()],
+      Opcode::In => vec![self.write()],
+      Opcode::Out => vec![self.read()],
+      Opcode::Jnz | Opcode::Jz => vec![self.read(), Param::Label(self.rng.gen_range(index + 1..=labels))],
+      Opcode::Arb => vec![Param::Immediate(self.rng.gen_range(-ARB_STEP..=ARB_STEP))],
+      Opcode::Hlt => vec![],
+    };
+    Item::Instruction(opcode, params)
+  }
+
+  fn block(&mut self, length: usize, top: bool) -> Vec<Item> {
+    let mut items = vec![];
+    while items.len() < length && self.length > 0 {
+      let index = items.len();
+      if top && self.length > 4 && self.rng.gen_range(0..8) == 0 {
+        self.length -= 2;
+        let body = self.rng.gen_range(1..=self.length.min(6));
+        let counter = self.base + self.drift + DATA + self.counters;
+        self.counters += 1;
+        items.push(Item::Loop { count: self.rng.gen_range(1..5), counter, body: self.block(body, false) });
+      } else {
+        self.length -= 1;
+        items.push(self.instruction(length, index, top));
+      }
+    }
+    items
+  }
+}
+
+fn size(item: &Item) -> usize {
+  match item {
+    Item::Instruction(opcode, _) => opcode.size(),
+    // add count, 0, [counter]; body; add [counter], -1, [counter]; jnz [counter], body
+    Item::Loop { body, .. } => 4 + body.iter().map(size).sum::<usize>() + 4 + 3,
+  }
+}
+
+fn emit(items: &[Item], start: usize, program: &mut Vec<i64>) {
+  let mut labels = vec![start];
+  for item in items {
+    labels.push(labels.last().unwrap() + size(item));
+  }
+
+  let push = |program: &mut Vec<i64>, opcode: Opcode, params: &[Param]| {
+    let mut raw = opcode.code();
+    let mut values = vec![];
+    for (index, param) in params.iter().enumerate() {
+      let (mode, value) = match *param {
+        Param::Position(address) => (0, address as i64),
+        Param::Immediate(value) => (1, value),
+        Param::Relative(offset) => (2, offset),
+        Param::Label(label) => (1, labels[label.min(items.len())] as i64),
+      };
+      raw += mode * 10i64.pow(index as u32 + 2);
+      values.push(value);
+    }
+    program.push(raw);
+    program.extend(values);
+  };
+
+  for item in items {
+    match item {
+      Item::Instruction(opcode, params) => push(program, *opcode, params),
+      Item::Loop { count, counter, body } => {
+        push(program, Opcode::Add, &[Param::Immediate(*count), Param::Immediate(0), Param::Position(*counter)]);
+        let head = program.len();
+        emit(body, head, program);
+        push(program, Opcode::Add, &[Param::Position(*counter), Param::Immediate(-1), Param::Position(*counter)]);
+        program.extend([1005, *counter as i64, head as i64]);
+      },
+    }
+  }
+}
+
+/// A random program of `length` instructions that halts, and inputs for it.
+/// Programs may still fault with `VmError::Overflow` or stop waiting for more
+/// input.
+pub fn generate_program<R: Rng>(rng: &mut R, length: usize) -> (Vec<i64>, Vec<i64>) {
+  // Instructions take at most 4 cells and a loop 11 more for 2 instructions,
+  // so the code ends before the lowest address `rb` can reach.
+  let drift = ARB_STEP as usize * length;
+  let base = 10 * length + drift + 16;
+  let mut generator = Generator { rng, base, drift, counters: 0, length };
+  let items = generator.block(length, true);
+
+  let mut program = vec![109, base as i64];
+  emit(&items, program.len(), &mut program);
+  program.push(99);
+
+  let count = generator.rng.gen_range(0..length / 4 + 2);
+  let inputs = (0..count).map(|_| generator.value()).collect();
+  (program, inputs)
+}
+
+/// Shrinks `program` while `fails` holds: removes runs of cells, then makes
+/// the remaining values smaller. `fails` must hold for `program`.
+pub fn minimize<F: FnMut(&[i64]) -> bool>(program: &[i64], mut fails: F) -> Vec<i64> {
+  let mut program = program.to_vec();
+  let mut changed = true;
+
+  while changed {
+    changed = false;
+
+    let mut chunk = program.len() / 2;
+    while chunk > 0 {
+      let mut start = 0;
+      while start + chunk <= program.len() {
+        let mut candidate = program.clone();
+        candidate.drain(start..start + chunk);
+        if fails(&candidate) {
+          program = candidate;
+          changed = true;
+        } else {
+          start += 1;
+        }
+      }
+      // instructions take 1 to 4 cells
+      chunk = if chunk > 8 { chunk / 2 } else { chunk.min(5) - 1 };
+    }
+
+    for index in 0..program.len() {
+      let value = program[index];
+      for smaller in [0, value / 2, value - value.signum()] {
+        if smaller.unsigned_abs() >= value.unsigned_abs() {
+          continue;
+        }
+        let mut candidate = program.clone();
+        candidate[index] = smaller;
+        if fails(&candidate) {
+          program = candidate;
+          changed = true;
+          break;
+        }
+      }
+    }
+  }
+
+  program
+}
+
+/// A program the backends disagree on.
+#[derive(Debug, Clone)]
+pub struct Failure {
+  pub seed: u64,
+  pub inputs: Vec<i64>,
+  pub program: Vec<i64>,
+  /// The minimized program.
+  pub reproducer: Vec<i64>,
+  pub mismatch: Box<Mismatch>,
+}
+
+impl fmt::Display for Failure {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "seed {}: {}", self.seed, self.mismatch)?;
+    writeln!(f, "inputs {:?}", self.inputs)?;
+    write!(f, "reproducer {:?}", self.reproducer)
+  }
+}
+
+/// Checks `runs` programs of `length` instructions, generated from the seeds
+/// `seed..seed + runs`.
+pub fn fuzz(seed: u64, runs: u64, length: usize) -> Result<(), Box<Failure>> {
+  for seed in seed..seed + runs {
+    let (program, inputs) = generate_program(&mut StdRng::seed_from_u64(seed), length);
+    if check_backends(&program, &inputs).is_err() {
+      let reproducer = minimize(&program, |program| check_backends(program, &inputs).is_err());
+      let mismatch = check_backends(&reproducer, &inputs).unwrap_err();
+      return Err(Box::new(Failure { seed, inputs, program, reproducer, mismatch }));
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn generated_programs_halt() {
+    for seed in 0..200 {
+      let (program, inputs) = generate_program(&mut StdRng::seed_from_u64(seed), 40);
+      let outcome = run_backend(Backend::Interpreter, &program, &inputs);
+      match outcome.result {
+        Ok(State::Halted) | Ok(State::NeedsInput) | Err(VmError::Overflow { .. }) => {},
+        result => panic!("seed {}: {:?} for {:?}", seed, result, program),
+      }
+    }
+  }
+
+  #[test]
+  fn backends_agree_on_random_programs() {
+    if let Err(failure) = fuzz(0, 300, 40) {
+      panic!("{}", failure);
+    }
+  }
+
+  #[test]
+  fn minimizes_to_a_small_reproducer() {
+    // "fails" whenever 100 is printed
+    let fails = |program: &[i64]| run_backend(Backend::Interpreter, program, &[]).output.contains(&100);
+    let program = [104, 3, 1101, 7, 8, 30, 1102, 50, 2, 20, 4, 20, 99];
+    assert!(fails(&program));
+
+    assert_eq!(minimize(&program, fails), vec![1102, 50, 2, 20, 4, 20]);
+
+    // the extremes have no absolute value
+    let program = [1101, i64::MIN, 0, 20, 104, 100, 99];
+    assert_eq!(minimize(&program, fails), vec![1101, 0, 0, 0, 104, 100]);
+  }
+
+  #[test]
+  fn mismatches_are_described() {
+    let mut expected = run_backend(Backend::Interpreter, &[104, 1, 99], &[]);
+    expected.memory.insert(7, 3);
+    let mismatch = Mismatch { backend: Backend::Wide, expected, actual: run_backend(Backend::Wide, &[104, 2, 99], &[]) };
+
+    assert_eq!(mismatch.to_string(), "Wide differs from the interpreter: output [2] instead of [1]; [1] = 2 instead of 1; [7] = 0 instead of 3;");
+  }
+}
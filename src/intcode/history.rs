@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+
+use super::{Machine, Memory, State, VmError};
+
+/// What an instruction changed, enough to undo it.
+#[derive(Debug, Clone)]
+struct Change {
+  ip: usize,
+  relative_base: i64,
+  state: State,
+  /// The cell the instruction wrote to and its old value.
+  write: Option<(usize, i64)>,
+  input: Option<i64>,
+}
+
+/// The undo log behind reverse execution in the debugger.
+///
+/// Every `interval` instructions the machine is saved in a snapshot, and the
+/// changes of the instructions since the latest one are kept to undo them
+/// one by one. Going back further restores an earlier snapshot and executes
+/// forward again, with the inputs the program consumed back then. Only the
+/// last `capacity` snapshots are kept, which bounds how far back the machine
+/// can go and the memory the history takes.
+///
+/// Output values are not taken back: the output queue is left alone when
+/// going back, and executing forward again produces them another time.
+#[derive(Debug, Clone)]
+pub(crate) struct History {
+  interval: u64,
+  capacity: usize,
+  /// Snapshots by instruction count, oldest first.
+  checkpoints: VecDeque<(u64, Vec<u8>)>,
+  /// Changes of the instructions since the latest checkpoint.
+  changes: Vec<Change>,
+  /// Inputs consumed since the oldest checkpoint, by instruction count.
+  inputs: VecDeque<(u64, i64)>,
+}
+
+impl History {
+  pub(crate) fn new<M: Memory>(machine: &Machine<M>, interval: u64, capacity: usize) -> History {
+    assert!(interval > 0 && capacity > 0, "history needs a checkpoint interval and capacity");
+    History {
+      interval,
+      capacity,
+      checkpoints: VecDeque::from(vec![(machine.steps(), machine.snapshot())]),
+      changes: vec![],
+      inputs: VecDeque::new(),
+    }
+  }
+
+  /// The earliest instruction count the machine can go back to.
+  pub(crate) fn start(&self) -> u64 {
+    self.checkpoints.front().expect("there's always a checkpoint").0
+  }
+
+  /// Executes an instruction and records what it changes.
+  pub(crate) fn step<M: Memory>(&mut self, machine: &mut Machine<M>) -> Result<State, VmError> {
+    let (ip, relative_base, state, steps) = (machine.ip(), machine.relative_base(), machine.state(), machine.steps());
+    // an instruction whose target can't be found fails to execute as well
+    let write = machine.write_target().map(|target| target.map(|address| (address, machine.peek(address))));
+    let (input, pending) = {
+      let (queue, _) = machine.queues_mut();
+      (queue.front().copied(), queue.len())
+    };
+
+    let result = machine.step()?;
+    if machine.steps() == steps {
+      return Ok(result);
+    }
+
+    let write = write?;
+    let input = input.filter(|_| machine.queues_mut().0.len() < pending);
+    if let Some(value) = input {
+      self.inputs.push_back((steps, value));
+    }
+    self.changes.push(Change { ip, relative_base, state, write, input });
+
+    if machine.steps().is_multiple_of(self.interval) {
+      self.checkpoints.push_back((machine.steps(), machine.snapshot()));
+      self.changes.clear();
+      if self.checkpoints.len() > self.capacity {
+        self.checkpoints.pop_front();
+        let start = self.start();
+        while self.inputs.front().is_some_and(|(steps, _)| *steps < start) {
+          self.inputs.pop_front();
+        }
+      }
+    }
+    Ok(result)
+  }
+
+  /// Takes the machine back to the instruction count `steps`. Returns false
+  /// and leaves the machine alone if that is before the start of the history
+  /// or in the future.
+  pub(crate) fn rewind<M: Memory>(&mut self, machine: &mut Machine<M>, steps: u64) -> Result<bool, VmError> {
+    if steps < self.start() || steps > machine.steps() {
+      return Ok(false);
+    }
+
+    let latest = self.checkpoints.back().expect("there's always a checkpoint").0;
+    if steps >= latest && machine.steps() - latest == self.changes.len() as u64 {
+      while machine.steps() > steps {
+        let change = self.changes.pop().expect("a change per instruction since the checkpoint");
+        if let Some((address, value)) = change.write {
+          machine.poke(address, value);
+        }
+        if let Some(value) = change.input {
+          machine.queues_mut().0.push_front(value);
+          self.inputs.pop_back();
+        }
+        machine.unwind(change.ip, change.relative_base, change.state, machine.steps() - 1);
+      }
+      return Ok(true);
+    }
+
+    let index = self.checkpoints.iter().rposition(|(at, _)| *at <= steps).expect("steps is after the start");
+    self.checkpoints.truncate(index + 1);
+    let start = self.checkpoints[index].0;
+    let mut restored = Machine::<M>::restore(&self.checkpoints[index].1).expect("checkpoints are valid snapshots");
+
+    let consumed = self.inputs.iter().filter(|(at, _)| *at >= start).map(|(_, value)| *value).collect::<Vec<_>>();
+    self.inputs.retain(|(at, _)| *at < start);
+    let (input, output) = machine.queues_mut();
+    restored.queues_mut().0.clear();
+    restored.queues_mut().0.extend(consumed.into_iter().chain(input.drain(..)));
+    let output = std::mem::take(output);
+    restored.queues_mut().1.clear();
+
+    machine.rewind(restored);
+    self.changes.clear();
+    while machine.steps() < steps {
+      let before = machine.steps();
+      self.step(machine)?;
+      if machine.steps() == before {
+        break;
+      }
+    }
+    *machine.queues_mut().1 = output;
+    Ok(true)
+  }
+}
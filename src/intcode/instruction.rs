@@ -0,0 +1,207 @@
+use std::fmt;
+
+use super::VmError;
+
+/// The instructions of the Intcode ISA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Opcode {
+  Add,
+  Mul,
+  In,
+  Out,
+  Jnz,
+  Jz,
+  Lt,
+  Eq,
+  Arb,
+  Hlt,
+}
+
+impl Opcode {
+  pub const ALL: [Opcode; 10] = [
+    Opcode::Add, Opcode::Mul, Opcode::In, Opcode::Out, Opcode::Jnz,
+    Opcode::Jz, Opcode::Lt, Opcode::Eq, Opcode::Arb, Opcode::Hlt,
+  ];
+
+  /// Looks up the opcode in the last two digits of a raw instruction.
+  pub fn from_raw(raw: i64) -> Option<Opcode> {
+    match raw % 100 {
+      1 => Some(Opcode::Add),
+      2 => Some(Opcode::Mul),
+      3 => Some(Opcode::In),
+      4 => Some(Opcode::Out),
+      5 => Some(Opcode::Jnz),
+      6 => Some(Opcode::Jz),
+      7 => Some(Opcode::Lt),
+      8 => Some(Opcode::Eq),
+      9 => Some(Opcode::Arb),
+      99 => Some(Opcode::Hlt),
+      _ => None,
+    }
+  }
+
+  pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+    Opcode::ALL.iter().copied().find(|op| op.mnemonic() == mnemonic)
+  }
+
+  pub fn code(self) -> i64 {
+    match self {
+      Opcode::Add => 1,
+      Opcode::Mul => 2,
+      Opcode::In => 3,
+      Opcode::Out => 4,
+      Opcode::Jnz => 5,
+      Opcode::Jz => 6,
+      Opcode::Lt => 7,
+      Opcode::Eq => 8,
+      Opcode::Arb => 9,
+      Opcode::Hlt => 99,
+    }
+  }
+
+  pub fn mnemonic(self) -> &'static str {
+    match self {
+      Opcode::Add => "add",
+      Opcode::Mul => "mul",
+      Opcode::In => "in",
+      Opcode::Out => "out",
+      Opcode::Jnz => "jnz",
+      Opcode::Jz => "jz",
+      Opcode::Lt => "lt",
+      Opcode::Eq => "eq",
+      Opcode::Arb => "arb",
+      Opcode::Hlt => "hlt",
+    }
+  }
+
+  /// Number of parameters following the opcode.
+  pub fn arity(self) -> usize {
+    match self {
+      Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => 3,
+      Opcode::Jnz | Opcode::Jz => 2,
+      Opcode::In | Opcode::Out | Opcode::Arb => 1,
+      Opcode::Hlt => 0,
+    }
+  }
+
+  /// Number of cells the instruction occupies, including the opcode.
+  pub fn size(self) -> usize {
+    self.arity() + 1
+  }
+
+  /// Index of the parameter the instruction writes to, if any.
+  pub fn write_param(self) -> Option<usize> {
+    match self {
+      Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => Some(2),
+      Opcode::In => Some(0),
+      _ => None,
+    }
+  }
+}
+
+/// How a parameter is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+  Position,
+  Immediate,
+  Relative,
+}
+
+impl Mode {
+  /// Extracts the mode of parameter `index` from a raw instruction. Returns
+  /// the offending digit if it is not a valid mode.
+  pub fn from_raw(raw: i64, index: usize) -> Result<Mode, i64> {
+    match raw / 10i64.pow(index as u32 + 2) % 10 {
+      0 => Ok(Mode::Position),
+      1 => Ok(Mode::Immediate),
+      2 => Ok(Mode::Relative),
+      digit => Err(digit),
+    }
+  }
+
+  pub fn digit(self) -> i64 {
+    match self {
+      Mode::Position => 0,
+      Mode::Immediate => 1,
+      Mode::Relative => 2,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Param {
+  pub mode: Mode,
+  pub value: i64,
+}
+
+impl fmt::Display for Param {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.mode {
+      Mode::Position => write!(f, "[{}]", self.value),
+      Mode::Immediate => write!(f, "{}", self.value),
+      Mode::Relative if self.value < 0 => write!(f, "[rb{}]", self.value),
+      Mode::Relative => write!(f, "[rb+{}]", self.value),
+    }
+  }
+}
+
+/// A decoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+  pub address: usize,
+  pub raw: i64,
+  pub opcode: Opcode,
+  pub params: Vec<Param>,
+}
+
+impl Instruction {
+  /// Decodes the instruction at `address`, reading cells through `read`.
+  pub fn decode<F: Fn(usize) -> i64>(read: F, address: usize) -> Result<Instruction, VmError> {
+    let raw = read(address);
+    let opcode = Opcode::from_raw(raw)
+      .ok_or(VmError::InvalidOpcode { ip: address, opcode: raw })?;
+
+    let mut params = Vec::with_capacity(opcode.arity());
+    for index in 0..opcode.arity() {
+      let mode = Mode::from_raw(raw, index)
+        .map_err(|mode| VmError::InvalidParamMode { ip: address, opcode: raw, mode })?;
+      if mode == Mode::Immediate && opcode.write_param() == Some(index) {
+        return Err(VmError::WriteInImmediateMode { ip: address, opcode: raw });
+      }
+      params.push(Param { mode, value: read(address + index + 1) });
+    }
+
+    Ok(Instruction { address, raw, opcode, params })
+  }
+
+  /// The raw opcode this instruction would be assembled to.
+  pub fn encode(&self) -> i64 {
+    self.params
+      .iter()
+      .enumerate()
+      .fold(self.opcode.code(), |raw, (index, param)| {
+        raw + param.mode.digit() * 10i64.pow(index as u32 + 2)
+      })
+  }
+
+  /// Whether the raw opcode carries no mode digits beyond the ones the
+  /// parameters need, so it can be reproduced from its textual form.
+  pub fn is_canonical(&self) -> bool {
+    self.raw == self.encode()
+  }
+
+  pub fn size(&self) -> usize {
+    self.opcode.size()
+  }
+}
+
+impl fmt::Display for Instruction {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.opcode.mnemonic())?;
+    for (index, param) in self.params.iter().enumerate() {
+      let separator = if index == 0 { " " } else { ", " };
+      write!(f, "{}{}", separator, param)?;
+    }
+    Ok(())
+  }
+}
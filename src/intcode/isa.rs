@@ -0,0 +1,388 @@
+//! Instruction sets for experiments with the Intcode ISA.
+//!
+//! A `Machine` executes the standard instructions natively. Once it is given
+//! an `InstructionSet` it looks up every instruction there instead, so
+//! instructions can be added or replaced without touching the interpreter:
+//!
+//! ```
+//! # use y2019::intcode::{Cpu, Definition, Flow, InstructionSet, Machine, VmError};
+//! // 10: out the instruction pointer and the relative base
+//! fn print_state(cpu: &mut Cpu) -> Result<Flow, VmError> {
+//!   cpu.output(cpu.ip() as i64);
+//!   cpu.output(cpu.relative_base());
+//!   Ok(Flow::Next)
+//! }
+//!
+//! let mut isa = InstructionSet::standard();
+//! isa.register(Definition { code: 10, mnemonic: "pst", arity: 0, write_param: None, execute: print_state });
+//!
+//! let mut machine = Machine::new(&[109, 7, 10, 99]);
+//! machine.set_instruction_set(isa);
+//! machine.run().unwrap();
+//! assert_eq!(machine.drain_output(), vec![2, 7]);
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use super::{Memory, Opcode, VmError};
+
+/// The most parameters an instruction can have: the modes of more don't fit
+/// into the digits of a raw instruction.
+pub const MAX_ARITY: usize = 17;
+
+/// Executes an instruction whose operands were already fetched.
+pub type Execute = fn(&mut Cpu) -> Result<Flow, VmError>;
+
+/// Where execution continues after an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+  /// With the next instruction.
+  Next,
+  Jump(i64),
+  /// Waits for input: the instruction is executed again once there is some.
+  Wait,
+  Halt,
+}
+
+/// An instruction of an `InstructionSet`.
+#[derive(Clone, Copy)]
+pub struct Definition {
+  /// The opcode in the last two digits of a raw instruction.
+  pub code: i64,
+  pub mnemonic: &'static str,
+  pub arity: usize,
+  /// The parameter the instruction writes to. Its operand is the address
+  /// instead of the value, and it can't be in immediate mode.
+  pub write_param: Option<usize>,
+  pub execute: Execute,
+}
+
+impl fmt::Debug for Definition {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Definition({} {}/{}, write {:?})", self.code, self.mnemonic, self.arity, self.write_param)
+  }
+}
+
+impl Definition {
+  /// The definition of a standard instruction.
+  pub fn standard(opcode: Opcode) -> Definition {
+    let execute: Execute = match opcode {
+      Opcode::Add => add,
+      Opcode::Mul => mul,
+      Opcode::In => input,
+      Opcode::Out => output,
+      Opcode::Jnz => jnz,
+      Opcode::Jz => jz,
+      Opcode::Lt => lt,
+      Opcode::Eq => eq,
+      Opcode::Arb => arb,
+      Opcode::Hlt => hlt,
+    };
+    Definition {
+      code: opcode.code(),
+      mnemonic: opcode.mnemonic(),
+      arity: opcode.arity(),
+      write_param: opcode.write_param(),
+      execute,
+    }
+  }
+}
+
+/// A registry of instructions by opcode.
+#[derive(Debug, Clone)]
+pub struct InstructionSet {
+  definitions: BTreeMap<i64, Definition>,
+}
+
+impl Default for InstructionSet {
+  fn default() -> InstructionSet {
+    InstructionSet::standard()
+  }
+}
+
+impl InstructionSet {
+  pub fn empty() -> InstructionSet {
+    InstructionSet { definitions: BTreeMap::new() }
+  }
+
+  /// The ten instructions of the Intcode ISA.
+  pub fn standard() -> InstructionSet {
+    let mut isa = InstructionSet::empty();
+    for opcode in Opcode::ALL.iter() {
+      isa.register(Definition::standard(*opcode));
+    }
+    isa
+  }
+
+  /// Adds an instruction, returning the one it replaces. Opcodes are the last
+  /// two digits of an instruction, so `code` must be in `0..100`. The arity
+  /// is at most `MAX_ARITY` and the write parameter one of the parameters.
+  pub fn register(&mut self, definition: Definition) -> Option<Definition> {
+    assert!((0..100).contains(&definition.code), "opcode {} out of range", definition.code);
+    assert!(definition.arity <= MAX_ARITY, "arity {} of {} too large", definition.arity, definition.mnemonic);
+    assert!(
+      definition.write_param.is_none_or(|index| index < definition.arity),
+      "write parameter {:?} of {} out of range",
+      definition.write_param,
+      definition.mnemonic
+    );
+    self.definitions.insert(definition.code, definition)
+  }
+
+  pub fn remove(&mut self, code: i64) -> Option<Definition> {
+    self.definitions.remove(&code)
+  }
+
+  /// The instruction for the last two digits of `raw`.
+  pub fn lookup(&self, raw: i64) -> Option<&Definition> {
+    self.definitions.get(&(raw % 100))
+  }
+
+  pub fn definitions(&self) -> impl Iterator<Item = &Definition> {
+    self.definitions.values()
+  }
+}
+
+/// What an instruction can see of and do to the machine executing it.
+pub struct Cpu<'a> {
+  pub(crate) ip: usize,
+  pub(crate) raw: i64,
+  pub(crate) operands: &'a [i64],
+  pub(crate) write_param: Option<usize>,
+  pub(crate) memory: &'a mut dyn Memory,
+  pub(crate) relative_base: &'a mut i64,
+  pub(crate) input: &'a mut VecDeque<i64>,
+  pub(crate) output: &'a mut VecDeque<i64>,
+}
+
+impl Cpu<'_> {
+  pub fn ip(&self) -> usize {
+    self.ip
+  }
+
+  pub fn raw(&self) -> i64 {
+    self.raw
+  }
+
+  /// The value of parameter `index`, or its address if it is the write
+  /// parameter.
+  pub fn operand(&self, index: usize) -> i64 {
+    self.operands[index]
+  }
+
+  /// Stores `value` at the address of the write parameter.
+  pub fn write(&mut self, value: i64) {
+    let index = self.write_param.expect("the instruction has no write parameter");
+    self.memory.set(self.operands[index] as usize, value);
+  }
+
+  pub fn read(&self, address: usize) -> i64 {
+    self.memory.get(address)
+  }
+
+  pub fn relative_base(&self) -> i64 {
+    *self.relative_base
+  }
+
+  pub fn adjust_relative_base(&mut self, offset: i64) -> Result<(), VmError> {
+    *self.relative_base = self.relative_base.checked_add(offset).ok_or(self.overflow())?;
+    Ok(())
+  }
+
+  /// The next input value, if there is one.
+  pub fn input(&mut self) -> Option<i64> {
+    self.input.pop_front()
+  }
+
+  /// Queues an output value. The machine stops with `State::HasOutput`
+  /// after the instruction.
+  pub fn output(&mut self, value: i64) {
+    self.output.push_back(value);
+  }
+
+  pub fn overflow(&self) -> VmError {
+    VmError::Overflow { ip: self.ip, opcode: self.raw }
+  }
+
+  /// A fault for instructions that stop the program on purpose.
+  pub fn trap(&self) -> VmError {
+    VmError::Trap { ip: self.ip, opcode: self.raw }
+  }
+}
+
+fn add(cpu: &mut Cpu) -> Result<Flow, VmError> {
+  let value = cpu.operand(0).checked_add(cpu.operand(1)).ok_or(cpu.overflow())?;
+  cpu.write(value);
+  Ok(Flow::Next)
+}
+
+fn mul(cpu: &mut Cpu) -> Result<Flow, VmError> {
+  let value = cpu.operand(0).checked_mul(cpu.operand(1)).ok_or(cpu.overflow())?;
+  cpu.write(value);
+  Ok(Flow::Next)
+}
+
+fn input(cpu: &mut Cpu) -> Result<Flow, VmError> {
+  match cpu.input() {
+    Some(value) => {
+      cpu.write(value);
+      Ok(Flow::Next)
+    },
+    None => Ok(Flow::Wait),
+  }
+}
+
+fn output(cpu: &mut Cpu) -> Result<Flow, VmError> {
+  cpu.output(cpu.operand(0));
+  Ok(Flow::Next)
+}
+
+fn jnz(cpu: &mut Cpu) -> Result<Flow, VmError> {
+  Ok(if cpu.operand(0) != 0 { Flow::Jump(cpu.operand(1)) } else { Flow::Next })
+}
+
+fn jz(cpu: &mut Cpu) -> Result<Flow, VmError> {
+  Ok(if cpu.operand(0) == 0 { Flow::Jump(cpu.operand(1)) } else { Flow::Next })
+}
+
+fn lt(cpu: &mut Cpu) -> Result<Flow, VmError> {
+  cpu.write((cpu.operand(0) < cpu.operand(1)) as i64);
+  Ok(Flow::Next)
+}
+
+fn eq(cpu: &mut Cpu) -> Result<Flow, VmError> {
+  cpu.write((cpu.operand(0) == cpu.operand(1)) as i64);
+  Ok(Flow::Next)
+}
+
+fn arb(cpu: &mut Cpu) -> Result<Flow, VmError> {
+  cpu.adjust_relative_base(cpu.operand(0))?;
+  Ok(Flow::Next)
+}
+
+fn hlt(_: &mut Cpu) -> Result<Flow, VmError> {
+  Ok(Flow::Halt)
+}
+
+#[cfg(test)]
+mod test {
+  use std::sync::{Arc, Mutex};
+
+  use super::*;
+  use crate::intcode::{parse_instructions, Io, Machine, State, TraceEntry};
+
+  #[test]
+  fn standard_instruction_set_behaves_like_the_machine() {
+    let programs = [
+      (include_str!("../day5/data/input-1.txt"), 5),
+      (include_str!("../day9/data/input-1.txt"), 1),
+    ];
+
+    for (program, input) in programs.iter() {
+      let program = parse_instructions(program.trim());
+      let mut native = Machine::new(&program);
+      let mut defined = Machine::new(&program);
+      defined.set_instruction_set(InstructionSet::standard());
+      defined.compile();
+
+      for machine in [&mut native, &mut defined].iter_mut() {
+        machine.push_input(*input);
+        assert_eq!(machine.run(), Ok(State::Halted));
+      }
+      assert!(!defined.is_compiled());
+      assert_eq!(defined.drain_output(), native.drain_output());
+      assert_eq!(defined.steps(), native.steps());
+    }
+  }
+
+  // 10: out ip, rb
+  fn print_state(cpu: &mut Cpu) -> Result<Flow, VmError> {
+    cpu.output(cpu.ip() as i64);
+    cpu.output(cpu.relative_base());
+    Ok(Flow::Next)
+  }
+
+  // 11: trap if the parameter is not 0
+  fn trap(cpu: &mut Cpu) -> Result<Flow, VmError> {
+    if cpu.operand(0) != 0 {
+      return Err(cpu.trap());
+    }
+    Ok(Flow::Next)
+  }
+
+  // 12: square the first parameter into the second
+  fn square(cpu: &mut Cpu) -> Result<Flow, VmError> {
+    let value = cpu.operand(0).checked_mul(cpu.operand(0)).ok_or(cpu.overflow())?;
+    cpu.write(value);
+    Ok(Flow::Next)
+  }
+
+  #[test]
+  fn instructions_can_be_added() {
+    let mut isa = InstructionSet::standard();
+    isa.register(Definition { code: 10, mnemonic: "pst", arity: 0, write_param: None, execute: print_state });
+    isa.register(Definition { code: 11, mnemonic: "trap", arity: 1, write_param: None, execute: trap });
+    assert!(isa.register(Definition { code: 12, mnemonic: "sq", arity: 2, write_param: Some(1), execute: square }).is_none());
+    assert_eq!(isa.lookup(2112).map(|definition| definition.mnemonic), Some("sq"));
+
+    // arb 3; sq 7, [rb+0]; out [3]; pst; trap [3]; hlt
+    let program = [109, 3, 2112, 7, 0, 4, 3, 10, 11, 3, 99];
+    let mut machine = Machine::new(&program);
+    machine.set_instruction_set(isa.clone());
+    assert_eq!(machine.run_until_io(), Ok(State::HasOutput));
+    assert_eq!(machine.run(), Err(VmError::Trap { ip: 8, opcode: 11 }));
+    assert_eq!(machine.drain_output(), vec![49, 7, 3]);
+
+    isa.remove(11);
+    let mut machine = Machine::new(&program);
+    machine.set_instruction_set(isa);
+    assert_eq!(machine.run(), Err(VmError::InvalidOpcode { ip: 8, opcode: 11 }));
+    assert_eq!(Machine::new(&program).run(), Err(VmError::InvalidOpcode { ip: 2, opcode: 2112 }));
+  }
+
+  // 1: store the second parameter in the first
+  fn store_second(cpu: &mut Cpu) -> Result<Flow, VmError> {
+    cpu.write(cpu.operand(1));
+    Ok(Flow::Next)
+  }
+
+  #[test]
+  fn defined_instructions_are_traced() {
+    let mut isa = InstructionSet::standard();
+    isa.register(Definition { code: 12, mnemonic: "sq", arity: 2, write_param: Some(1), execute: square });
+    isa.register(Definition { code: 1, mnemonic: "st", arity: 2, write_param: Some(0), execute: store_second });
+
+    // sq 7, [9]; st [11], 42; out [9]; hlt
+    let mut machine = Machine::new(&[112, 7, 9, 1001, 11, 42, 4, 9, 99, 0, 0, 0]);
+    machine.set_instruction_set(isa);
+    let trace = Arc::new(Mutex::new(Vec::<TraceEntry>::new()));
+    machine.set_tracer(Box::new(trace.clone()));
+
+    assert_eq!(machine.write_target(), Ok(Some(9)));
+    machine.step().unwrap();
+    assert_eq!(machine.write_target(), Ok(Some(11)));
+    assert_eq!(machine.run(), Ok(State::Halted));
+
+    let trace = trace.lock().unwrap();
+    let summary = trace.iter().map(|entry| (entry.step, entry.ip, entry.write, entry.io)).collect::<Vec<_>>();
+    assert_eq!(summary, vec![
+      (0, 0, Some((9, 49)), None),
+      (1, 3, Some((11, 42)), None),
+      (2, 6, None, Some(Io::Output(49))),
+      (3, 8, None, None),
+    ]);
+  }
+
+  #[test]
+  #[should_panic(expected = "write parameter Some(3) of sq out of range")]
+  fn write_parameters_are_parameters() {
+    InstructionSet::empty().register(Definition { code: 12, mnemonic: "sq", arity: 1, write_param: Some(3), execute: square });
+  }
+
+  #[test]
+  #[should_panic(expected = "arity 18 of sq too large")]
+  fn modes_fit_into_instructions() {
+    InstructionSet::empty().register(Definition { code: 12, mnemonic: "sq", arity: MAX_ARITY + 1, write_param: None, execute: square });
+  }
+}
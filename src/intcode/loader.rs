@@ -0,0 +1,188 @@
+//! Loading Intcode programs.
+//!
+//! Text programs are integers separated by commas. Whitespace around the
+//! values, including CRLF line breaks, is ignored, as are a trailing comma
+//! and everything from a `#` to the end of its line:
+//!
+//! ```
+//! # use y2019::intcode::load;
+//! assert_eq!(load("1101, 2, 3, 5,\r\n99, # halts\r\n0\r\n"), Ok(vec![1101, 2, 3, 5, 99, 0]));
+//! assert_eq!(load("1, 2, x").unwrap_err().to_string(), "invalid value 'x' at byte 6");
+//! ```
+//!
+//! Programs can also be stored in a compact binary format:
+//!
+//! ```text
+//! program := "ICPROG01" count value{count}
+//! ```
+//!
+//! `count` is an unsigned LEB128 varint and the values are zigzag encoded
+//! signed varints, as in the trace format.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::varint::{expect_signed, expect_unsigned, write_signed, write_unsigned};
+
+const MAGIC: &[u8; 8] = b"ICPROG01";
+
+/// Why a program couldn't be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+  /// A token of a text program that isn't an integer, and its byte offset.
+  InvalidValue { offset: usize, token: String },
+  /// A binary program that is broken at byte `offset`.
+  InvalidBinary { offset: usize, what: String },
+  /// A file that can't be read.
+  Io { path: String, what: String },
+  /// A program with fewer values than its puzzle patches or reads.
+  TooShort { length: usize, needed: usize },
+}
+
+impl fmt::Display for LoadError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LoadError::InvalidValue { offset, token } => write!(f, "invalid value '{}' at byte {}", token, offset),
+      LoadError::InvalidBinary { offset, what } => write!(f, "invalid binary program at byte {}: {}", offset, what),
+      LoadError::Io { path, what } => write!(f, "can't read {}: {}", path, what),
+      LoadError::TooShort { length, needed } => write!(f, "program of {} values is too short, needs {}", length, needed),
+    }
+  }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Parses a text program, see the module documentation.
+pub fn load(text: &str) -> Result<Vec<i64>, LoadError> {
+  // blank out comments byte by byte to keep the offsets
+  let mut bytes = text.as_bytes().to_vec();
+  let mut comment = false;
+  for byte in &mut bytes {
+    match *byte {
+      b'\n' => comment = false,
+      b'#' => comment = true,
+      _ => (),
+    }
+    if comment {
+      *byte = b' ';
+    }
+  }
+  let text = String::from_utf8(bytes).expect("only whole characters are blanked out");
+
+  let mut program = vec![];
+  let mut offset = 0;
+  let mut tokens = text.split(',').peekable();
+  while let Some(token) = tokens.next() {
+    let value = token.trim();
+    let start = offset + token.len() - token.trim_start().len();
+    offset += token.len() + 1;
+    if value.is_empty() && tokens.peek().is_none() {
+      break;
+    }
+    match value.parse::<i64>() {
+      Ok(value) => program.push(value),
+      Err(_) => return Err(LoadError::InvalidValue { offset: start, token: value.to_string() }),
+    }
+  }
+  Ok(program)
+}
+
+/// Loads a text or binary program from a file.
+pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Vec<i64>, LoadError> {
+  let path = path.as_ref();
+  let io_error = |what: String| LoadError::Io { path: path.display().to_string(), what };
+
+  let bytes = fs::read(path).map_err(|err| io_error(err.to_string()))?;
+  if bytes.starts_with(MAGIC) {
+    return deserialize(&bytes);
+  }
+  match String::from_utf8(bytes) {
+    Ok(text) => load(&text),
+    Err(err) => Err(io_error(format!("invalid UTF-8 at byte {}", err.utf8_error().valid_up_to()))),
+  }
+}
+
+/// Encodes a program in the binary format.
+pub fn serialize(program: &[i64]) -> Vec<u8> {
+  let mut bytes = MAGIC.to_vec();
+  write_unsigned(&mut bytes, program.len() as u64).expect("writing to a Vec can't fail");
+  for value in program {
+    write_signed(&mut bytes, *value).expect("writing to a Vec can't fail");
+  }
+  bytes
+}
+
+/// Decodes a program in the binary format.
+pub fn deserialize(bytes: &[u8]) -> Result<Vec<i64>, LoadError> {
+  let mut input = bytes;
+  let broken = |input: &[u8], what: &str| LoadError::InvalidBinary { offset: bytes.len() - input.len(), what: what.to_string() };
+  let io_error = |input: &[u8], err: io::Error| broken(input, &err.to_string());
+
+  if !input.starts_with(MAGIC) {
+    return Err(broken(input, "not an Intcode program"));
+  }
+  input = &input[MAGIC.len()..];
+
+  let count = expect_unsigned(&mut input).map_err(|err| io_error(input, err))?;
+  // every value takes at least a byte
+  if count > input.len() as u64 {
+    return Err(broken(input, &format!("{} values don't fit into {} bytes", count, input.len())));
+  }
+  let mut program = Vec::with_capacity(count as usize);
+  for _ in 0..count {
+    program.push(expect_signed(&mut input).map_err(|err| io_error(input, err))?);
+  }
+  if !input.is_empty() {
+    return Err(broken(input, "trailing bytes"));
+  }
+  Ok(program)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn reports_the_offset_of_bad_values() {
+    assert_eq!(load(""), Ok(vec![]));
+    assert_eq!(load(" 1 ,\t-2\n,3,\n\n"), Ok(vec![1, -2, 3]));
+    assert_eq!(load("# a comment\n1,2 # and another, 3\n,4"), Ok(vec![1, 2, 4]));
+    assert_eq!(load("1,,2"), Err(LoadError::InvalidValue { offset: 2, token: "".to_string() }));
+    assert_eq!(load("1,\r\n  2 3,4"), Err(LoadError::InvalidValue { offset: 6, token: "2 3".to_string() }));
+    assert_eq!(
+      load("# ünïcödé\n1,99999999999999999999"),
+      Err(LoadError::InvalidValue { offset: 16, token: "99999999999999999999".to_string() })
+    );
+  }
+
+  #[test]
+  fn binary_programs_round_trip() {
+    let program = vec![1, -1, 0, i64::MAX, i64::MIN, 99];
+    let bytes = serialize(&program);
+    assert_eq!(deserialize(&bytes), Ok(program));
+
+    assert_eq!(
+      deserialize(&bytes[..bytes.len() - 1]),
+      Err(LoadError::InvalidBinary { offset: bytes.len() - 1, what: "truncated record".to_string() })
+    );
+    assert!(deserialize(b"ICTRACE1").is_err());
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(deserialize(&trailing).unwrap_err().to_string(), format!("invalid binary program at byte {}: trailing bytes", bytes.len()));
+  }
+
+  #[test]
+  fn loads_files_of_either_format() {
+    let text = include_str!("../day9/data/input-1.txt");
+    let program = load(text).unwrap();
+    let path = std::env::temp_dir().join(format!("intcode-loader-{}.bin", std::process::id()));
+    fs::write(&path, serialize(&program)).unwrap();
+    assert_eq!(load_file(&path), Ok(program.clone()));
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(load_file("src/day9/data/input-1.txt"), Ok(program));
+    assert!(matches!(load_file("no/such/program"), Err(LoadError::Io { .. })));
+  }
+}
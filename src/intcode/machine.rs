@@ -0,0 +1,960 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use super::budget::Meter;
+use super::compiler::{Compiled, Op, Operand};
+use super::varint::{expect_signed, expect_unsigned, write_signed, write_unsigned};
+use super::{Budget, Cpu, Flow, Instruction, InstructionSet, Io, IoDevice, Memory, Mode, Opcode, PagedMemory, TraceEntry, Tracer, VmError};
+
+/// Number of cells a machine may address unless configured otherwise.
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 24;
+
+/// The largest memory limit a machine may have, so that even dense memory
+/// can hold every cell below it.
+pub const MAX_MEMORY_LIMIT: usize = 1 << 28;
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"ICSNAP01";
+
+/// Runs of zeros at least this long split a memory segment in a snapshot.
+const SNAPSHOT_GAP: usize = 8;
+
+/// The execution state a `Machine` is in after the last instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+  /// The machine can continue to execute instructions.
+  Running,
+  /// The machine is blocked on an `in` instruction and the input queue is empty.
+  NeedsInput,
+  /// The last instruction produced an output value.
+  HasOutput,
+  /// The machine executed opcode 99.
+  Halted,
+}
+
+/// Holds the optional tracer of a machine. Cloning a machine does not clone
+/// its tracer.
+#[derive(Default)]
+struct TracerHook(Option<Box<dyn Tracer + Send>>);
+
+impl Clone for TracerHook {
+  fn clone(&self) -> TracerHook {
+    TracerHook(None)
+  }
+}
+
+impl fmt::Debug for TracerHook {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", if self.0.is_some() { "Some(..)" } else { "None" })
+  }
+}
+
+/// A pausable Intcode machine.
+///
+/// Unlike the `isa_interpreter*` functions the machine keeps its instruction
+/// pointer, relative base and memory between calls, so a driver can feed it
+/// input and collect its output in the same thread.
+///
+/// Memory is provided by a `Memory` backend and grows on demand. Accessing a
+/// cell at or beyond the memory limit is reported as an error.
+#[derive(Debug, Clone)]
+pub struct Machine<M: Memory = PagedMemory> {
+  memory: M,
+  limit: usize,
+  ip: usize,
+  relative_base: i64,
+  state: State,
+  input: VecDeque<i64>,
+  output: VecDeque<i64>,
+  steps: u64,
+  tracer: TracerHook,
+  code: Option<Arc<Compiled>>,
+  isa: Option<Arc<InstructionSet>>,
+  budget: Budget,
+}
+
+impl Machine {
+  pub fn new(program: &[i64]) -> Machine {
+    Machine::with_memory(PagedMemory::load(program))
+  }
+}
+
+impl<M: Memory + Clone> Machine<M> {
+  /// Creates an independent copy of the machine, including its queues. With
+  /// `PagedMemory` both machines share their memory until either writes to
+  /// it. The tracer is not copied.
+  pub fn fork(&self) -> Machine<M> {
+    self.clone()
+  }
+}
+
+/// Splits `cells` into `(start, end)` ranges, leaving out long runs of zeros.
+fn segments(cells: &[i64]) -> Vec<(usize, usize)> {
+  let mut segments: Vec<(usize, usize)> = vec![];
+  for (index, _) in cells.iter().enumerate().filter(|(_, cell)| **cell != 0) {
+    match segments.last_mut() {
+      Some((_, end)) if index - *end < SNAPSHOT_GAP => *end = index + 1,
+      _ => segments.push((index, index + 1)),
+    }
+  }
+  segments
+}
+
+fn invalid(what: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+impl<M: Memory> Machine<M> {
+  pub fn with_memory(memory: M) -> Machine<M> {
+    Machine {
+      memory,
+      limit: DEFAULT_MEMORY_LIMIT,
+      ip: 0,
+      relative_base: 0,
+      state: State::Running,
+      input: VecDeque::new(),
+      output: VecDeque::new(),
+      steps: 0,
+      tracer: TracerHook::default(),
+      code: None,
+      isa: None,
+      budget: Budget::unlimited(),
+    }
+  }
+
+  pub fn ip(&self) -> usize {
+    self.ip
+  }
+
+  pub fn relative_base(&self) -> i64 {
+    self.relative_base
+  }
+
+  pub fn state(&self) -> State {
+    self.state
+  }
+
+  /// Number of instructions executed so far.
+  pub fn steps(&self) -> u64 {
+    self.steps
+  }
+
+  /// Reports every instruction executed from now on to `tracer`.
+  pub fn set_tracer(&mut self, tracer: Box<dyn Tracer + Send>) {
+    self.tracer = TracerHook(Some(tracer));
+  }
+
+  pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer + Send>> {
+    self.tracer.0.take()
+  }
+
+  /// Limits every following call to `run_until_io`, `run` and `run_with`.
+  /// The budget is not part of a snapshot.
+  pub fn set_budget(&mut self, budget: Budget) {
+    self.budget = budget;
+  }
+
+  pub fn budget(&self) -> Budget {
+    self.budget
+  }
+
+  pub fn memory(&self) -> &M {
+    &self.memory
+  }
+
+  pub fn memory_limit(&self) -> usize {
+    self.limit
+  }
+
+  /// Sets the number of cells the program may address, at most
+  /// `MAX_MEMORY_LIMIT`.
+  pub fn set_memory_limit(&mut self, limit: usize) {
+    assert!(limit <= MAX_MEMORY_LIMIT, "memory limit {} too large", limit);
+    self.limit = limit;
+  }
+
+  pub fn peek(&self, address: usize) -> i64 {
+    self.memory.get(address)
+  }
+
+  pub fn poke(&mut self, address: usize, value: i64) {
+    self.store(address, value);
+  }
+
+  /// Decodes the code reachable from address 0 ahead of time, which makes
+  /// executing it faster. Forks share the compiled code. Instructions that
+  /// are changed by a write are dropped from the machine's copy of the code
+  /// and interpreted from then on.
+  /// Does nothing for machines with an instruction set.
+  pub fn compile(&mut self) {
+    if self.isa.is_some() {
+      return;
+    }
+    let mut program = vec![];
+    for (address, cells) in self.memory.chunks() {
+      if address != program.len() {
+        break;
+      }
+      program.extend_from_slice(cells);
+    }
+    while program.last() == Some(&0) {
+      program.pop();
+    }
+
+    self.code = Some(Arc::new(Compiled::new(&program)));
+  }
+
+  /// Whether the machine executes compiled code, see `compile`.
+  pub fn is_compiled(&self) -> bool {
+    self.code.is_some()
+  }
+
+  /// Executes every instruction as defined by `isa` from now on instead of
+  /// natively. Compiled code is dropped; instructions are still traced.
+  pub fn set_instruction_set(&mut self, isa: InstructionSet) {
+    self.isa = Some(Arc::new(isa));
+    self.code = None;
+  }
+
+  pub fn instruction_set(&self) -> Option<&InstructionSet> {
+    self.isa.as_deref()
+  }
+
+  #[inline]
+  fn store(&mut self, address: usize, value: i64) {
+    if let Some(code) = &mut self.code {
+      if code.covers(address) && self.memory.get(address) != value {
+        Arc::make_mut(code).invalidate(address);
+      }
+    }
+    self.memory.set(address, value);
+  }
+
+  pub fn push_input(&mut self, value: i64) {
+    self.input.push_back(value);
+  }
+
+  pub fn extend_input<I: IntoIterator<Item = i64>>(&mut self, values: I) {
+    self.input.extend(values);
+  }
+
+  pub fn pop_output(&mut self) -> Option<i64> {
+    self.output.pop_front()
+  }
+
+  pub fn drain_output(&mut self) -> Vec<i64> {
+    self.output.drain(..).collect()
+  }
+
+  pub(crate) fn queues_mut(&mut self) -> (&mut VecDeque<i64>, &mut VecDeque<i64>) {
+    (&mut self.input, &mut self.output)
+  }
+
+  /// Puts the registers back to what they were before an instruction.
+  pub(crate) fn unwind(&mut self, ip: usize, relative_base: i64, state: State, steps: u64) {
+    self.ip = ip;
+    self.relative_base = relative_base;
+    self.state = state;
+    self.steps = steps;
+  }
+
+  /// Takes over memory, registers and queues of `other`, but keeps the
+  /// tracer, budget and instruction set. Compiled code is dropped as it may
+  /// not match the new memory.
+  pub(crate) fn rewind(&mut self, other: Machine<M>) {
+    self.memory = other.memory;
+    self.limit = other.limit;
+    self.unwind(other.ip, other.relative_base, other.state, other.steps);
+    self.input = other.input;
+    self.output = other.output;
+    self.code = None;
+  }
+
+  /// Decodes the instruction at the instruction pointer.
+  pub fn current_instruction(&self) -> Result<Instruction, VmError> {
+    Instruction::decode(|address| self.memory.get(address), self.ip)
+  }
+
+  /// The address the instruction at the instruction pointer writes to, if
+  /// it writes to memory at all.
+  /// With an instruction set, the write parameter of its definition counts.
+  pub fn write_target(&self) -> Result<Option<usize>, VmError> {
+    let (raw, write_param) = match &self.isa {
+      Some(isa) => {
+        let raw = self.memory.get(self.ip);
+        let definition = isa.lookup(raw).ok_or(VmError::InvalidOpcode { ip: self.ip, opcode: raw })?;
+        (raw, definition.write_param)
+      },
+      None => {
+        let instruction = self.current_instruction()?;
+        (instruction.raw, instruction.opcode.write_param())
+      },
+    };
+    match write_param {
+      Some(index) => Ok(Some(self.param_address(raw, index)?)),
+      None => Ok(None),
+    }
+  }
+
+  fn address(&self, op: i64, address: i64) -> Result<usize, VmError> {
+    if address < 0 {
+      return Err(VmError::NegativeAddress { ip: self.ip, opcode: op, address });
+    }
+    if address as u64 >= self.limit as u64 {
+      return Err(VmError::MemoryLimitExceeded { ip: self.ip, opcode: op, address });
+    }
+    Ok(address as usize)
+  }
+
+  fn param_address(&self, op: i64, index: usize) -> Result<usize, VmError> {
+    let param_address = self.ip + index + 1;
+    match Mode::from_raw(op, index) {
+      Ok(Mode::Position) => self.address(op, self.memory.get(param_address)),
+      Ok(Mode::Immediate) => Ok(param_address),
+      Ok(Mode::Relative) => self.relative(op, self.memory.get(param_address)),
+      Err(mode) => Err(VmError::InvalidParamMode { ip: self.ip, opcode: op, mode }),
+    }
+  }
+
+  fn relative(&self, op: i64, offset: i64) -> Result<usize, VmError> {
+    self.address(op, self.checked(op, self.relative_base.checked_add(offset))?)
+  }
+
+  fn checked(&self, op: i64, value: Option<i64>) -> Result<i64, VmError> {
+    value.ok_or(VmError::Overflow { ip: self.ip, opcode: op })
+  }
+
+  fn param(&self, op: i64, index: usize) -> Result<i64, VmError> {
+    Ok(self.memory.get(self.param_address(op, index)?))
+  }
+
+  fn target(&self, op: i64, index: usize) -> Result<usize, VmError> {
+    if Mode::from_raw(op, index) == Ok(Mode::Immediate) {
+      return Err(VmError::WriteInImmediateMode { ip: self.ip, opcode: op });
+    }
+    self.param_address(op, index)
+  }
+
+  fn jump(&self, op: i64, target: i64) -> Result<usize, VmError> {
+    if target < 0 || target as u64 >= self.limit as u64 {
+      return Err(VmError::IpOutOfBounds { ip: self.ip, opcode: op, target });
+    }
+    Ok(target as usize)
+  }
+
+  /// Serializes the machine: memory, instruction pointer, relative base,
+  /// state, instruction count, memory limit and both queues. The tracer is
+  /// not part of a snapshot.
+  ///
+  /// ```text
+  /// snapshot := "ICSNAP01" ip rb state steps limit queue queue count segment{count}
+  /// queue    := count value{count}
+  /// segment  := address count value{count}
+  /// ```
+  ///
+  /// All numbers are varints as in the trace format, `state` is a single
+  /// byte.
+  pub fn snapshot(&self) -> Vec<u8> {
+    let mut bytes = SNAPSHOT_MAGIC.to_vec();
+    self.write_snapshot(&mut bytes).expect("writing to a Vec can't fail");
+    bytes
+  }
+
+  fn write_snapshot(&self, out: &mut Vec<u8>) -> io::Result<()> {
+    write_unsigned(out, self.ip as u64)?;
+    write_signed(out, self.relative_base)?;
+    out.push(match self.state {
+      State::Running => 0,
+      State::NeedsInput => 1,
+      State::HasOutput => 2,
+      State::Halted => 3,
+    });
+    write_unsigned(out, self.steps)?;
+    write_unsigned(out, self.limit as u64)?;
+    for queue in &[&self.input, &self.output] {
+      write_unsigned(out, queue.len() as u64)?;
+      for value in queue.iter() {
+        write_signed(out, *value)?;
+      }
+    }
+
+    let chunks = self.memory.chunks();
+    let segments = chunks
+      .iter()
+      .flat_map(|(address, cells)| {
+        segments(cells).into_iter().map(move |(start, end)| (address + start, &cells[start..end]))
+      })
+      .collect::<Vec<_>>();
+    write_unsigned(out, segments.len() as u64)?;
+    for (address, cells) in segments {
+      write_unsigned(out, address as u64)?;
+      write_unsigned(out, cells.len() as u64)?;
+      for cell in cells {
+        write_signed(out, *cell)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Recreates a machine from a `snapshot`. A memory segment beyond the
+  /// memory limit is reported as `VmError::MemoryLimitExceeded` wrapped in an
+  /// `io::Error`.
+  pub fn restore(mut bytes: &[u8]) -> io::Result<Machine<M>> {
+    let input = &mut bytes;
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != SNAPSHOT_MAGIC {
+      return Err(invalid("not an Intcode snapshot"));
+    }
+
+    let mut machine = Machine::with_memory(M::load(&[]));
+    machine.ip = expect_unsigned(input)? as usize;
+    machine.relative_base = expect_signed(input)?;
+    let mut state = [0u8];
+    input.read_exact(&mut state)?;
+    machine.state = match state[0] {
+      0 => State::Running,
+      1 => State::NeedsInput,
+      2 => State::HasOutput,
+      3 => State::Halted,
+      _ => return Err(invalid("invalid machine state")),
+    };
+    machine.steps = expect_unsigned(input)?;
+    machine.limit = expect_unsigned(input)? as usize;
+    if machine.limit > MAX_MEMORY_LIMIT {
+      return Err(invalid("memory limit too large"));
+    }
+    for queue in &mut [&mut machine.input, &mut machine.output] {
+      for _ in 0..expect_unsigned(input)? {
+        queue.push_back(expect_signed(input)?);
+      }
+    }
+
+    for _ in 0..expect_unsigned(input)? {
+      let address = expect_unsigned(input)? as usize;
+      let count = expect_unsigned(input)? as usize;
+      if count > 0 {
+        let last = address.saturating_add(count - 1).min(i64::MAX as usize) as i64;
+        machine
+          .address(machine.peek(machine.ip), last)
+          .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+      }
+      for cell in address..address + count {
+        let value = expect_signed(input)?;
+        machine.memory.set(cell, value);
+      }
+    }
+
+    if !input.is_empty() {
+      return Err(invalid("trailing bytes after snapshot"));
+    }
+    Ok(machine)
+  }
+
+  /// Executes a single instruction and returns the resulting state.
+  ///
+  /// An `in` instruction with an empty input queue does not advance the
+  /// instruction pointer, so the machine can be resumed once input is pushed.
+  /// A faulting instruction leaves the machine untouched.
+  pub fn step(&mut self) -> Result<State, VmError> {
+    if self.state == State::Halted {
+      return Ok(self.state);
+    }
+
+    if self.isa.is_some() {
+      return self.execute_defined();
+    }
+    if self.tracer.0.is_some() {
+      return self.step_traced();
+    }
+    if let Some(op) = self.code.as_ref().and_then(|code| code.op(self.ip)).copied() {
+      return self.execute_op(op);
+    }
+    self.execute()
+  }
+
+  fn step_traced(&mut self) -> Result<State, VmError> {
+    let instruction = match self.current_instruction() {
+      Ok(instruction) => instruction,
+      Err(_) => return self.execute(),
+    };
+    let ip = self.ip;
+    let relative_base = self.relative_base;
+    let write_param = instruction.opcode.write_param();
+
+    let mut operands = Vec::with_capacity(instruction.params.len());
+    for index in 0..instruction.params.len() {
+      let operand = if write_param == Some(index) {
+        self.param_address(instruction.raw, index)? as i64
+      } else {
+        self.param(instruction.raw, index)?
+      };
+      operands.push(operand);
+    }
+    let input = self.input.front().copied();
+
+    let state = self.execute()?;
+    if instruction.opcode == Opcode::In && state == State::NeedsInput {
+      return Ok(state);
+    }
+
+    let write = write_param.map(|index| {
+      let address = operands[index] as usize;
+      (address, self.memory.get(address))
+    });
+    let io = match instruction.opcode {
+      Opcode::In => input.map(Io::Input),
+      Opcode::Out => self.output.back().copied().map(Io::Output),
+      _ => None,
+    };
+    let entry = TraceEntry {
+      step: self.steps - 1,
+      ip,
+      raw: instruction.raw,
+      relative_base,
+      operands,
+      write,
+      io,
+    };
+    if let Some(tracer) = self.tracer.0.as_mut() {
+      tracer.trace(&entry);
+    }
+
+    Ok(state)
+  }
+
+  fn execute(&mut self) -> Result<State, VmError> {
+    let op = self.memory.get(self.ip);
+    let opcode = Opcode::from_raw(op)
+      .ok_or(VmError::InvalidOpcode { ip: self.ip, opcode: op })?;
+    let (state, next_ip) = match opcode {
+      Opcode::Add => {
+        let target = self.target(op, 2)?;
+        let value = self.checked(op, self.param(op, 0)?.checked_add(self.param(op, 1)?))?;
+        self.store(target, value);
+        (State::Running, self.ip + opcode.size())
+      },
+      Opcode::Mul => {
+        let target = self.target(op, 2)?;
+        let value = self.checked(op, self.param(op, 0)?.checked_mul(self.param(op, 1)?))?;
+        self.store(target, value);
+        (State::Running, self.ip + opcode.size())
+      },
+      Opcode::In => {
+        let target = self.target(op, 0)?;
+        if let Some(value) = self.input.pop_front() {
+          self.store(target, value);
+          (State::Running, self.ip + opcode.size())
+        } else {
+          (State::NeedsInput, self.ip)
+        }
+      },
+      Opcode::Out => {
+        let value = self.param(op, 0)?;
+        self.output.push_back(value);
+        (State::HasOutput, self.ip + opcode.size())
+      },
+      Opcode::Jnz => {
+        if self.param(op, 0)? != 0 {
+          (State::Running, self.jump(op, self.param(op, 1)?)?)
+        } else {
+          (State::Running, self.ip + opcode.size())
+        }
+      },
+      Opcode::Jz => {
+        if self.param(op, 0)? == 0 {
+          (State::Running, self.jump(op, self.param(op, 1)?)?)
+        } else {
+          (State::Running, self.ip + opcode.size())
+        }
+      },
+      Opcode::Lt => {
+        let target = self.target(op, 2)?;
+        self.store(target, (self.param(op, 0)? < self.param(op, 1)?) as i64);
+        (State::Running, self.ip + opcode.size())
+      },
+      Opcode::Eq => {
+        let target = self.target(op, 2)?;
+        self.store(target, (self.param(op, 0)? == self.param(op, 1)?) as i64);
+        (State::Running, self.ip + opcode.size())
+      },
+      Opcode::Arb => {
+        self.relative_base = self.checked(op, self.relative_base.checked_add(self.param(op, 0)?))?;
+        (State::Running, self.ip + opcode.size())
+      },
+      Opcode::Hlt => (State::Halted, self.ip),
+    };
+
+    self.ip = next_ip;
+    self.state = state;
+    if state != State::NeedsInput {
+      self.steps += 1;
+    }
+
+    Ok(self.state)
+  }
+
+  /// Executes an instruction of the machine's instruction set.
+  fn execute_defined(&mut self) -> Result<State, VmError> {
+    let raw = self.memory.get(self.ip);
+    let definition = self.isa
+      .as_ref()
+      .and_then(|isa| isa.lookup(raw))
+      .copied()
+      .ok_or(VmError::InvalidOpcode { ip: self.ip, opcode: raw })?;
+
+    let mut operands = Vec::with_capacity(definition.arity);
+    for index in 0..definition.arity {
+      operands.push(if definition.write_param == Some(index) {
+        self.target(raw, index)? as i64
+      } else {
+        self.param(raw, index)?
+      });
+    }
+
+    let (ip, relative_base, outputs) = (self.ip, self.relative_base, self.output.len());
+    let (input, inputs) = (self.input.front().copied(), self.input.len());
+    let mut cpu = Cpu {
+      ip: self.ip,
+      raw,
+      operands: &operands,
+      write_param: definition.write_param,
+      memory: &mut self.memory,
+      relative_base: &mut self.relative_base,
+      input: &mut self.input,
+      output: &mut self.output,
+    };
+    let (state, next_ip) = match (definition.execute)(&mut cpu)? {
+      Flow::Next if self.output.len() > outputs => (State::HasOutput, self.ip + definition.arity + 1),
+      Flow::Next => (State::Running, self.ip + definition.arity + 1),
+      Flow::Jump(target) => (State::Running, self.jump(raw, target)?),
+      Flow::Wait => (State::NeedsInput, self.ip),
+      Flow::Halt => (State::Halted, self.ip),
+    };
+
+    self.ip = next_ip;
+    self.state = state;
+    if state == State::NeedsInput {
+      return Ok(state);
+    }
+    self.steps += 1;
+
+    if let Some(tracer) = self.tracer.0.as_mut() {
+      let write = definition.write_param.map(|index| {
+        let address = operands[index] as usize;
+        (address, self.memory.get(address))
+      });
+      let io = if self.input.len() < inputs {
+        input.map(Io::Input)
+      } else if self.output.len() > outputs {
+        self.output.back().copied().map(Io::Output)
+      } else {
+        None
+      };
+      tracer.trace(&TraceEntry { step: self.steps - 1, ip, raw, relative_base, operands, write, io });
+    }
+
+    Ok(state)
+  }
+
+  #[inline]
+  fn operand(&self, raw: i64, operand: Operand) -> Result<i64, VmError> {
+    match operand {
+      Operand::Immediate(value) => Ok(value),
+      Operand::Position(address) => Ok(self.memory.get(self.address(raw, address)?)),
+      Operand::Relative(offset) => Ok(self.memory.get(self.relative(raw, offset)?)),
+    }
+  }
+
+  #[inline]
+  fn operand_address(&self, raw: i64, operand: Operand) -> Result<usize, VmError> {
+    match operand {
+      Operand::Immediate(_) => Err(VmError::WriteInImmediateMode { ip: self.ip, opcode: raw }),
+      Operand::Position(address) => self.address(raw, address),
+      Operand::Relative(offset) => self.relative(raw, offset),
+    }
+  }
+
+  /// Executes a compiled instruction. Faults are reported exactly like
+  /// `execute` reports them.
+  fn execute_op(&mut self, op: Op) -> Result<State, VmError> {
+    let raw = op.raw;
+    let [a, b, c] = op.args;
+    let (state, next_ip) = match op.opcode {
+      Opcode::Add => {
+        let target = self.operand_address(raw, c)?;
+        let value = self.checked(raw, self.operand(raw, a)?.checked_add(self.operand(raw, b)?))?;
+        self.store(target, value);
+        (State::Running, op.next)
+      },
+      Opcode::Mul => {
+        let target = self.operand_address(raw, c)?;
+        let value = self.checked(raw, self.operand(raw, a)?.checked_mul(self.operand(raw, b)?))?;
+        self.store(target, value);
+        (State::Running, op.next)
+      },
+      Opcode::In => {
+        let target = self.operand_address(raw, a)?;
+        if let Some(value) = self.input.pop_front() {
+          self.store(target, value);
+          (State::Running, op.next)
+        } else {
+          (State::NeedsInput, self.ip)
+        }
+      },
+      Opcode::Out => {
+        let value = self.operand(raw, a)?;
+        self.output.push_back(value);
+        (State::HasOutput, op.next)
+      },
+      Opcode::Jnz => {
+        if self.operand(raw, a)? != 0 {
+          (State::Running, self.jump(raw, self.operand(raw, b)?)?)
+        } else {
+          (State::Running, op.next)
+        }
+      },
+      Opcode::Jz => {
+        if self.operand(raw, a)? == 0 {
+          (State::Running, self.jump(raw, self.operand(raw, b)?)?)
+        } else {
+          (State::Running, op.next)
+        }
+      },
+      Opcode::Lt => {
+        let target = self.operand_address(raw, c)?;
+        let value = (self.operand(raw, a)? < self.operand(raw, b)?) as i64;
+        self.store(target, value);
+        (State::Running, op.next)
+      },
+      Opcode::Eq => {
+        let target = self.operand_address(raw, c)?;
+        let value = (self.operand(raw, a)? == self.operand(raw, b)?) as i64;
+        self.store(target, value);
+        (State::Running, op.next)
+      },
+      Opcode::Arb => {
+        self.relative_base = self.checked(raw, self.relative_base.checked_add(self.operand(raw, a)?))?;
+        (State::Running, op.next)
+      },
+      Opcode::Hlt => (State::Halted, self.ip),
+    };
+
+    self.ip = next_ip;
+    self.state = state;
+    if state != State::NeedsInput {
+      self.steps += 1;
+    }
+
+    Ok(self.state)
+  }
+
+  /// Executes instructions until the machine needs input, produced an
+  /// output value or halted.
+  pub fn run_until_io(&mut self) -> Result<State, VmError> {
+    self.run_metered(&mut self.budget.meter())
+  }
+
+  fn run_metered(&mut self, meter: &mut Option<Meter>) -> Result<State, VmError> {
+    let meter = match meter {
+      Some(meter) => meter,
+      None => loop {
+        let state = self.step()?;
+        if state != State::Running {
+          return Ok(state);
+        }
+      },
+    };
+
+    loop {
+      if self.state != State::Halted {
+        if let Some(limit) = meter.exhausted() {
+          return Err(VmError::BudgetExhausted { ip: self.ip, opcode: self.peek(self.ip), limit });
+        }
+      }
+      let state = self.step()?;
+      if state != State::NeedsInput {
+        meter.charge(state == State::HasOutput);
+      }
+      if state != State::Running {
+        return Ok(state);
+      }
+    }
+  }
+
+  /// Executes instructions until the machine halts or blocks on input.
+  /// Output values are queued and can be collected with `pop_output`.
+  pub fn run(&mut self) -> Result<State, VmError> {
+    let mut meter = self.budget.meter();
+    loop {
+      let state = self.run_metered(&mut meter)?;
+      if state != State::HasOutput {
+        return Ok(state);
+      }
+    }
+  }
+
+  /// Executes instructions until the machine halts or `device` has no more
+  /// input. Queued input is consumed before the device is asked, and all
+  /// output, including values queued before the call, goes to the device.
+  pub fn run_with<D: IoDevice + ?Sized>(&mut self, device: &mut D) -> Result<State, VmError> {
+    while let Some(value) = self.pop_output() {
+      device.output(value);
+    }
+
+    let mut meter = self.budget.meter();
+    loop {
+      match self.run_metered(&mut meter)? {
+        State::NeedsInput => match device.input() {
+          Some(value) => self.push_input(value),
+          None => return Ok(State::NeedsInput),
+        },
+        State::HasOutput => {
+          while let Some(value) = self.pop_output() {
+            device.output(value);
+          }
+        },
+        state => return Ok(state),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{parse_instructions, DenseMemory};
+
+  #[test]
+  fn machine_pauses_on_input_and_output() {
+    // echo two values, then halt
+    let mut machine = Machine::new(&[3, 11, 4, 11, 3, 11, 4, 11, 99, 0, 0, 0]);
+
+    assert_eq!(machine.run_until_io(), Ok(State::NeedsInput));
+    assert_eq!(machine.ip(), 0);
+
+    machine.push_input(42);
+    assert_eq!(machine.run_until_io(), Ok(State::HasOutput));
+    assert_eq!(machine.pop_output(), Some(42));
+
+    assert_eq!(machine.run_until_io(), Ok(State::NeedsInput));
+    machine.push_input(-7);
+    assert_eq!(machine.run(), Ok(State::Halted));
+    assert_eq!(machine.drain_output(), vec![-7]);
+  }
+
+  #[test]
+  fn machine_steps_single_instructions() {
+    let mut machine = Machine::new(&[1101, 2, 3, 5, 99, 0]);
+
+    assert_eq!(machine.step(), Ok(State::Running));
+    assert_eq!(machine.ip(), 4);
+    assert_eq!(machine.peek(5), 5);
+    assert_eq!(machine.step(), Ok(State::Halted));
+    assert_eq!(machine.step(), Ok(State::Halted));
+  }
+
+  #[test]
+  fn machine_tracks_relative_base() {
+    let mut machine = Machine::new(&[109, 19, 204, -19, 99]);
+
+    assert_eq!(machine.step(), Ok(State::Running));
+    assert_eq!(machine.relative_base(), 19);
+    assert_eq!(machine.run(), Ok(State::Halted));
+    assert_eq!(machine.pop_output(), Some(109));
+  }
+
+  // reads a value, stores it far away and outputs value * 2, forever
+  fn doubler() -> Vec<i64> {
+    vec![3, 100000, 1002, 100000, 2, 100001, 4, 100001, 1105, 1, 0]
+  }
+
+  #[test]
+  fn forks_share_memory_until_written() {
+    let mut machine = Machine::new(&doubler());
+    assert_eq!(machine.run(), Ok(State::NeedsInput));
+
+    let mut fork = machine.fork();
+    assert_eq!(machine.memory().shared_pages(), 1);
+
+    fork.push_input(21);
+    assert_eq!(fork.run(), Ok(State::NeedsInput));
+    assert_eq!(fork.pop_output(), Some(42));
+    assert_eq!(fork.memory().shared_pages(), 1);
+    assert_eq!((fork.peek(100000), machine.peek(100000)), (21, 0));
+
+    machine.push_input(5);
+    assert_eq!(machine.run(), Ok(State::NeedsInput));
+    assert_eq!(machine.pop_output(), Some(10));
+  }
+
+  #[test]
+  fn snapshot_round_trip() {
+    let mut machine = Machine::new(&doubler());
+    machine.extend_input(vec![3, 4]);
+    assert_eq!(machine.run_until_io(), Ok(State::HasOutput));
+    machine.poke(5000, -1);
+
+    let bytes = machine.snapshot();
+    let mut restored: Machine = Machine::restore(&bytes).unwrap();
+    assert_eq!(restored.snapshot(), bytes);
+    assert_eq!((restored.ip(), restored.state(), restored.steps()), (8, State::HasOutput, 3));
+    assert_eq!((restored.peek(100001), restored.peek(5000)), (6, -1));
+
+    assert_eq!(restored.run(), Ok(State::NeedsInput));
+    assert_eq!(restored.drain_output(), vec![6, 8]);
+
+    let day9 = parse_instructions(include_str!("../day9/data/input-1.txt").trim());
+    let mut restored: Machine<DenseMemory> = Machine::restore(&Machine::new(&day9).snapshot()).unwrap();
+    restored.push_input(1);
+    assert_eq!(restored.run(), Ok(State::Halted));
+    assert_eq!(restored.drain_output(), vec![2453265701]);
+  }
+
+  #[test]
+  fn restore_rejects_invalid_snapshots() {
+    let bytes = Machine::new(&doubler()).snapshot();
+
+    assert!(Machine::<PagedMemory>::restore(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Machine::<PagedMemory>::restore(&[bytes.clone(), vec![0]].concat()).is_err());
+    assert!(Machine::<PagedMemory>::restore(b"ICTRACE1").is_err());
+  }
+
+  #[test]
+  fn restore_checks_segments_against_the_memory_limit() {
+    // a machine with the given memory limit and one cell at `address`
+    let snapshot = |limit: u64, address: u64| {
+      let mut bytes = b"ICSNAP01".to_vec();
+      for value in [0, 0] {
+        write_unsigned(&mut bytes, value).unwrap();
+      }
+      bytes.push(0);
+      for value in [0, limit, 0, 0, 1, address, 1, 2] {
+        write_unsigned(&mut bytes, value).unwrap();
+      }
+      bytes
+    };
+
+    let restored = Machine::<DenseMemory>::restore(&snapshot(100, 99)).unwrap();
+    assert_eq!(restored.peek(99), 1);
+
+    let err = Machine::<DenseMemory>::restore(&snapshot(100, 100)).unwrap_err();
+    let err = err.get_ref().and_then(|err| err.downcast_ref::<VmError>());
+    assert_eq!(err, Some(&VmError::MemoryLimitExceeded { ip: 0, opcode: 0, address: 100 }));
+
+    // would allocate far more than any machine may use
+    assert!(Machine::<DenseMemory>::restore(&snapshot(u64::MAX, 1 << 60)).is_err());
+  }
+
+  #[test]
+  fn overflow_is_a_fault() {
+    let mut machine = Machine::new(&[1102, 1 << 40, 1 << 40, 7, 4, 7, 99, 0]);
+    assert_eq!(machine.run(), Err(VmError::Overflow { ip: 0, opcode: 1102 }));
+    assert_eq!(machine.peek(7), 0);
+
+    let mut compiled = Machine::new(&[109, i64::MAX, 109, 1, 99]);
+    compiled.compile();
+    assert_eq!(compiled.run(), Err(VmError::Overflow { ip: 2, opcode: 109 }));
+  }
+}
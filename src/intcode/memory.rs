@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Storage for the cells of an Intcode machine.
+///
+/// Memory is conceptually infinite and zero-initialized: reading a cell that
+/// was never written returns 0. Enforcing an upper bound is up to the machine.
+pub trait Memory {
+  fn load(program: &[i64]) -> Self where Self: Sized;
+  fn get(&self, address: usize) -> i64;
+  fn set(&mut self, address: usize, value: i64);
+
+  /// The allocated regions of memory as `(address, cells)` pairs, in
+  /// ascending order. Cells outside of them are 0.
+  fn chunks(&self) -> Vec<(usize, &[i64])>;
+}
+
+/// A flat vector reserving 4MB up front, the layout the interpreter used
+/// originally. It grows when a program writes beyond the reserved area.
+#[derive(Debug, Clone)]
+pub struct DenseMemory {
+  cells: Vec<i64>,
+}
+
+impl Memory for DenseMemory {
+  fn load(program: &[i64]) -> DenseMemory {
+    let mut cells = program.to_vec();
+    cells.resize(cells.len().max(524288), 0);
+    DenseMemory { cells }
+  }
+
+  fn get(&self, address: usize) -> i64 {
+    self.cells.get(address).copied().unwrap_or(0)
+  }
+
+  fn set(&mut self, address: usize, value: i64) {
+    if address >= self.cells.len() {
+      self.cells.resize(address + 1, 0);
+    }
+    self.cells[address] = value;
+  }
+
+  fn chunks(&self) -> Vec<(usize, &[i64])> {
+    vec![(0, &self.cells)]
+  }
+}
+
+const PAGE_BITS: usize = 10;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+
+type Page = Arc<[i64; PAGE_SIZE]>;
+
+/// A dense prefix holding the program and everything written right after it,
+/// plus sparse pages for cells far away from it.
+///
+/// The prefix always spans a whole number of pages. Writing to the page right
+/// after the prefix extends the prefix, which keeps the typical stack growing
+/// upwards from the end of the program in the fast path.
+///
+/// Pages are reference counted and copied on the first write, so cloning the
+/// memory of a machine is cheap and clones only pay for the pages they change.
+#[derive(Debug, Clone, Default)]
+pub struct PagedMemory {
+  dense: Vec<Page>,
+  pages: HashMap<usize, Page>,
+}
+
+impl PagedMemory {
+  fn grow_dense(&mut self) {
+    let page = self.dense.len();
+    let cells = self.pages.remove(&page).unwrap_or_else(|| Arc::new([0; PAGE_SIZE]));
+    self.dense.push(cells);
+  }
+
+  /// Number of cells backed by the dense prefix.
+  pub fn dense_len(&self) -> usize {
+    self.dense.len() << PAGE_BITS
+  }
+
+  /// Number of pages allocated outside of the dense prefix.
+  pub fn sparse_pages(&self) -> usize {
+    self.pages.len()
+  }
+
+  /// Number of pages this memory shares with clones of it.
+  pub fn shared_pages(&self) -> usize {
+    self.dense.iter().chain(self.pages.values()).filter(|page| Arc::strong_count(page) > 1).count()
+  }
+}
+
+impl Memory for PagedMemory {
+  fn load(program: &[i64]) -> PagedMemory {
+    let dense = program
+      .chunks(PAGE_SIZE)
+      .map(|chunk| {
+        let mut cells = [0; PAGE_SIZE];
+        cells[..chunk.len()].copy_from_slice(chunk);
+        Arc::new(cells)
+      })
+      .collect();
+
+    PagedMemory { dense, pages: HashMap::new() }
+  }
+
+  #[inline]
+  fn get(&self, address: usize) -> i64 {
+    let page = address >> PAGE_BITS;
+    if let Some(cells) = self.dense.get(page) {
+      return cells[address & (PAGE_SIZE - 1)];
+    }
+
+    self.pages
+      .get(&page)
+      .map_or(0, |cells| cells[address & (PAGE_SIZE - 1)])
+  }
+
+  #[inline]
+  fn set(&mut self, address: usize, value: i64) {
+    let page = address >> PAGE_BITS;
+    if page == self.dense.len() {
+      self.grow_dense();
+    }
+    if let Some(cells) = self.dense.get_mut(page) {
+      Arc::make_mut(cells)[address & (PAGE_SIZE - 1)] = value;
+      return;
+    }
+
+    if value == 0 && !self.pages.contains_key(&page) {
+      return;
+    }
+    let cells = self.pages.entry(page).or_insert_with(|| Arc::new([0; PAGE_SIZE]));
+    Arc::make_mut(cells)[address & (PAGE_SIZE - 1)] = value;
+  }
+
+  fn chunks(&self) -> Vec<(usize, &[i64])> {
+    let sparse = self.pages.iter().map(|(page, cells)| (page << PAGE_BITS, &cells[..])).collect::<BTreeMap<_, _>>();
+    self.dense
+      .iter()
+      .enumerate()
+      .map(|(page, cells)| (page << PAGE_BITS, &cells[..]))
+      .chain(sparse)
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{Machine, State, VmError};
+
+  #[test]
+  fn memory_grows_beyond_the_old_bound() {
+    // write the input to 1000000 and echo it back
+    let mut machine = Machine::new(&[3, 1000000, 4, 1000000, 99]);
+    machine.push_input(17);
+
+    assert_eq!(machine.run(), Ok(State::Halted));
+    assert_eq!(machine.pop_output(), Some(17));
+    assert_eq!(machine.memory().sparse_pages(), 1);
+  }
+
+  #[test]
+  fn paged_memory_extends_dense_prefix() {
+    let mut memory = PagedMemory::load(&[1, 2, 3]);
+    assert_eq!(memory.dense_len(), 1024);
+
+    memory.set(1500, 5);
+    memory.set(5000, 0);
+    memory.set(9000, 7);
+    assert_eq!(memory.dense_len(), 2048);
+    assert_eq!(memory.sparse_pages(), 1);
+
+    memory.set(2100, 1);
+    memory.set(3000, 2);
+    assert_eq!(memory.dense_len(), 3072);
+    assert_eq!((memory.get(2), memory.get(1500), memory.get(3000)), (3, 5, 2));
+    assert_eq!((memory.get(5000), memory.get(9000), memory.get(9001)), (0, 7, 0));
+  }
+
+  #[test]
+  fn memory_limit_is_enforced() {
+    let mut machine = Machine::new(&[1101, 1, 1, 100, 99]);
+    machine.set_memory_limit(100);
+
+    assert_eq!(machine.run(), Err(VmError::MemoryLimitExceeded { ip: 0, opcode: 1101, address: 100 }));
+  }
+
+  #[test]
+  fn dense_memory_backend() {
+    let mut machine = Machine::with_memory(DenseMemory::load(&[109, 600000, 21101, 2, 3, 0, 204, 0, 99]));
+
+    assert_eq!(machine.run(), Ok(State::Halted));
+    assert_eq!(machine.pop_output(), Some(5));
+  }
+}
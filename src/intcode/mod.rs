@@ -1,135 +1,100 @@
+mod analysis;
+mod ascii;
+mod asm;
+mod budget;
+mod compiler;
+mod debugger;
+mod decompile;
+mod device;
+mod disasm;
+mod error;
+mod fuzz;
+mod history;
+mod instruction;
+mod isa;
+mod loader;
+mod machine;
+mod memory;
+mod network;
+mod profile;
+mod scanner;
+mod symbolic;
+mod topology;
+mod trace;
+mod varint;
+mod wide;
+
+pub use self::analysis::{analyze, analyze_with_targets, Analysis, Block, Edge, EdgeKind, Exit};
+pub use self::ascii::AsciiConsole;
+pub use self::asm::{assemble, AsmError};
+pub use self::budget::{Budget, Limit};
+pub use self::debugger::{Debugger, Stop};
+pub use self::decompile::{decompile, decompile_with_targets, BinOp, Cond, Decompiled, Expr, Function, RunError, Stmt};
+pub use self::device::{InputProvider, IoDevice, OutputSink};
+pub use self::disasm::{disassemble, disassemble_with_targets, render, Line};
+pub use self::error::VmError;
+pub use self::fuzz::{check_backends, fuzz, generate_program, minimize, run_backend, Backend, Failure, Mismatch, Outcome};
+pub use self::instruction::{Instruction, Mode, Opcode, Param};
+pub use self::isa::{Cpu, Definition, Execute, Flow, InstructionSet, MAX_ARITY};
+pub use self::loader::{deserialize, load, load_file, serialize, LoadError};
+pub use self::machine::{Machine, State, DEFAULT_MEMORY_LIMIT, MAX_MEMORY_LIMIT};
+pub use self::memory::{DenseMemory, Memory, PagedMemory};
+pub use self::network::{Control, Endpoint, Network, Packet};
+pub use self::profile::{Profile, Profiler};
+pub use self::scanner::{Filter, Scanner, Snapshot};
+pub use self::symbolic::{solve, Constraint, Linear, Path, Symbol, SymbolicError, SymbolicMachine, Term};
+pub use self::topology::{NodeId, Topology};
+pub use self::trace::{diff, read_trace, replay, Divergence, Io, TraceEntry, TraceReader, TraceWriter, Tracer};
+pub use self::wide::{WideMachine, Word};
+
 use std::sync::mpsc::{channel, Receiver, Sender};
 
+/// Like `load`, but panics on invalid programs.
 pub fn parse_instructions(input: &str) -> Vec<i64> {
-  input.split(",")
-    .filter(|v| *v != "\n")
-    .filter(|v| *v != "")
-    .map(|v| v.parse::<i64>().unwrap())
-    .collect::<Vec<_>>()
+  load(input).unwrap_or_else(|err| panic!("{}", err))
 }
 
-pub fn isa_interpreter(instructions: &mut Vec<i64>, input: i64) -> i64 {
+pub fn isa_interpreter(instructions: &mut Vec<i64>, input: i64) -> Result<i64, VmError> {
   isa_interpreter_mi(instructions, &vec![input])
 }
 
-pub fn isa_interpreter_mi(instructions: &mut Vec<i64>, input: &Vec<i64>) -> i64 {
+pub fn isa_interpreter_mi(instructions: &mut Vec<i64>, input: &Vec<i64>) -> Result<i64, VmError> {
   let (send, recv) = channel();
   for i in input {
     send.send(*i).unwrap();
   }
+  // close the channel so running out of input is reported instead of blocking forever
+  drop(send);
   isa_interpreter_mpsc(instructions, recv)
 }
 
-pub fn isa_interpreter_mpsc(instructions: &mut Vec<i64>, input: Receiver<i64>) -> i64 {
+pub fn isa_interpreter_mpsc(instructions: &mut Vec<i64>, input: Receiver<i64>) -> Result<i64, VmError> {
   let (send, _recv) = channel();
   isa_interpreter_async(instructions.clone(), input, send)
 }
 
-pub fn isa_interpreter_async(instructions: Vec<i64>, input: Receiver<i64>, output: Sender<i64>) -> i64 {
-  let mut ip = 0;
-  let mut instructions = instructions.clone();
-  // reserve 4MB for the intcode program
-  instructions.resize(524288, 0);
-  let mut op = instructions[ip];
-
-  let jump_table: Vec<usize> = vec![1, 4, 4, 2, 2, 3, 3, 4, 4, 2];
-  let param_table: Vec<u32> = vec![1, 2, 2, 0, 1, 2, 2, 2, 2, 1];
-  let mut outputs = vec![];
-  let mut relative_base = 0;
-
-  while op != 99 {
-    let main_op = op % 10;
-    let num_params = param_table[main_op as usize];
-
-    let mut operands = vec![];
-    for i in 0..num_params {
-      let mode = op / 10i64.pow(i + 2) % 10;
-      let param = instructions[ip + i as usize + 1];
-      let operand = if mode == 0 {
-        instructions[param as usize]
-      } else if mode == 1 {
-        param
-      } else if mode == 2 {
-        instructions[(relative_base + param) as usize]
-      } else {
-        panic!("Unknown param mode: {}", mode);
-      };
-      operands.push(operand);
-    }
-    let result_index = if (op / 10i64.pow(num_params + 2)) % 10 == 2 {
-      (relative_base as isize + instructions[ip + num_params as usize + 1] as isize) as usize
-    } else {
-      instructions[ip + num_params as usize + 1] as usize
-    };
+pub fn isa_interpreter_async(instructions: Vec<i64>, input: Receiver<i64>, output: Sender<i64>) -> Result<i64, VmError> {
+  isa_interpreter_budgeted(instructions, input, output, Budget::unlimited())
+}
 
-    let mut jumped = false;
-    match main_op {
-      1 => {
-        instructions[result_index] = operands[0] + operands[1]
-      },
-      2 => {
-        instructions[result_index] = operands[0] * operands[1]
-      },
-      3 => {
-        let value = input.recv();
-        if let Ok(value) = value {
-          instructions[result_index] = value;
-        } else if let Err(err) = value {
-          println!("Error while reading value: {}", err);
-          break;
-        }
-      },
-      4 => {
-        if let Err(_) = output.send(operands[0]) {
-          // some sends might fail because the receiving end was already deallocated
-        }
-        outputs.push(operands[0]);
-      },
-      5 => {
-        if operands[0] != 0 {
-          ip = operands[1] as usize;
-          jumped = true;
-        }
-      },
-      6 => {
-        if operands[0] == 0 {
-          ip = operands[1] as usize;
-          jumped = true;
-        }
-      },
-      7 => {
-        instructions[result_index] = if operands[0] < operands[1] {
-          1
-        } else {
-          0
-        };
-      },
-      8 => {
-        instructions[result_index] = if operands[0] == operands[1] {
-          1
-        } else {
-          0
-        };
-      },
-      9 => {
-        relative_base += operands[0];
-      }
-      _ => panic!("At the math disco!")
-    };
+/// Like `isa_interpreter_async`, but fails with `VmError::BudgetExhausted`
+/// instead of running forever.
+pub fn isa_interpreter_budgeted(instructions: Vec<i64>, input: Receiver<i64>, output: Sender<i64>, budget: Budget) -> Result<i64, VmError> {
+  let mut machine = Machine::new(&instructions);
+  machine.set_budget(budget);
+  let mut last_output = None;
 
-    if !jumped {
-      ip += jump_table[main_op as usize];
-    }
-    op = instructions[ip];
+  let mut sink = |value| {
+    // some sends might fail because the receiving end was already deallocated
+    let _ = output.send(value);
+    last_output = Some(value);
+  };
+  if machine.run_with(&mut (input, &mut sink))? == State::NeedsInput {
+    let ip = machine.ip();
+    return Err(VmError::InputClosed { ip, opcode: machine.peek(ip) });
   }
 
-  // println!("outputs: {:?}", outputs);
-
-  if outputs.len() > 0 {
-    outputs[outputs.len() - 1]
-  } else {
-    instructions[0]
-  }
+  Ok(last_output.unwrap_or_else(|| machine.peek(0)))
 }
 
 #[cfg(test)]
@@ -138,6 +103,35 @@ mod test {
 
   #[test]
   fn verify_203_works() {
-    assert_eq!(isa_interpreter(&mut vec![109, 3, 203, 4, 4, 7, 99, 0], 1), 1);
+    assert_eq!(isa_interpreter(&mut vec![109, 3, 203, 4, 4, 7, 99, 0], 1), Ok(1));
+  }
+
+  #[test]
+  fn faults_are_reported() {
+    assert_eq!(
+      isa_interpreter(&mut vec![1, 0, 0, 0, 42], 0),
+      Err(VmError::InvalidOpcode { ip: 4, opcode: 42 })
+    );
+    assert_eq!(
+      isa_interpreter(&mut vec![301, 0, 0, 0, 99], 0),
+      Err(VmError::InvalidParamMode { ip: 0, opcode: 301, mode: 3 })
+    );
+    assert_eq!(
+      isa_interpreter(&mut vec![1, -1, 0, 0, 99], 0),
+      Err(VmError::NegativeAddress { ip: 0, opcode: 1, address: -1 })
+    );
+    assert_eq!(
+      isa_interpreter(&mut vec![11101, 1, 1, 0, 99], 0),
+      Err(VmError::WriteInImmediateMode { ip: 0, opcode: 11101 })
+    );
+    assert_eq!(
+      isa_interpreter_mi(&mut vec![3, 0, 3, 0, 99], &vec![1]),
+      Err(VmError::InputClosed { ip: 2, opcode: 3 })
+    );
+    assert_eq!(
+      isa_interpreter(&mut vec![1105, 1, -3, 99], 0),
+      Err(VmError::IpOutOfBounds { ip: 0, opcode: 1105, target: -3 })
+    );
   }
-}
\ No newline at end of file
+
+}
@@ -0,0 +1,335 @@
+//! Networks of Intcode machines that exchange packets, as in day 23.
+//!
+//! Every machine is told its address as its first input and then sends
+//! packets as three output values: the destination address, `x` and `y`.
+//! `in` reads the `x` and `y` of received packets, or the empty input value
+//! (`-1` by default) if there are none.
+//!
+//! The network runs in rounds on the calling thread. In a round every machine
+//! runs in address order until it asks for input a second time without
+//! anything to read, then the packets sent in the round are delivered in the
+//! order they were sent. Runs are therefore reproducible.
+//!
+//! Addresses that are not machines can be served by an `Endpoint`:
+//!
+//! ```
+//! # use y2019::intcode::{Control, Endpoint, Network, Packet};
+//! // the first y sent to 255 ends the run
+//! struct First;
+//!
+//! impl Endpoint for First {
+//!   fn receive(&mut self, packet: Packet, _: &mut Vec<Packet>) -> Control {
+//!     Control::Stop(packet.y)
+//!   }
+//! }
+//!
+//! // sends [255, address, address * 10]
+//! let mut network = Network::new(&[3, 100, 104, 255, 4, 100, 1002, 100, 10, 100, 4, 100, 99], 2);
+//! network.attach(255, First);
+//! assert_eq!(network.run(), Ok(Some(0)));
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+
+use super::{Budget, IoDevice, Machine, State, VmError};
+
+/// Three output values of a machine, or a packet from an endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+  pub from: i64,
+  pub to: i64,
+  pub x: i64,
+  pub y: i64,
+}
+
+/// Whether a network keeps running after an endpoint was called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+  Continue,
+  /// Ends `Network::run` with the value.
+  Stop(i64),
+}
+
+/// Handles the packets sent to an address that is not a machine.
+pub trait Endpoint {
+  /// Called for every packet sent to the endpoint. Packets pushed to `send`
+  /// are delivered with the other packets of the round.
+  fn receive(&mut self, packet: Packet, send: &mut Vec<Packet>) -> Control;
+
+  /// Called when the network is idle: every machine waits for input and no
+  /// packets are underway. The network stays idle unless an endpoint sends
+  /// a packet.
+  fn idle(&mut self, _send: &mut Vec<Packet>) -> Control {
+    Control::Continue
+  }
+}
+
+/// The network interface of a machine.
+struct Nic {
+  address: i64,
+  incoming: VecDeque<i64>,
+  empty_input: Option<i64>,
+  polled: bool,
+  buffer: Vec<i64>,
+  outgoing: Vec<Packet>,
+}
+
+impl IoDevice for Nic {
+  // an empty queue is reported once per round, after that the machine
+  // waits for the next round
+  fn input(&mut self) -> Option<i64> {
+    if let Some(value) = self.incoming.pop_front() {
+      return Some(value);
+    }
+    if self.polled {
+      return None;
+    }
+    self.polled = true;
+    self.empty_input
+  }
+
+  fn output(&mut self, value: i64) {
+    self.buffer.push(value);
+    if let [to, x, y] = self.buffer[..] {
+      self.outgoing.push(Packet { from: self.address, to, x, y });
+      self.buffer.clear();
+    }
+  }
+}
+
+/// Machines at the addresses `0..count` and endpoints, see the module
+/// documentation.
+pub struct Network {
+  nodes: Vec<(Machine, Nic)>,
+  endpoints: BTreeMap<i64, Box<dyn Endpoint>>,
+  underway: Vec<Packet>,
+  rounds: u64,
+  delivered: u64,
+  dropped: u64,
+}
+
+impl Network {
+  /// `count` machines running `program`.
+  pub fn new(program: &[i64], count: usize) -> Network {
+    let nodes = (0..count as i64)
+      .map(|address| {
+        let nic = Nic {
+          address,
+          incoming: VecDeque::from(vec![address]),
+          empty_input: Some(-1),
+          polled: false,
+          buffer: vec![],
+          outgoing: vec![],
+        };
+        (Machine::new(program), nic)
+      })
+      .collect();
+
+    Network { nodes, endpoints: BTreeMap::new(), underway: vec![], rounds: 0, delivered: 0, dropped: 0 }
+  }
+
+  /// Sets the value `in` reads when there is no packet. With `None` the
+  /// machines wait for packets instead.
+  pub fn set_empty_input(&mut self, value: Option<i64>) {
+    for (_, nic) in &mut self.nodes {
+      nic.empty_input = value;
+    }
+  }
+
+  /// Limits what each machine may do in a round.
+  pub fn set_budget(&mut self, budget: Budget) {
+    for (machine, _) in &mut self.nodes {
+      machine.set_budget(budget);
+    }
+  }
+
+  /// Serves `address` with `endpoint` instead of dropping its packets.
+  pub fn attach<E: Endpoint + 'static>(&mut self, address: i64, endpoint: E) {
+    self.endpoints.insert(address, Box::new(endpoint));
+  }
+
+  pub fn machine(&self, address: usize) -> &Machine {
+    &self.nodes[address].0
+  }
+
+  /// Queues a packet for delivery at the start of the next round.
+  pub fn send(&mut self, packet: Packet) {
+    self.underway.push(packet);
+  }
+
+  /// Number of rounds run so far.
+  pub fn rounds(&self) -> u64 {
+    self.rounds
+  }
+
+  /// Number of packets delivered to machines and endpoints.
+  pub fn delivered(&self) -> u64 {
+    self.delivered
+  }
+
+  /// Number of packets sent to halted machines or to addresses without a
+  /// machine or endpoint.
+  pub fn dropped(&self) -> u64 {
+    self.dropped
+  }
+
+  /// Whether every machine waits for input with nothing to read, or halted,
+  /// and no packets are underway.
+  pub fn is_idle(&self) -> bool {
+    self.underway.is_empty()
+      && self.nodes.iter().all(|(machine, nic)| machine.state() == State::Halted || (nic.incoming.is_empty() && nic.polled))
+  }
+
+  fn deliver(&mut self, packets: Vec<Packet>) -> Control {
+    let mut replies = vec![];
+    let mut stop = Control::Continue;
+    for packet in packets {
+      if let Some(endpoint) = self.endpoints.get_mut(&packet.to) {
+        self.delivered += 1;
+        stop = endpoint.receive(packet, &mut replies);
+        if stop != Control::Continue {
+          break;
+        }
+        continue;
+      }
+      match self.nodes.get_mut(packet.to as usize) {
+        Some((machine, nic)) if packet.to >= 0 && machine.state() != State::Halted => {
+          self.delivered += 1;
+          nic.incoming.extend([packet.x, packet.y]);
+        },
+        _ => self.dropped += 1,
+      }
+    }
+    self.underway = replies;
+    stop
+  }
+
+  /// Delivers the packets underway, then runs every machine until it waits
+  /// for input. The packets they send are delivered in the next round.
+  pub fn round(&mut self) -> Result<Control, VmError> {
+    let packets = std::mem::take(&mut self.underway);
+    if let Control::Stop(value) = self.deliver(packets) {
+      return Ok(Control::Stop(value));
+    }
+
+    for (machine, nic) in &mut self.nodes {
+      if machine.state() == State::Halted {
+        continue;
+      }
+      nic.polled = false;
+      machine.run_with(nic)?;
+      self.underway.append(&mut nic.outgoing);
+    }
+    self.rounds += 1;
+    Ok(Control::Continue)
+  }
+
+  /// Runs rounds until an endpoint stops the network, or it is idle and no
+  /// endpoint wakes it up.
+  pub fn run(&mut self) -> Result<Option<i64>, VmError> {
+    loop {
+      if let Control::Stop(value) = self.round()? {
+        return Ok(Some(value));
+      }
+      if !self.is_idle() {
+        continue;
+      }
+
+      let mut packets = vec![];
+      for endpoint in self.endpoints.values_mut() {
+        if let Control::Stop(value) = endpoint.idle(&mut packets) {
+          return Ok(Some(value));
+        }
+      }
+      if packets.is_empty() {
+        return Ok(None);
+      }
+      self.underway = packets;
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::assemble;
+
+  /// Forwards every packet to the next address with y + 1, machine
+  /// `count - 1` sends to 255 instead.
+  fn relay(count: i64) -> Vec<i64> {
+    assemble(&format!("
+              in [address]
+              add [address], 1, [next]
+              eq [next], {count}, [last]
+              jz [last], wait
+              add 255, 0, [next]
+      wait:   in [x]
+              eq [x], -1, [empty]
+              jnz [empty], wait
+              in [y]
+              add [y], 1, [y]
+              out [next]
+              out [x]
+              out [y]
+              jz 0, wait
+      address: .data 0
+      next:   .data 0
+      last:   .data 0
+      x:      .data 0
+      y:      .data 0
+      empty:  .data 0
+    ", count = count)).unwrap()
+  }
+
+  struct Recorder(Vec<Packet>);
+
+  impl Endpoint for Recorder {
+    fn receive(&mut self, packet: Packet, _: &mut Vec<Packet>) -> Control {
+      self.0.push(packet);
+      if self.0.len() == 3 { Control::Stop(packet.y) } else { Control::Continue }
+    }
+  }
+
+  #[test]
+  fn routes_packets_until_an_endpoint_stops() {
+    let mut network = Network::new(&relay(10), 10);
+    network.attach(255, Recorder(vec![]));
+    for x in 0..3 {
+      network.send(Packet { from: -1, to: 0, x, y: 100 * x });
+    }
+
+    assert_eq!(network.run(), Ok(Some(210)));
+    assert_eq!(network.delivered(), 33);
+    assert_eq!(network.dropped(), 0);
+  }
+
+  struct Wake(u64);
+
+  impl Endpoint for Wake {
+    fn receive(&mut self, _: Packet, _: &mut Vec<Packet>) -> Control {
+      Control::Continue
+    }
+
+    fn idle(&mut self, send: &mut Vec<Packet>) -> Control {
+      self.0 += 1;
+      if self.0 == 4 {
+        return Control::Stop(self.0 as i64);
+      }
+      send.push(Packet { from: 255, to: 0, x: 0, y: 0 });
+      Control::Continue
+    }
+  }
+
+  #[test]
+  fn idle_networks_are_woken_up_by_endpoints() {
+    let mut network = Network::new(&relay(5), 5);
+    assert_eq!(network.run(), Ok(None));
+    assert!(network.is_idle());
+
+    network.attach(255, Wake(0));
+    assert_eq!(network.run(), Ok(Some(4)));
+    // each wake-up travels through the five machines to 255
+    assert_eq!(network.delivered(), 3 * 6);
+    assert_eq!(network.rounds(), 1 + 3 * 6 + 1);
+  }
+}
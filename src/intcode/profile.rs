@@ -0,0 +1,350 @@
+//! Execution profiles.
+//!
+//! A `Profiler` hands out tracers that count how often every address and
+//! opcode is executed and how many instructions machines execute between
+//! I/O events. All machines traced by the same profiler add to one
+//! `Profile`, so the amplifiers of day 7 can be profiled together:
+//!
+//! ```
+//! # use y2019::intcode::{Machine, Profiler};
+//! let program = [3, 9, 4, 9, 1005, 9, 0, 99, 0, 0];
+//! let profiler = Profiler::new();
+//! for input in 0..2 {
+//!   let mut machine = Machine::new(&program);
+//!   profiler.attach(&mut machine);
+//!   machine.push_input(input);
+//!   machine.run().unwrap();
+//! }
+//! assert_eq!(profiler.profile().executions(0), 2);
+//! print!("{}", profiler.profile().table(&program));
+//! ```
+//!
+//! Profiling uses the tracer hook of a machine and is as slow as tracing.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use super::{disassemble_with_targets, Instruction, Line, Machine, Memory, Opcode, TraceEntry, Tracer};
+
+/// Number of addresses listed as hot spots in `Profile::table`.
+const HOT_SPOTS: usize = 10;
+
+/// Execution counts of one or more machines running the same program.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+  instances: usize,
+  executions: BTreeMap<usize, u64>,
+  opcodes: BTreeMap<Opcode, u64>,
+  io_gaps: BTreeMap<u64, u64>,
+}
+
+impl Profile {
+  /// Number of machines that were profiled.
+  pub fn instances(&self) -> usize {
+    self.instances
+  }
+
+  /// Number of instructions executed by all machines.
+  pub fn instructions(&self) -> u64 {
+    self.executions.values().sum()
+  }
+
+  /// How often the instruction at `address` was executed.
+  pub fn executions(&self, address: usize) -> u64 {
+    self.executions.get(&address).copied().unwrap_or(0)
+  }
+
+  pub fn opcode_executions(&self, opcode: Opcode) -> u64 {
+    self.opcodes.get(&opcode).copied().unwrap_or(0)
+  }
+
+  /// How often machines executed a number of instructions between two I/O
+  /// instructions, or before their first one.
+  pub fn io_gaps(&self) -> &BTreeMap<u64, u64> {
+    &self.io_gaps
+  }
+
+  /// The `count` most executed addresses, most executed first.
+  pub fn hot_spots(&self, count: usize) -> Vec<(usize, u64)> {
+    let mut spots = self.executions.iter().map(|(address, count)| (*address, *count)).collect::<Vec<_>>();
+    spots.sort_by_key(|(address, count)| (std::cmp::Reverse(*count), *address));
+    spots.truncate(count);
+    spots
+  }
+
+  /// The instructions of `program`, including the ones only reached through
+  /// computed jumps the profiled machines took.
+  fn code(&self, program: &[i64]) -> Vec<Line> {
+    let targets = self.executions.keys().copied().collect::<Vec<_>>();
+    disassemble_with_targets(program, &targets)
+  }
+
+  /// The instructions of `program` that were never executed.
+  pub fn unexecuted(&self, program: &[i64]) -> Vec<Instruction> {
+    self.code(program)
+      .into_iter()
+      .filter_map(|line| match line {
+        Line::Code(instruction) if self.executions(instruction.address) == 0 => Some(instruction),
+        _ => None,
+      })
+      .collect()
+  }
+
+  /// Adds the counts of `other`.
+  pub fn merge(&mut self, other: &Profile) {
+    self.instances += other.instances;
+    for (address, count) in &other.executions {
+      *self.executions.entry(*address).or_default() += count;
+    }
+    for (opcode, count) in &other.opcodes {
+      *self.opcodes.entry(*opcode).or_default() += count;
+    }
+    for (gap, count) in &other.io_gaps {
+      *self.io_gaps.entry(*gap).or_default() += count;
+    }
+  }
+
+  fn record(&mut self, entry: &TraceEntry, last_io: &mut Option<u64>) {
+    *self.executions.entry(entry.ip).or_default() += 1;
+    if let Some(opcode) = Opcode::from_raw(entry.raw) {
+      *self.opcodes.entry(opcode).or_default() += 1;
+    }
+    if entry.io.is_some() {
+      let gap = entry.step - last_io.map_or(0, |step| step + 1);
+      *self.io_gaps.entry(gap).or_default() += 1;
+      *last_io = Some(entry.step);
+    }
+  }
+
+  /// A summary of the profile of `program`: coverage, executions per opcode,
+  /// the hot spots and the instructions between I/O events.
+  pub fn table(&self, program: &[i64]) -> String {
+    let total = self.instructions();
+    let share = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+    let code = self.code(program)
+      .into_iter()
+      .filter_map(|line| match line {
+        Line::Code(instruction) => Some(instruction),
+        Line::Data { .. } => None,
+      })
+      .collect::<Vec<_>>();
+    let executed = code.iter().filter(|instruction| self.executions(instruction.address) > 0).count();
+    let mut table = String::new();
+
+    writeln!(table, "instances     {}", self.instances).unwrap();
+    writeln!(table, "instructions  {}", total).unwrap();
+    writeln!(
+      table,
+      "coverage      {} of {} instructions ({:.1}%)",
+      executed,
+      code.len(),
+      100.0 * executed as f64 / code.len().max(1) as f64,
+    ).unwrap();
+
+    // runs of adjacent instructions that were never executed
+    let mut runs: Vec<(usize, usize)> = vec![];
+    for instruction in code.iter().filter(|instruction| self.executions(instruction.address) == 0) {
+      let end = instruction.address + instruction.size() - 1;
+      match runs.last_mut() {
+        Some((_, last)) if *last + 1 == instruction.address => *last = end,
+        _ => runs.push((instruction.address, end)),
+      }
+    }
+    let runs = runs
+      .iter()
+      .map(|(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+      .collect::<Vec<_>>();
+    writeln!(table, "unexecuted    {}", if runs.is_empty() { "-".to_string() } else { runs.join(", ") }).unwrap();
+
+    writeln!(table, "\nopcode        count   share").unwrap();
+    for (opcode, count) in &self.opcodes {
+      writeln!(table, "{:<6} {:>12} {:>6.1}%", opcode.mnemonic(), count, share(*count)).unwrap();
+    }
+
+    writeln!(table, "\naddress       count   share  instruction").unwrap();
+    for (address, count) in self.hot_spots(HOT_SPOTS) {
+      let instruction = code
+        .iter()
+        .find(|instruction| instruction.address == address)
+        .map_or("?".to_string(), |instruction| instruction.to_string());
+      writeln!(table, "{:>7} {:>12} {:>6.1}%  {}", address, count, share(count), instruction).unwrap();
+    }
+
+    let events = self.io_gaps.values().sum::<u64>();
+    if events > 0 {
+      let instructions = self.io_gaps.iter().map(|(gap, count)| gap * count).sum::<u64>();
+      let mut seen = 0;
+      let median = self.io_gaps
+        .iter()
+        .find(|(_, count)| {
+          seen += **count;
+          seen * 2 >= events
+        })
+        .map_or(0, |(gap, _)| *gap);
+      writeln!(table, "\ninstructions between I/O events").unwrap();
+      writeln!(
+        table,
+        "events {}, min {}, median {}, mean {:.1}, max {}",
+        events,
+        self.io_gaps.keys().next().unwrap(),
+        median,
+        instructions as f64 / events as f64,
+        self.io_gaps.keys().next_back().unwrap(),
+      ).unwrap();
+    }
+
+    table
+  }
+
+  /// A disassembly of `program` with the number of executions of every
+  /// instruction in front of it. Instructions that were never executed are
+  /// marked with `#####`, data with `-`.
+  pub fn annotate(&self, program: &[i64]) -> String {
+    let lines = self.code(program);
+    let width = lines.last().map_or(1, |line| line.address().to_string().len());
+    let mut listing = String::new();
+
+    for line in &lines {
+      match line {
+        Line::Code(instruction) => {
+          let count = match self.executions(instruction.address) {
+            0 => "#####".to_string(),
+            count => count.to_string(),
+          };
+          writeln!(listing, "{:>10}  {:>width$}: {}", count, instruction.address, instruction, width = width)
+        },
+        Line::Data { address, value } => writeln!(listing, "{:>10}  {:>width$}: .data {}", "-", address, value, width = width),
+      }.unwrap();
+    }
+
+    listing
+  }
+}
+
+/// Collects the profile of any number of machines, see the module docs.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+  profile: Arc<Mutex<Profile>>,
+}
+
+/// The tracer of a single machine.
+struct Probe {
+  profile: Arc<Mutex<Profile>>,
+  last_io: Option<u64>,
+}
+
+impl Tracer for Probe {
+  fn trace(&mut self, entry: &TraceEntry) {
+    self.profile.lock().unwrap().record(entry, &mut self.last_io);
+  }
+}
+
+impl Profiler {
+  pub fn new() -> Profiler {
+    Profiler::default()
+  }
+
+  /// A tracer for one more machine. The tracer can be moved to another
+  /// thread.
+  pub fn tracer(&self) -> Box<dyn Tracer + Send> {
+    self.profile.lock().unwrap().instances += 1;
+    Box::new(Probe { profile: self.profile.clone(), last_io: None })
+  }
+
+  /// Profiles `machine` from now on, replacing its tracer.
+  pub fn attach<M: Memory>(&self, machine: &mut Machine<M>) {
+    machine.set_tracer(self.tracer());
+  }
+
+  /// The profile collected so far.
+  pub fn profile(&self) -> Profile {
+    self.profile.lock().unwrap().clone()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{assemble, parse_instructions, State};
+
+  fn countdown() -> Vec<i64> {
+    assemble("
+              in [n]
+              jz [n], zero
+      loop:   out [n]
+              add [n], -1, [n]
+              jnz [n], loop
+              hlt
+      zero:   out 42
+              hlt
+      n:      .data 0
+    ").unwrap()
+  }
+
+  #[test]
+  fn counts_executions() {
+    let program = countdown();
+    let profiler = Profiler::new();
+    let mut machine = Machine::new(&program);
+    profiler.attach(&mut machine);
+    machine.push_input(3);
+    assert_eq!(machine.run(), Ok(State::Halted));
+
+    let profile = profiler.profile();
+    assert_eq!(profile.instructions(), machine.steps());
+    assert_eq!((profile.executions(5), profile.executions(14)), (3, 1));
+    assert_eq!(profile.opcode_executions(Opcode::Jnz), 3);
+    // in, then an out every third instruction
+    assert_eq!(profile.io_gaps(), &BTreeMap::from([(0, 1), (1, 1), (2, 2)]));
+    assert_eq!(profile.unexecuted(&program).iter().map(|instruction| instruction.address).collect::<Vec<_>>(), vec![15, 17]);
+    assert_eq!(profile.annotate(&program), concat!(
+      "         1   0: in [18]\n",
+      "         1   2: jz [18], 15\n",
+      "         3   5: out [18]\n",
+      "         3   7: add [18], -1, [18]\n",
+      "         3  11: jnz [18], 5\n",
+      "         1  14: hlt\n",
+      "     #####  15: out 42\n",
+      "     #####  17: hlt\n",
+      "         -  18: .data 0\n",
+    ));
+
+    let table = profile.table(&program);
+    assert!(table.starts_with("instances     1\ninstructions  12\ncoverage      6 of 8 instructions (75.0%)\nunexecuted    15-17\n"));
+    assert!(table.contains("\nadd               3   25.0%\n"));
+    assert!(table.contains("\n      5            3   25.0%  out [18]\n"));
+    assert!(table.contains("\nevents 4, min 0, median 1, mean 1.2, max 2\n"));
+  }
+
+  #[test]
+  fn aggregates_the_amplifiers_of_day7() {
+    let program = parse_instructions(include_str!("../day7/data/input-1.txt").trim());
+    let profiler = Profiler::new();
+    let mut amplifiers = [9, 8, 7, 6, 5]
+      .iter()
+      .map(|phase| {
+        let mut amplifier = Machine::new(&program);
+        profiler.attach(&mut amplifier);
+        amplifier.push_input(*phase);
+        amplifier
+      })
+      .collect::<Vec<_>>();
+
+    let mut signal = 0;
+    while amplifiers[4].state() != State::Halted {
+      for amplifier in amplifiers.iter_mut() {
+        amplifier.push_input(signal);
+        amplifier.run().unwrap();
+        signal = amplifier.pop_output().unwrap();
+      }
+    }
+
+    let profile = profiler.profile();
+    assert_eq!(profile.instances(), 5);
+    assert_eq!(profile.instructions(), amplifiers.iter().map(|amplifier| amplifier.steps()).sum());
+    // each amplifier takes its phase, then a signal and outputs a signal ten times
+    assert_eq!(profile.io_gaps().values().sum::<u64>(), 5 * 21);
+    assert!(profile.table(&program).starts_with("instances     5\n"));
+  }
+}
@@ -0,0 +1,314 @@
+//! Finding and changing the variables of running programs.
+//!
+//! A `Scanner` runs a machine, takes snapshots of its memory and narrows down
+//! the cells that could hold a variable, like a game's score, by filtering
+//! them by their value or by how they changed between the last two
+//! snapshots. Found cells can be patched once or pinned to a value.
+//!
+//! ```
+//! # use y2019::intcode::{Filter, Machine, Scanner};
+//! // counts the inputs in cell 11 and outputs the count before each one
+//! let mut scanner = Scanner::new(Machine::new(&[1001, 11, 1, 11, 4, 11, 3, 12, 1105, 1, 0, 0, 0]));
+//! scanner.run().unwrap();
+//! scanner.snapshot();
+//! for _ in 0..2 {
+//!   scanner.machine_mut().push_input(0);
+//!   scanner.run().unwrap();
+//! }
+//! scanner.snapshot();
+//! scanner.filter(Filter::ChangedBy(2));
+//! assert_eq!(scanner.candidates(), vec![(11, 3)]);
+//!
+//! scanner.pin(11, 100);
+//! scanner.machine_mut().push_input(0);
+//! scanner.run().unwrap();
+//! assert_eq!(scanner.machine_mut().drain_output(), vec![1, 2, 3, 101]);
+//! ```
+
+use std::collections::BTreeMap;
+
+use super::{IoDevice, Machine, Memory, PagedMemory, State, VmError};
+
+/// How the cells a `Scanner` keeps have to look in the last snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+  Equals(i64),
+  /// Compared to the snapshot before.
+  Changed,
+  Unchanged,
+  Increased,
+  Decreased,
+  ChangedBy(i64),
+}
+
+impl Filter {
+  fn matches(self, before: i64, after: i64) -> bool {
+    match self {
+      Filter::Equals(value) => after == value,
+      Filter::Changed => after != before,
+      Filter::Unchanged => after == before,
+      Filter::Increased => after > before,
+      Filter::Decreased => after < before,
+      Filter::ChangedBy(delta) => after.checked_sub(before) == Some(delta),
+    }
+  }
+}
+
+/// The memory of a machine at some point of its run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+  steps: u64,
+  chunks: Vec<(usize, Vec<i64>)>,
+}
+
+impl Snapshot {
+  fn take<M: Memory>(machine: &Machine<M>) -> Snapshot {
+    let chunks = machine.memory().chunks().into_iter().map(|(address, cells)| (address, cells.to_vec())).collect();
+    Snapshot { steps: machine.steps(), chunks }
+  }
+
+  /// Number of instructions the machine had executed.
+  pub fn steps(&self) -> u64 {
+    self.steps
+  }
+
+  pub fn get(&self, address: usize) -> i64 {
+    let index = self.chunks.partition_point(|(start, _)| *start <= address);
+    match index.checked_sub(1).map(|index| &self.chunks[index]) {
+      Some((start, cells)) => cells.get(address - start).copied().unwrap_or(0),
+      None => 0,
+    }
+  }
+
+  fn addresses(&self) -> impl Iterator<Item = usize> + '_ {
+    self.chunks.iter().flat_map(|(start, cells)| *start..start + cells.len())
+  }
+}
+
+/// A machine whose memory can be scanned, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct Scanner<M: Memory = PagedMemory> {
+  machine: Machine<M>,
+  snapshots: Vec<Snapshot>,
+  candidates: Option<Vec<usize>>,
+  pins: BTreeMap<usize, i64>,
+}
+
+impl<M: Memory> Scanner<M> {
+  pub fn new(machine: Machine<M>) -> Scanner<M> {
+    Scanner { machine, snapshots: vec![], candidates: None, pins: BTreeMap::new() }
+  }
+
+  pub fn machine(&self) -> &Machine<M> {
+    &self.machine
+  }
+
+  pub fn machine_mut(&mut self) -> &mut Machine<M> {
+    &mut self.machine
+  }
+
+  pub fn into_machine(self) -> Machine<M> {
+    self.machine
+  }
+
+  /// Records the current memory and returns the number of snapshots.
+  pub fn snapshot(&mut self) -> usize {
+    self.snapshots.push(Snapshot::take(&self.machine));
+    self.snapshots.len()
+  }
+
+  pub fn snapshots(&self) -> &[Snapshot] {
+    &self.snapshots
+  }
+
+  /// Keeps the candidates that match `filter` in the last snapshot and
+  /// returns how many are left. The first filter considers every allocated
+  /// cell. Panics without snapshots to compare.
+  pub fn filter(&mut self, filter: Filter) -> usize {
+    let needed = if let Filter::Equals(_) = filter { 1 } else { 2 };
+    assert!(self.snapshots.len() >= needed, "{:?} needs {} snapshots", filter, needed);
+
+    let after = &self.snapshots[self.snapshots.len() - 1];
+    let before = &self.snapshots[self.snapshots.len() - needed];
+    let candidates = match self.candidates.take() {
+      Some(candidates) => candidates,
+      None => after.addresses().collect(),
+    };
+    let candidates = candidates
+      .into_iter()
+      .filter(|address| filter.matches(before.get(*address), after.get(*address)))
+      .collect::<Vec<_>>();
+
+    let count = candidates.len();
+    self.candidates = Some(candidates);
+    count
+  }
+
+  /// The cells that passed every filter so far, with their current values.
+  pub fn candidates(&self) -> Vec<(usize, i64)> {
+    let candidates = self.candidates.as_deref().unwrap_or_default();
+    candidates.iter().map(|address| (*address, self.machine.peek(*address))).collect()
+  }
+
+  /// Starts a new search. Snapshots are kept.
+  pub fn reset(&mut self) {
+    self.candidates = None;
+  }
+
+  /// Writes `value` to `address` once.
+  pub fn patch(&mut self, address: usize, value: i64) {
+    self.machine.poke(address, value);
+  }
+
+  /// Keeps `address` at `value`, see `run_with`.
+  pub fn pin(&mut self, address: usize, value: i64) {
+    self.pins.insert(address, value);
+    self.patch(address, value);
+  }
+
+  pub fn unpin(&mut self, address: usize) {
+    self.pins.remove(&address);
+  }
+
+  pub fn pins(&self) -> &BTreeMap<usize, i64> {
+    &self.pins
+  }
+
+  fn apply_pins(&mut self) {
+    for (address, value) in &self.pins {
+      // rewriting an unchanged cell would still drop its compiled code
+      if self.machine.peek(*address) != *value {
+        self.machine.poke(*address, *value);
+      }
+    }
+  }
+
+  /// Like `Machine::run_with`, but pinned cells are reset to their values
+  /// before every input and after every output. A program that changes a
+  /// pinned cell sees the change until its next I/O instruction. The budget
+  /// applies to the instructions between two I/O instructions.
+  pub fn run_with<D: IoDevice + ?Sized>(&mut self, device: &mut D) -> Result<State, VmError> {
+    while let Some(value) = self.machine.pop_output() {
+      device.output(value);
+    }
+
+    loop {
+      self.apply_pins();
+      match self.machine.run_until_io()? {
+        State::NeedsInput => match device.input() {
+          Some(value) => self.machine.push_input(value),
+          None => return Ok(State::NeedsInput),
+        },
+        State::HasOutput => {
+          while let Some(value) = self.machine.pop_output() {
+            device.output(value);
+          }
+        },
+        state => return Ok(state),
+      }
+    }
+  }
+
+  /// Like `Machine::run` with pins, see `run_with`.
+  pub fn run(&mut self) -> Result<State, VmError> {
+    loop {
+      self.apply_pins();
+      let state = self.machine.run_until_io()?;
+      if state != State::HasOutput {
+        return Ok(state);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::collections::VecDeque;
+
+  use super::*;
+  use crate::intcode::{assemble, parse_instructions};
+
+  #[test]
+  fn pins_hold_while_running() {
+    // loses a life per input until there are none left
+    let program = assemble("
+      loop:   in [frame]
+              add [lives], -1, [lives]
+              out [lives]
+              jnz [lives], loop
+              hlt
+      lives:  .data 3
+      frame:  .data 0
+    ").unwrap();
+    let mut scanner = Scanner::new(Machine::new(&program));
+    let mut outputs = vec![];
+
+    scanner.run_with(&mut (VecDeque::from(vec![0]), &mut outputs)).unwrap();
+    scanner.snapshot();
+    scanner.run_with(&mut (VecDeque::from(vec![0]), &mut outputs)).unwrap();
+    scanner.snapshot();
+    assert_eq!(scanner.filter(Filter::ChangedBy(-1)), 1);
+    let (lives, _) = scanner.candidates()[0];
+    assert_eq!(lives, 12);
+
+    scanner.pin(lives, 3);
+    assert_eq!(scanner.run_with(&mut (VecDeque::from(vec![0; 5]), &mut outputs)), Ok(State::NeedsInput));
+    scanner.unpin(lives);
+    assert_eq!(scanner.run_with(&mut (VecDeque::from(vec![0; 5]), &mut outputs)), Ok(State::Halted));
+    assert_eq!(outputs, vec![2, 1, 2, 2, 2, 2, 2, 2, 1, 0]);
+  }
+
+  /// Moves the paddle of day 13 towards the ball for `frames` inputs.
+  struct Player {
+    buffer: Vec<i64>,
+    ball: i64,
+    paddle: i64,
+    score: i64,
+    frames: usize,
+  }
+
+  impl IoDevice for Player {
+    fn input(&mut self) -> Option<i64> {
+      if self.frames == 0 {
+        return None;
+      }
+      self.frames -= 1;
+      Some((self.ball - self.paddle).signum())
+    }
+
+    fn output(&mut self, value: i64) {
+      self.buffer.push(value);
+      if let [x, y, tile] = self.buffer[..] {
+        match (x, y, tile) {
+          (-1, 0, score) => self.score = score,
+          (x, _, 3) => self.paddle = x,
+          (x, _, 4) => self.ball = x,
+          _ => (),
+        }
+        self.buffer.clear();
+      }
+    }
+  }
+
+  #[test]
+  fn finds_the_ball_of_day13() {
+    let program = parse_instructions(include_str!("../day13/data/input-1.txt"));
+    let mut scanner = Scanner::new(Machine::new(&program));
+    // insert a coin
+    scanner.patch(0, 2);
+
+    let mut player = Player { buffer: vec![], ball: 0, paddle: 0, score: 0, frames: 1 };
+    scanner.run_with(&mut player).unwrap();
+    scanner.snapshot();
+    scanner.filter(Filter::Equals(player.ball));
+    for _ in 0..20 {
+      player.frames = 1;
+      scanner.run_with(&mut player).unwrap();
+      scanner.snapshot();
+      scanner.filter(Filter::Changed);
+      scanner.filter(Filter::Equals(player.ball));
+    }
+    // the game keeps a copy of the ball's x in its scratch area
+    assert_eq!(scanner.candidates(), vec![(388, player.ball), (2657, player.ball)]);
+  }
+}
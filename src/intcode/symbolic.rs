@@ -0,0 +1,716 @@
+//! Symbolic execution of Intcode programs.
+//!
+//! A `SymbolicMachine` runs a program with some values left open: the input
+//! values and memory cells chosen with `symbolize`. Results are built up as
+//! `Term`s, and every branch on an open value splits the run into two paths
+//! with a `Constraint` each. `solve` then finds values for the symbols that
+//! satisfy the constraints of a path, working on linear equations directly:
+//!
+//! ```
+//! # use std::collections::BTreeMap;
+//! # use y2019::intcode::{solve, Constraint, Symbol, SymbolicMachine};
+//! // out 3 * in + 5
+//! let mut machine = SymbolicMachine::new(&[3, 13, 1002, 13, 3, 13, 1001, 13, 5, 13, 4, 13, 99]);
+//! let paths = machine.explore(1).unwrap();
+//! assert_eq!(paths[0].outputs[0].to_string(), "3*in0 + 5");
+//!
+//! let domains = BTreeMap::from([(Symbol::Input(0), 0..=1000)]);
+//! let solution = solve(&[Constraint::equals(paths[0].outputs[0].clone(), 302)], &domains).unwrap();
+//! assert_eq!(solution[&Symbol::Input(0)], 99);
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use super::{Mode, Opcode, VmError, DEFAULT_MEMORY_LIMIT};
+
+/// An open value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Symbol {
+  /// The `n`th value read by `in`.
+  Input(usize),
+  /// The initial value of a memory cell, see `SymbolicMachine::symbolize`.
+  Cell(usize),
+  /// The `n`th value read from an address that depends on symbols. Nothing
+  /// is known about it.
+  Unknown(usize),
+}
+
+impl fmt::Display for Symbol {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Symbol::Input(n) => write!(f, "in{}", n),
+      Symbol::Cell(address) => write!(f, "m{}", address),
+      Symbol::Unknown(n) => write!(f, "?{}", n),
+    }
+  }
+}
+
+/// A value computed from symbols. Binary terms are `add`, `mul`, `lt` or
+/// `eq` of their operands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+  Const(i64),
+  Symbol(Symbol),
+  Binary(Opcode, Box<Term>, Box<Term>),
+}
+
+impl Term {
+  /// Combines `a` and `b` with one of the arithmetic or comparison opcodes.
+  /// Constants are folded, `None` if that overflows.
+  pub fn binary(opcode: Opcode, a: Term, b: Term) -> Option<Term> {
+    use self::Term::Const;
+
+    Some(match (opcode, a, b) {
+      (Opcode::Add, Const(a), Const(b)) => Const(a.checked_add(b)?),
+      (Opcode::Mul, Const(a), Const(b)) => Const(a.checked_mul(b)?),
+      (Opcode::Lt, Const(a), Const(b)) => Const((a < b) as i64),
+      (Opcode::Eq, Const(a), Const(b)) => Const((a == b) as i64),
+      (Opcode::Add, Const(0), term) | (Opcode::Add, term, Const(0)) => term,
+      (Opcode::Mul, Const(1), term) | (Opcode::Mul, term, Const(1)) => term,
+      (Opcode::Mul, Const(0), _) | (Opcode::Mul, _, Const(0)) => Const(0),
+      (Opcode::Lt, a, b) if a == b => Const(0),
+      (Opcode::Eq, a, b) if a == b => Const(1),
+      (opcode, a, b) => Term::Binary(opcode, Box::new(a), Box::new(b)),
+    })
+  }
+
+  pub fn constant(&self) -> Option<i64> {
+    match self {
+      Term::Const(value) => Some(*value),
+      _ => None,
+    }
+  }
+
+  /// The term as `constant + Σ coefficient·symbol`, if it is linear.
+  pub fn linear(&self) -> Option<Linear> {
+    match self {
+      Term::Const(value) => Some(Linear { constant: *value, terms: BTreeMap::new() }),
+      Term::Symbol(symbol) => Some(Linear { constant: 0, terms: BTreeMap::from([(*symbol, 1)]) }),
+      Term::Binary(Opcode::Add, a, b) => a.linear()?.add(&b.linear()?),
+      Term::Binary(Opcode::Mul, a, b) => match (a.linear()?, b.linear()?) {
+        (a, b) if a.terms.is_empty() => b.scale(a.constant),
+        (a, b) if b.terms.is_empty() => a.scale(b.constant),
+        _ => None,
+      },
+      Term::Binary(..) => None,
+    }
+  }
+
+  /// The value of the term, `None` if a symbol has no value or it overflows.
+  pub fn eval(&self, values: &BTreeMap<Symbol, i64>) -> Option<i64> {
+    match self {
+      Term::Const(value) => Some(*value),
+      Term::Symbol(symbol) => values.get(symbol).copied(),
+      Term::Binary(opcode, a, b) => {
+        Term::binary(*opcode, Term::Const(a.eval(values)?), Term::Const(b.eval(values)?))?.constant()
+      },
+    }
+  }
+
+  fn symbols(&self, symbols: &mut Vec<Symbol>) {
+    match self {
+      Term::Const(_) => (),
+      Term::Symbol(symbol) => {
+        if !symbols.contains(symbol) {
+          symbols.push(*symbol);
+        }
+      },
+      Term::Binary(_, a, b) => {
+        a.symbols(symbols);
+        b.symbols(symbols);
+      },
+    }
+  }
+}
+
+impl fmt::Display for Term {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(linear) = self.linear() {
+      return write!(f, "{}", linear);
+    }
+    match self {
+      Term::Const(value) => write!(f, "{}", value),
+      Term::Symbol(symbol) => write!(f, "{}", symbol),
+      Term::Binary(opcode, a, b) => {
+        let operator = match opcode {
+          Opcode::Add => "+",
+          Opcode::Mul => "*",
+          Opcode::Lt => "<",
+          _ => "==",
+        };
+        write!(f, "({} {} {})", a, operator, b)
+      },
+    }
+  }
+}
+
+/// `constant + Σ coefficient·symbol`, without zero coefficients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Linear {
+  pub constant: i64,
+  pub terms: BTreeMap<Symbol, i64>,
+}
+
+impl Linear {
+  fn add(mut self, other: &Linear) -> Option<Linear> {
+    self.constant = self.constant.checked_add(other.constant)?;
+    for (symbol, coefficient) in &other.terms {
+      let sum = self.terms.get(symbol).unwrap_or(&0).checked_add(*coefficient)?;
+      if sum == 0 {
+        self.terms.remove(symbol);
+      } else {
+        self.terms.insert(*symbol, sum);
+      }
+    }
+    Some(self)
+  }
+
+  fn scale(mut self, factor: i64) -> Option<Linear> {
+    if factor == 0 {
+      return Some(Linear { constant: 0, terms: BTreeMap::new() });
+    }
+    self.constant = self.constant.checked_mul(factor)?;
+    for coefficient in self.terms.values_mut() {
+      *coefficient = coefficient.checked_mul(factor)?;
+    }
+    Some(self)
+  }
+}
+
+impl fmt::Display for Linear {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut first = true;
+    for (symbol, coefficient) in &self.terms {
+      let sign = if *coefficient < 0 { "-" } else { "+" };
+      match (first, coefficient.unsigned_abs()) {
+        (true, 1) if *coefficient < 0 => write!(f, "-{}", symbol)?,
+        (true, 1) => write!(f, "{}", symbol)?,
+        (true, _) => write!(f, "{}*{}", coefficient, symbol)?,
+        (false, 1) => write!(f, " {} {}", sign, symbol)?,
+        (false, magnitude) => write!(f, " {} {}*{}", sign, magnitude, symbol)?,
+      }
+      first = false;
+    }
+    match (first, self.constant) {
+      (true, constant) => write!(f, "{}", constant),
+      (false, 0) => Ok(()),
+      (false, constant) => write!(f, " {} {}", if constant < 0 { "-" } else { "+" }, constant.unsigned_abs()),
+    }
+  }
+}
+
+/// A condition a path depends on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Constraint {
+  Zero(Term),
+  NonZero(Term),
+}
+
+impl Constraint {
+  /// `term == value`
+  pub fn equals(term: Term, value: i64) -> Constraint {
+    match value.checked_neg().and_then(|value| Term::binary(Opcode::Add, term.clone(), Term::Const(value))) {
+      Some(difference) => Constraint::Zero(difference),
+      None => Constraint::NonZero(Term::binary(Opcode::Eq, term, Term::Const(value)).expect("comparisons don't overflow")),
+    }
+  }
+
+  pub fn term(&self) -> &Term {
+    match self {
+      Constraint::Zero(term) | Constraint::NonZero(term) => term,
+    }
+  }
+
+  /// Whether the constraint holds, `None` if a symbol has no value.
+  pub fn holds(&self, values: &BTreeMap<Symbol, i64>) -> Option<bool> {
+    let value = self.term().eval(values)?;
+    Some(matches!(self, Constraint::Zero(_)) == (value == 0))
+  }
+}
+
+impl fmt::Display for Constraint {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Constraint::Zero(term) => write!(f, "{} == 0", term),
+      Constraint::NonZero(term) => write!(f, "{} != 0", term),
+    }
+  }
+}
+
+/// Why a program can't be executed symbolically.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolicError {
+  /// The program faults on every value of the symbols.
+  Vm(VmError),
+  /// The instruction at `ip` is a term.
+  SymbolicCode { ip: usize },
+  /// The instruction at `ip` writes to an address that depends on symbols.
+  SymbolicWrite { ip: usize },
+  /// The instruction at `ip` jumps to an address that depends on symbols.
+  SymbolicJump { ip: usize },
+  /// The instruction at `ip` adjusts the relative base by a term.
+  SymbolicRelativeBase { ip: usize },
+  /// More paths than allowed, see `SymbolicMachine::explore`.
+  TooManyPaths(usize),
+  /// A path didn't halt within the step limit.
+  TooManySteps(u64),
+  /// No values of the symbols lead to the expected outcome on any path.
+  NoSolution,
+}
+
+impl fmt::Display for SymbolicError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SymbolicError::Vm(err) => write!(f, "{}", err),
+      SymbolicError::SymbolicCode { ip } => write!(f, "symbolic instruction at {}", ip),
+      SymbolicError::SymbolicWrite { ip } => write!(f, "write to a symbolic address at {}", ip),
+      SymbolicError::SymbolicJump { ip } => write!(f, "jump to a symbolic address at {}", ip),
+      SymbolicError::SymbolicRelativeBase { ip } => write!(f, "symbolic relative base adjustment at {}", ip),
+      SymbolicError::TooManyPaths(count) => write!(f, "more than {} paths", count),
+      SymbolicError::TooManySteps(count) => write!(f, "a path runs for more than {} instructions", count),
+      SymbolicError::NoSolution => write!(f, "no values lead to the expected outcome"),
+    }
+  }
+}
+
+impl std::error::Error for SymbolicError {}
+
+/// A way through the program, ending in `hlt`.
+#[derive(Debug, Clone)]
+pub struct Path {
+  /// What the symbols have to satisfy for the program to take this path.
+  pub constraints: Vec<Constraint>,
+  pub outputs: Vec<Term>,
+  memory: Vec<Term>,
+}
+
+impl Path {
+  /// The value of a memory cell when the program halts.
+  pub fn cell(&self, address: usize) -> Term {
+    self.memory.get(address).cloned().unwrap_or(Term::Const(0))
+  }
+}
+
+/// An Intcode machine on terms, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct SymbolicMachine {
+  memory: Vec<Term>,
+  ip: usize,
+  relative_base: i64,
+  inputs: usize,
+  unknowns: usize,
+  outputs: Vec<Term>,
+  constraints: Vec<Constraint>,
+  steps: u64,
+  step_limit: u64,
+}
+
+enum Next {
+  Continue,
+  Fork(Constraint, usize, Constraint),
+  Halt,
+}
+
+impl SymbolicMachine {
+  pub fn new(program: &[i64]) -> SymbolicMachine {
+    SymbolicMachine {
+      memory: program.iter().map(|value| Term::Const(*value)).collect(),
+      ip: 0,
+      relative_base: 0,
+      inputs: 0,
+      unknowns: 0,
+      outputs: vec![],
+      constraints: vec![],
+      steps: 0,
+      step_limit: 1_000_000,
+    }
+  }
+
+  /// Leaves the initial value of the cell at `address` open as
+  /// `Symbol::Cell(address)`.
+  pub fn symbolize(&mut self, address: usize) {
+    self.store(address, Term::Symbol(Symbol::Cell(address)));
+  }
+
+  /// Sets the number of instructions a path may execute, 1000000 by default.
+  pub fn set_step_limit(&mut self, limit: u64) {
+    self.step_limit = limit;
+  }
+
+  fn load(&self, address: usize) -> Term {
+    self.memory.get(address).cloned().unwrap_or(Term::Const(0))
+  }
+
+  fn store(&mut self, address: usize, value: Term) {
+    if address >= self.memory.len() {
+      self.memory.resize(address + 1, Term::Const(0));
+    }
+    self.memory[address] = value;
+  }
+
+  fn address(&self, op: i64, address: i64) -> Result<usize, SymbolicError> {
+    if address < 0 {
+      return Err(SymbolicError::Vm(VmError::NegativeAddress { ip: self.ip, opcode: op, address }));
+    }
+    if address as u64 >= DEFAULT_MEMORY_LIMIT as u64 {
+      return Err(SymbolicError::Vm(VmError::MemoryLimitExceeded { ip: self.ip, opcode: op, address }));
+    }
+    Ok(address as usize)
+  }
+
+  /// The address of a parameter, `None` if it depends on symbols.
+  fn param_address(&self, op: i64, index: usize) -> Result<Option<usize>, SymbolicError> {
+    let param_address = self.ip + index + 1;
+    let param = self.load(param_address).constant();
+    match (Mode::from_raw(op, index), param) {
+      (Ok(Mode::Immediate), _) => Ok(Some(param_address)),
+      (Ok(Mode::Position), Some(address)) => Ok(Some(self.address(op, address)?)),
+      (Ok(Mode::Relative), Some(offset)) => {
+        let address = self.relative_base.checked_add(offset).ok_or(VmError::Overflow { ip: self.ip, opcode: op });
+        Ok(Some(self.address(op, address.map_err(SymbolicError::Vm)?)?))
+      },
+      (Ok(_), None) => Ok(None),
+      (Err(mode), _) => Err(SymbolicError::Vm(VmError::InvalidParamMode { ip: self.ip, opcode: op, mode })),
+    }
+  }
+
+  fn param(&mut self, op: i64, index: usize) -> Result<Term, SymbolicError> {
+    match self.param_address(op, index)? {
+      Some(address) => Ok(self.load(address)),
+      None => {
+        self.unknowns += 1;
+        Ok(Term::Symbol(Symbol::Unknown(self.unknowns - 1)))
+      },
+    }
+  }
+
+  fn target(&self, op: i64, index: usize) -> Result<usize, SymbolicError> {
+    if Mode::from_raw(op, index) == Ok(Mode::Immediate) {
+      return Err(SymbolicError::Vm(VmError::WriteInImmediateMode { ip: self.ip, opcode: op }));
+    }
+    self.param_address(op, index)?.ok_or(SymbolicError::SymbolicWrite { ip: self.ip })
+  }
+
+  fn jump(&self, op: i64, target: Term) -> Result<usize, SymbolicError> {
+    match target.constant() {
+      Some(target) if target < 0 || target as u64 >= DEFAULT_MEMORY_LIMIT as u64 => {
+        Err(SymbolicError::Vm(VmError::IpOutOfBounds { ip: self.ip, opcode: op, target }))
+      },
+      Some(target) => Ok(target as usize),
+      None => Err(SymbolicError::SymbolicJump { ip: self.ip }),
+    }
+  }
+
+  fn step(&mut self) -> Result<Next, SymbolicError> {
+    let op = self.load(self.ip).constant().ok_or(SymbolicError::SymbolicCode { ip: self.ip })?;
+    let opcode = Opcode::from_raw(op).ok_or(SymbolicError::Vm(VmError::InvalidOpcode { ip: self.ip, opcode: op }))?;
+    let next = self.ip + opcode.size();
+
+    match opcode {
+      Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => {
+        let target = self.target(op, 2)?;
+        let (a, b) = (self.param(op, 0)?, self.param(op, 1)?);
+        let value = Term::binary(opcode, a, b).ok_or(SymbolicError::Vm(VmError::Overflow { ip: self.ip, opcode: op }))?;
+        self.store(target, value);
+      },
+      Opcode::In => {
+        let target = self.target(op, 0)?;
+        self.store(target, Term::Symbol(Symbol::Input(self.inputs)));
+        self.inputs += 1;
+      },
+      Opcode::Out => {
+        let value = self.param(op, 0)?;
+        self.outputs.push(value);
+      },
+      Opcode::Jnz | Opcode::Jz => {
+        let condition = self.param(op, 0)?;
+        let target = self.param(op, 1)?;
+        let (taken, not_taken) = if opcode == Opcode::Jnz {
+          (Constraint::NonZero(condition.clone()), Constraint::Zero(condition.clone()))
+        } else {
+          (Constraint::Zero(condition.clone()), Constraint::NonZero(condition.clone()))
+        };
+        match condition.constant() {
+          Some(value) if (value != 0) == (opcode == Opcode::Jnz) => {
+            self.ip = self.jump(op, target)?;
+            return Ok(Next::Continue);
+          },
+          Some(_) => (),
+          None => return Ok(Next::Fork(taken, self.jump(op, target)?, not_taken)),
+        }
+      },
+      Opcode::Arb => {
+        let offset = self.param(op, 0)?.constant().ok_or(SymbolicError::SymbolicRelativeBase { ip: self.ip })?;
+        self.relative_base = self.relative_base.checked_add(offset)
+          .ok_or(SymbolicError::Vm(VmError::Overflow { ip: self.ip, opcode: op }))?;
+      },
+      Opcode::Hlt => return Ok(Next::Halt),
+    }
+
+    self.ip = next;
+    Ok(Next::Continue)
+  }
+
+  /// Follows every path through the program until it halts. Branches on
+  /// terms split a path in two, at most `max_paths` are followed.
+  pub fn explore(&mut self, max_paths: usize) -> Result<Vec<Path>, SymbolicError> {
+    let mut pending = vec![self.clone()];
+    let mut paths = vec![];
+
+    while let Some(mut machine) = pending.pop() {
+      loop {
+        if machine.steps >= machine.step_limit {
+          return Err(SymbolicError::TooManySteps(machine.step_limit));
+        }
+        machine.steps += 1;
+        match machine.step()? {
+          Next::Continue => (),
+          Next::Fork(taken, target, not_taken) => {
+            if paths.len() + pending.len() + 2 > max_paths {
+              return Err(SymbolicError::TooManyPaths(max_paths));
+            }
+            let mut jumped = machine.clone();
+            jumped.constraints.push(taken);
+            jumped.ip = target;
+            pending.push(jumped);
+            machine.constraints.push(not_taken);
+            let op = machine.load(machine.ip).constant().expect("the instruction was decoded");
+            machine.ip += Opcode::from_raw(op).expect("the instruction was decoded").size();
+          },
+          Next::Halt => {
+            paths.push(Path { constraints: machine.constraints, outputs: machine.outputs, memory: machine.memory });
+            break;
+          },
+        }
+      }
+    }
+
+    Ok(paths)
+  }
+}
+
+fn div_floor(a: i128, b: i128) -> i128 {
+  let quotient = a / b;
+  if (a % b != 0) && ((a < 0) != (b < 0)) { quotient - 1 } else { quotient }
+}
+
+fn div_ceil(a: i128, b: i128) -> i128 {
+  -div_floor(-a, b)
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+  if b == 0 { a.abs() } else { gcd(b, a % b) }
+}
+
+/// `(g, x)` with `a·x ≡ g (mod m)` and `g = gcd(a, m)`.
+fn inverse_gcd(a: i128, m: i128) -> (i128, i128) {
+  let (mut old_r, mut r) = (a.rem_euclid(m), m);
+  let (mut old_s, mut s) = (1, 0);
+  while r != 0 {
+    let quotient = old_r / r;
+    (old_r, r) = (r, old_r - quotient * r);
+    (old_s, s) = (s, old_s - quotient * s);
+  }
+  (old_r, old_s)
+}
+
+struct Solver<'a> {
+  domains: &'a BTreeMap<Symbol, RangeInclusive<i64>>,
+  equations: Vec<Linear>,
+  others: Vec<&'a Constraint>,
+}
+
+impl Solver<'_> {
+  /// The range of `Σ coefficient·symbol` over the unassigned symbols.
+  fn bounds(&self, terms: &BTreeMap<Symbol, i64>, skip: Symbol) -> Option<(i128, i128)> {
+    let (mut low, mut high) = (0i128, 0i128);
+    for (symbol, coefficient) in terms.iter().filter(|(symbol, _)| **symbol != skip) {
+      let domain = self.domains.get(symbol)?;
+      let (a, b) = (*coefficient as i128 * *domain.start() as i128, *coefficient as i128 * *domain.end() as i128);
+      low += a.min(b);
+      high += a.max(b);
+    }
+    Some((low, high))
+  }
+
+  /// Candidates for one symbol of the equation with the fewest unknowns.
+  fn candidates(&self, values: &BTreeMap<Symbol, i64>) -> Result<Option<(Symbol, Vec<i64>)>, ()> {
+    let mut best: Option<(usize, Symbol, i64, Linear)> = None;
+    for equation in &self.equations {
+      let mut rest = Linear { constant: equation.constant, terms: BTreeMap::new() };
+      for (symbol, coefficient) in &equation.terms {
+        match values.get(symbol) {
+          Some(value) => {
+            let product = (*value as i128) * (*coefficient as i128);
+            rest.constant = i64::try_from(rest.constant as i128 + product).map_err(|_| ())?;
+          },
+          None => {
+            rest.terms.insert(*symbol, *coefficient);
+          },
+        }
+      }
+      match rest.terms.iter().next() {
+        None if rest.constant != 0 => return Err(()),
+        Some((symbol, coefficient)) if best.as_ref().is_none_or(|(count, ..)| rest.terms.len() < *count) => {
+          best = Some((rest.terms.len(), *symbol, *coefficient, rest.clone()));
+        },
+        _ => (),
+      }
+    }
+
+    let (_, symbol, coefficient, equation) = match best {
+      Some(best) => best,
+      None => return Ok(None),
+    };
+    let domain = self.domains.get(&symbol).ok_or(())?;
+    let (a, c) = (coefficient as i128, equation.constant as i128);
+
+    // a·x + rest + c = 0 with rest in [low, high], so a·x in [-c - high, -c - low]
+    let (low, high) = self.bounds(&equation.terms, symbol).ok_or(())?;
+    let (from, to) = if a > 0 {
+      (div_ceil(-c - high, a), div_floor(-c - low, a))
+    } else {
+      (div_ceil(-c - low, a), div_floor(-c - high, a))
+    };
+    let (from, to) = (from.max(*domain.start() as i128), to.min(*domain.end() as i128));
+
+    // a·x ≡ -c (mod g) where g divides every other coefficient
+    let g = equation.terms.iter().filter(|(other, _)| **other != symbol).fold(0, |g, (_, b)| gcd(g, *b as i128));
+    if g == 0 {
+      let value = -c / a;
+      let fits = -c % a == 0 && (from..=to).contains(&value);
+      return Ok(Some((symbol, if fits { vec![value as i64] } else { vec![] })));
+    }
+    let (d, inverse) = inverse_gcd(a, g);
+    if (-c).rem_euclid(d) != 0 {
+      return Ok(Some((symbol, vec![])));
+    }
+    let step = g / d;
+    let residue = ((-c / d).rem_euclid(step) * inverse.rem_euclid(step)).rem_euclid(step);
+    let first = from + (residue - from).rem_euclid(step);
+    let values = (0..).map(|k| first + k * step).take_while(|value| *value <= to).map(|value| value as i64).collect();
+    Ok(Some((symbol, values)))
+  }
+
+  fn search(&self, values: &mut BTreeMap<Symbol, i64>, rest: &[Symbol]) -> bool {
+    match self.candidates(values) {
+      Err(()) => false,
+      Ok(Some((symbol, candidates))) => self.try_each(values, rest, symbol, candidates),
+      Ok(None) => match rest.iter().find(|symbol| !values.contains_key(symbol)) {
+        // only symbols in nonlinear constraints left, try their whole domain
+        Some(symbol) => self.try_each(values, rest, *symbol, self.domains[symbol].clone()),
+        None => self.others.iter().all(|constraint| constraint.holds(values) == Some(true)),
+      },
+    }
+  }
+
+  fn try_each<I: IntoIterator<Item = i64>>(&self, values: &mut BTreeMap<Symbol, i64>, rest: &[Symbol], symbol: Symbol, candidates: I) -> bool {
+    for value in candidates {
+      values.insert(symbol, value);
+      if self.search(values, rest) {
+        return true;
+      }
+    }
+    values.remove(&symbol);
+    false
+  }
+}
+
+/// Finds values for the symbols within their `domains` that satisfy all
+/// `constraints`. Linear equations are solved for one symbol at a time,
+/// narrowed down by the ranges of the other symbols and divisibility, only
+/// symbols that appear in nothing but other constraints are enumerated.
+/// Symbols without a domain can't be solved for.
+pub fn solve(constraints: &[Constraint], domains: &BTreeMap<Symbol, RangeInclusive<i64>>) -> Option<BTreeMap<Symbol, i64>> {
+  let mut symbols = vec![];
+  for constraint in constraints {
+    constraint.term().symbols(&mut symbols);
+  }
+  if symbols.iter().any(|symbol| !domains.contains_key(symbol)) {
+    return None;
+  }
+
+  let mut equations = vec![];
+  let mut others = vec![];
+  for constraint in constraints {
+    match (constraint, constraint.term().linear()) {
+      (Constraint::Zero(_), Some(linear)) => equations.push(linear),
+      _ => others.push(constraint),
+    }
+  }
+
+  let solver = Solver { domains, equations, others };
+  let mut values = BTreeMap::new();
+  if !solver.search(&mut values, &symbols) {
+    return None;
+  }
+  for (symbol, domain) in domains {
+    values.entry(*symbol).or_insert(*domain.start());
+  }
+  Some(values)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{parse_instructions, Machine};
+
+  #[test]
+  fn solves_day2_for_any_output() {
+    let program = parse_instructions(include_str!("../day2/data/input-1.txt").trim());
+    let mut machine = SymbolicMachine::new(&program);
+    machine.symbolize(1);
+    machine.symbolize(2);
+    let paths = machine.explore(1).unwrap();
+    let domains = BTreeMap::from([(Symbol::Cell(1), 0..=99), (Symbol::Cell(2), 0..=99)]);
+
+    for (noun, verb) in [(12, 2), (0, 0), (99, 99), (64, 21)] {
+      let mut patched = program.clone();
+      patched[1] = noun;
+      patched[2] = verb;
+      let mut concrete = Machine::new(&patched);
+      concrete.run().unwrap();
+
+      let constraint = Constraint::equals(paths[0].cell(0), concrete.peek(0));
+      let solution = solve(&[constraint], &domains).unwrap();
+      assert_eq!((solution[&Symbol::Cell(1)], solution[&Symbol::Cell(2)]), (noun, verb));
+    }
+  }
+
+  #[test]
+  fn branches_become_constraints() {
+    // out 1 if in0 < 10 else out in0 * in0
+    let program = [3, 20, 1007, 20, 10, 21, 1005, 21, 15, 2, 20, 20, 22, 4, 22, 104, 1, 99];
+    let mut paths = SymbolicMachine::new(&program).explore(10).unwrap();
+    paths.sort_by_key(|path| path.outputs.len());
+    assert_eq!(paths.len(), 2);
+    assert_eq!(paths[0].constraints, vec![Constraint::NonZero(Term::binary(Opcode::Lt, Term::Symbol(Symbol::Input(0)), Term::Const(10)).unwrap())]);
+    assert_eq!(paths[1].outputs[0].to_string(), "(in0 * in0)");
+
+    let domains = BTreeMap::from([(Symbol::Input(0), -100..=100)]);
+    let mut constraints = paths[1].constraints.clone();
+    constraints.push(Constraint::equals(paths[1].outputs[0].clone(), 144));
+    assert_eq!(solve(&constraints, &domains).unwrap()[&Symbol::Input(0)], 12);
+    constraints.push(Constraint::equals(paths[1].outputs[1].clone(), 2));
+    assert_eq!(solve(&constraints, &domains), None);
+  }
+
+  #[test]
+  fn linear_equations_with_several_unknowns() {
+    let [x, y, z] = [Symbol::Input(0), Symbol::Input(1), Symbol::Input(2)].map(Term::Symbol);
+    let sum = |a: Term, b: Term| Term::binary(Opcode::Add, a, b).unwrap();
+    let scaled = |factor: i64, term: Term| Term::binary(Opcode::Mul, Term::Const(factor), term).unwrap();
+    // 6x + 10y + 15z = 301, x + y + z = 30
+    let constraints = [
+      Constraint::equals(sum(sum(scaled(6, x.clone()), scaled(10, y.clone())), scaled(15, z.clone())), 301),
+      Constraint::equals(sum(sum(x, y), z), 30),
+    ];
+    let domains = (0..3).map(|n| (Symbol::Input(n), 0..=30)).collect::<BTreeMap<_, _>>();
+
+    let solution = solve(&constraints, &domains).unwrap();
+    assert!(constraints.iter().all(|constraint| constraint.holds(&solution) == Some(true)));
+    assert_eq!(solve(&constraints[..1], &(0..3).map(|n| (Symbol::Input(n), 0..=5)).collect()), None);
+  }
+}
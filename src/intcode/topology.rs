@@ -0,0 +1,158 @@
+//! Graphs of Intcode machines connected by their inputs and outputs.
+//!
+//! Every node is a machine with some initial input. An edge sends each value
+//! its source outputs to the input of its target: a node with several
+//! outgoing edges sends a copy to each, a node with several incoming edges
+//! reads their values in the order they were produced. Edges may form cycles.
+//!
+//! ```
+//! # use y2019::intcode::Topology;
+//! // in x, out x + 1
+//! let increment = [3, 9, 1001, 9, 1, 9, 4, 9, 99, 0];
+//!
+//! let mut topology = Topology::new();
+//! let first = topology.node(&increment, &[1]);
+//! let second = topology.node(&increment, &[]);
+//! topology.edge(first, second);
+//! assert_eq!(topology.run().unwrap(), vec![vec![2], vec![3]]);
+//! ```
+
+use super::{Budget, Machine, VmError};
+
+/// A node of a `Topology`.
+pub type NodeId = usize;
+
+/// Nodes and edges of a machine graph, see the module documentation.
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+  nodes: Vec<(Vec<i64>, Vec<i64>)>,
+  edges: Vec<(NodeId, NodeId)>,
+  budget: Budget,
+}
+
+impl Topology {
+  pub fn new() -> Topology {
+    Topology::default()
+  }
+
+  /// Adds a machine running `program` that reads `inputs` first.
+  pub fn node(&mut self, program: &[i64], inputs: &[i64]) -> NodeId {
+    self.nodes.push((program.to_vec(), inputs.to_vec()));
+    self.nodes.len() - 1
+  }
+
+  /// Sends the outputs of `from` to `to`.
+  pub fn edge(&mut self, from: NodeId, to: NodeId) {
+    assert!(from < self.nodes.len() && to < self.nodes.len(), "no node {} or {}", from, to);
+    self.edges.push((from, to));
+  }
+
+  /// Connects each node to the next one.
+  pub fn chain(&mut self, nodes: &[NodeId]) {
+    for pair in nodes.windows(2) {
+      self.edge(pair[0], pair[1]);
+    }
+  }
+
+  /// Limits the instructions each machine may execute between two reads
+  /// from an empty input queue.
+  pub fn set_budget(&mut self, budget: Budget) {
+    self.budget = budget;
+  }
+
+  /// Runs the machines in turn until each has halted or waits for input
+  /// nobody is going to send, and returns the outputs of every node.
+  pub fn run(&self) -> Result<Vec<Vec<i64>>, VmError> {
+    let mut machines = self
+      .nodes
+      .iter()
+      .map(|(program, inputs)| {
+        let mut machine = Machine::new(program);
+        machine.set_budget(self.budget);
+        machine.extend_input(inputs.iter().copied());
+        machine
+      })
+      .collect::<Vec<_>>();
+    let mut outputs = vec![vec![]; machines.len()];
+
+    loop {
+      let mut progress = false;
+      for node in 0..machines.len() {
+        let steps = machines[node].steps();
+        machines[node].run()?;
+        progress |= machines[node].steps() != steps;
+
+        let values = machines[node].drain_output();
+        for (_, to) in self.edges.iter().filter(|(from, _)| *from == node) {
+          machines[*to].extend_input(values.iter().copied());
+        }
+        outputs[node].extend(values);
+      }
+      if !progress {
+        return Ok(outputs);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::assemble;
+
+  #[test]
+  fn fan_out_and_fan_in() {
+    let scale = |factor: i64| {
+      assemble(&format!("
+        loop: in [x]
+              mul [x], {}, [x]
+              out [x]
+              jz 0, loop
+        x:    .data 0
+      ", factor)).unwrap()
+    };
+    let sum = assemble("
+              in [a]
+              in [b]
+              add [a], [b], [a]
+              out [a]
+              hlt
+      a:      .data 0
+      b:      .data 0
+    ").unwrap();
+
+    let mut topology = Topology::new();
+    let source = topology.node(&scale(1), &[7]);
+    let double = topology.node(&scale(2), &[]);
+    let triple = topology.node(&scale(3), &[]);
+    let total = topology.node(&sum, &[]);
+    topology.edge(source, double);
+    topology.edge(source, triple);
+    topology.edge(double, total);
+    topology.edge(triple, total);
+
+    assert_eq!(topology.run(), Ok(vec![vec![7], vec![14], vec![21], vec![35]]));
+  }
+
+  #[test]
+  fn cycles_run_until_quiescence() {
+    // passes values on and counts down the first one until it reaches 0
+    let countdown = assemble("
+      loop:   in [x]
+              jz [x], done
+              add [x], -1, [x]
+              out [x]
+              jz 0, loop
+      done:   hlt
+      x:      .data 0
+    ").unwrap();
+
+    let mut topology = Topology::new();
+    let ping = topology.node(&countdown, &[5]);
+    let pong = topology.node(&countdown, &[]);
+    topology.edge(ping, pong);
+    topology.edge(pong, ping);
+
+    assert_eq!(topology.run(), Ok(vec![vec![4, 2, 0], vec![3, 1]]));
+  }
+}
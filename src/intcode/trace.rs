@@ -0,0 +1,414 @@
+//! Execution traces.
+//!
+//! A `Tracer` attached to a `Machine` is told about every instruction the
+//! machine executes. Traces can be written to and read back from a compact
+//! binary format:
+//!
+//! ```text
+//! file   := "ICTRACE1" entry*
+//! entry  := step ip raw rb count operand{count} flags [address value] [io]
+//! flags  := bit 0: memory write, bit 1: input, bit 2: output
+//! ```
+//!
+//! `step`, `ip`, `count` and `address` are unsigned LEB128 varints, all
+//! other numbers are zigzag encoded signed varints.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use super::varint::{expect_signed, expect_unsigned, read_unsigned, write_signed, write_unsigned};
+use super::{Machine, Opcode, VmError, MAX_ARITY};
+
+const MAGIC: &[u8; 8] = b"ICTRACE1";
+
+const WRITE: u8 = 1;
+const INPUT: u8 = 2;
+const OUTPUT: u8 = 4;
+
+/// An I/O event caused by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Io {
+  Input(i64),
+  Output(i64),
+}
+
+/// A single executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+  /// Number of instructions executed before this one.
+  pub step: u64,
+  pub ip: usize,
+  pub raw: i64,
+  /// The relative base before the instruction was executed.
+  pub relative_base: i64,
+  /// The values the parameters resolved to. For the parameter an instruction
+  /// writes to this is the target address.
+  pub operands: Vec<i64>,
+  /// Address and new value of the memory cell written.
+  pub write: Option<(usize, i64)>,
+  pub io: Option<Io>,
+}
+
+impl fmt::Display for TraceEntry {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{:>8} {:>6}: ", self.step, self.ip)?;
+    match Opcode::from_raw(self.raw) {
+      Some(opcode) => write!(f, "{}", opcode.mnemonic())?,
+      None => write!(f, "?{}", self.raw)?,
+    }
+    for (index, operand) in self.operands.iter().enumerate() {
+      let separator = if index == 0 { " " } else { ", " };
+      write!(f, "{}{}", separator, operand)?;
+    }
+    if let Some((address, value)) = self.write {
+      write!(f, " ; [{}] = {}", address, value)?;
+    }
+    match self.io {
+      Some(Io::Input(value)) => write!(f, " ; in {}", value)?,
+      Some(Io::Output(value)) => write!(f, " ; out {}", value)?,
+      None => {},
+    }
+    write!(f, " ; rb {}", self.relative_base)
+  }
+}
+
+/// Receives the instructions a machine executes.
+pub trait Tracer {
+  fn trace(&mut self, entry: &TraceEntry);
+}
+
+impl Tracer for Vec<TraceEntry> {
+  fn trace(&mut self, entry: &TraceEntry) {
+    self.push(entry.clone());
+  }
+}
+
+/// Lets the owner of a tracer inspect it while a machine holds a handle.
+impl<T: Tracer + ?Sized> Tracer for Arc<Mutex<T>> {
+  fn trace(&mut self, entry: &TraceEntry) {
+    self.lock().unwrap().trace(entry);
+  }
+}
+
+/// Writes a trace in the binary format.
+pub struct TraceWriter<W: Write> {
+  out: W,
+  error: Option<io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+  pub fn new(mut out: W) -> io::Result<TraceWriter<W>> {
+    out.write_all(MAGIC)?;
+    Ok(TraceWriter { out, error: None })
+  }
+
+  pub fn write(&mut self, entry: &TraceEntry) -> io::Result<()> {
+    let out = &mut self.out;
+    write_unsigned(out, entry.step)?;
+    write_unsigned(out, entry.ip as u64)?;
+    write_signed(out, entry.raw)?;
+    write_signed(out, entry.relative_base)?;
+    write_unsigned(out, entry.operands.len() as u64)?;
+    for operand in &entry.operands {
+      write_signed(out, *operand)?;
+    }
+
+    let flags = match entry.io {
+      Some(Io::Input(_)) => INPUT,
+      Some(Io::Output(_)) => OUTPUT,
+      None => 0,
+    } | if entry.write.is_some() { WRITE } else { 0 };
+    out.write_all(&[flags])?;
+
+    if let Some((address, value)) = entry.write {
+      write_unsigned(out, address as u64)?;
+      write_signed(out, value)?;
+    }
+    match entry.io {
+      Some(Io::Input(value)) | Some(Io::Output(value)) => write_signed(out, value),
+      None => Ok(()),
+    }
+  }
+
+  /// Flushes the writer and reports the first error that occurred while
+  /// tracing.
+  pub fn finish(mut self) -> io::Result<W> {
+    if let Some(error) = self.error.take() {
+      return Err(error);
+    }
+    self.out.flush()?;
+    Ok(self.out)
+  }
+}
+
+/// Write errors can't be reported from inside the machine, so the first one
+/// is kept for `finish` and everything after it is dropped.
+impl<W: Write> Tracer for TraceWriter<W> {
+  fn trace(&mut self, entry: &TraceEntry) {
+    if self.error.is_none() {
+      if let Err(error) = self.write(entry) {
+        self.error = Some(error);
+      }
+    }
+  }
+}
+
+/// Reads the entries of a binary trace.
+pub struct TraceReader<R: Read> {
+  input: R,
+}
+
+impl<R: Read> TraceReader<R> {
+  pub fn new(mut input: R) -> io::Result<TraceReader<R>> {
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "not an Intcode trace"));
+    }
+    Ok(TraceReader { input })
+  }
+
+  fn read_entry(&mut self) -> io::Result<Option<TraceEntry>> {
+    let input = &mut self.input;
+    let step = match read_unsigned(input)? {
+      Some(step) => step,
+      None => return Ok(None),
+    };
+    let ip = expect_unsigned(input)? as usize;
+    let raw = expect_signed(input)?;
+    let relative_base = expect_signed(input)?;
+    let count = expect_unsigned(input)?;
+    if count > MAX_ARITY as u64 {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "too many operands"));
+    }
+    let operands = (0..count).map(|_| expect_signed(input)).collect::<io::Result<Vec<_>>>()?;
+
+    let mut flags = [0u8];
+    input.read_exact(&mut flags)?;
+    let flags = flags[0];
+    let write = if flags & WRITE != 0 {
+      Some((expect_unsigned(input)? as usize, expect_signed(input)?))
+    } else {
+      None
+    };
+    let io = if flags & INPUT != 0 {
+      Some(Io::Input(expect_signed(input)?))
+    } else if flags & OUTPUT != 0 {
+      Some(Io::Output(expect_signed(input)?))
+    } else {
+      None
+    };
+
+    Ok(Some(TraceEntry { step, ip, raw, relative_base, operands, write, io }))
+  }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+  type Item = io::Result<TraceEntry>;
+
+  fn next(&mut self) -> Option<io::Result<TraceEntry>> {
+    self.read_entry().transpose()
+  }
+}
+
+/// Reads a complete binary trace.
+pub fn read_trace<R: Read>(input: R) -> io::Result<Vec<TraceEntry>> {
+  TraceReader::new(input)?.collect()
+}
+
+/// The first entry in which two traces differ. An entry is `None` if its
+/// trace ended before the other one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+  pub index: usize,
+  pub left: Option<TraceEntry>,
+  pub right: Option<TraceEntry>,
+}
+
+impl fmt::Display for Divergence {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "traces diverge at entry {}", self.index)?;
+    for (side, entry) in [("<", &self.left), (">", &self.right)].iter() {
+      match entry {
+        Some(entry) => writeln!(f, "{} {}", side, entry)?,
+        None => writeln!(f, "{} end of trace", side)?,
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Finds the first entry in which `left` and `right` differ.
+pub fn diff(left: &[TraceEntry], right: &[TraceEntry]) -> Option<Divergence> {
+  let index = left
+    .iter()
+    .zip(right)
+    .position(|(l, r)| l != r)
+    .unwrap_or_else(|| left.len().min(right.len()));
+  if index == left.len() && index == right.len() {
+    return None;
+  }
+  Some(Divergence { index, left: left.get(index).cloned(), right: right.get(index).cloned() })
+}
+
+/// Runs `program` from the start, feeding it the inputs recorded in `trace`,
+/// and compares every executed instruction with the recording. The inputs
+/// are provided one at a time, exactly when the recorded run consumed them,
+/// so the replay doesn't depend on how the original run was scheduled.
+///
+/// Returns the first entry that doesn't match, with the recording on the
+/// left. The replay stops where the trace ends.
+pub fn replay(program: &[i64], trace: &[TraceEntry]) -> Result<Option<Divergence>, VmError> {
+  let executed = Arc::new(Mutex::new(Vec::new()));
+  let mut machine = Machine::new(program);
+  machine.set_tracer(Box::new(executed.clone()));
+
+  for (index, expected) in trace.iter().enumerate() {
+    if let Some(Io::Input(value)) = expected.io {
+      machine.push_input(value);
+    }
+    machine.step()?;
+    let actual = executed.lock().unwrap().pop();
+    if actual.as_ref() != Some(expected) {
+      return Ok(Some(Divergence { index, left: Some(expected.clone()), right: actual }));
+    }
+  }
+
+  Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::intcode::{Cpu, Definition, Flow, InstructionSet, State};
+
+  fn record(program: &[i64], inputs: &[i64]) -> Vec<TraceEntry> {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut machine = Machine::new(program);
+    machine.set_tracer(Box::new(log.clone()));
+    machine.extend_input(inputs.iter().copied());
+    machine.run().unwrap();
+    let entries = log.lock().unwrap().clone();
+    entries
+  }
+
+  // day 5 example: outputs 999, 1000 or 1001 depending on the input
+  fn compare_to_8() -> Vec<i64> {
+    vec![
+      3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,
+      1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,
+      999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99,
+    ]
+  }
+
+  #[test]
+  fn records_every_instruction() {
+    let trace = record(&[1002,4,3,4,33], &[]);
+    assert_eq!(trace.len(), 2);
+    assert_eq!(trace[0], TraceEntry {
+      step: 0,
+      ip: 0,
+      raw: 1002,
+      relative_base: 0,
+      operands: vec![33, 3, 4],
+      write: Some((4, 99)),
+      io: None,
+    });
+    assert_eq!(trace[1].step, 1);
+    assert_eq!(trace[1].ip, 4);
+    assert_eq!(trace[1].operands, vec![]);
+
+    let trace = record(&[3,0,4,0,99], &[42]);
+    assert_eq!(trace[0].io, Some(Io::Input(42)));
+    assert_eq!(trace[0].write, Some((0, 42)));
+    assert_eq!(trace[1].io, Some(Io::Output(42)));
+  }
+
+  #[test]
+  fn blocked_input_is_not_recorded() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut machine = Machine::new(&[3,0,4,0,99]);
+    machine.set_tracer(Box::new(log.clone()));
+    assert_eq!(machine.run(), Ok(State::NeedsInput));
+    assert!(log.lock().unwrap().is_empty());
+    machine.push_input(7);
+    assert_eq!(machine.run(), Ok(State::Halted));
+    assert_eq!(log.lock().unwrap().len(), 3);
+    assert_eq!(machine.steps(), 3);
+  }
+
+  #[test]
+  fn binary_round_trip() {
+    let trace = record(&compare_to_8(), &[-5]);
+    let mut writer = TraceWriter::new(Vec::new()).unwrap();
+    for entry in &trace {
+      writer.trace(entry);
+    }
+    let bytes = writer.finish().unwrap();
+    assert_eq!(read_trace(&bytes[..]).unwrap(), trace);
+
+    assert!(read_trace(&b"ICTRACE2"[..]).is_err());
+    assert!(read_trace(&bytes[..bytes.len() - 1]).is_err());
+  }
+
+  // 13: the sum of the first three parameters into the fourth
+  fn sum3(cpu: &mut Cpu) -> Result<Flow, VmError> {
+    cpu.write(cpu.operand(0) + cpu.operand(1) + cpu.operand(2));
+    Ok(Flow::Next)
+  }
+
+  #[test]
+  fn defined_instructions_round_trip() {
+    let mut isa = InstructionSet::standard();
+    isa.register(Definition { code: 13, mnemonic: "sum3", arity: 4, write_param: Some(3), execute: sum3 });
+    let mut machine = Machine::new(&[1113, 1, 2, 3, 7, 99, 0, 0]);
+    machine.set_instruction_set(isa);
+    let log = Arc::new(Mutex::new(Vec::new()));
+    machine.set_tracer(Box::new(log.clone()));
+    assert_eq!(machine.run(), Ok(State::Halted));
+
+    let trace = log.lock().unwrap().clone();
+    assert_eq!(trace[0].operands, vec![1, 2, 3, 7]);
+    assert_eq!(trace[0].write, Some((7, 6)));
+    let mut writer = TraceWriter::new(Vec::new()).unwrap();
+    for entry in &trace {
+      writer.trace(entry);
+    }
+    assert_eq!(read_trace(&writer.finish().unwrap()[..]).unwrap(), trace);
+  }
+
+  #[test]
+  fn diff_finds_first_divergence() {
+    let below = record(&compare_to_8(), &[7]);
+    let equal = record(&compare_to_8(), &[8]);
+    assert_eq!(diff(&below, &below), None);
+
+    let divergence = diff(&below, &equal).unwrap();
+    assert_eq!(divergence.index, 0);
+    assert_eq!(divergence.left.unwrap().io, Some(Io::Input(7)));
+
+    let divergence = diff(&below, &below[..3]).unwrap();
+    assert_eq!(divergence.index, 3);
+    assert_eq!(divergence.right, None);
+  }
+
+  #[test]
+  fn replays_recorded_runs() {
+    let program = compare_to_8();
+    let trace = record(&program, &[9]);
+    assert_eq!(replay(&program, &trace), Ok(None));
+
+    let mut patched = program.clone();
+    patched[37] = 997;
+    let divergence = replay(&patched, &trace).unwrap().unwrap();
+    assert_eq!(divergence.left.unwrap().operands, vec![1000, 1, 20]);
+    assert_eq!(divergence.right.unwrap().operands, vec![997, 1, 20]);
+
+    let mut other_input = trace.clone();
+    other_input[0].io = Some(Io::Input(3));
+    let divergence = replay(&program, &other_input).unwrap().unwrap();
+    assert_eq!(divergence.index, 0);
+    assert_eq!(divergence.right.unwrap().write, Some((21, 3)));
+  }
+}
@@ -0,0 +1,59 @@
+//! LEB128 varints shared by the binary trace and snapshot formats. Signed
+//! numbers are zigzag encoded first.
+
+use std::io::{self, Read, Write};
+
+pub fn write_unsigned<W: Write>(out: &mut W, mut value: u64) -> io::Result<()> {
+  let mut bytes = [0u8; 10];
+  let mut len = 0;
+  loop {
+    let byte = (value & 0x7f) as u8;
+    value >>= 7;
+    if value == 0 {
+      bytes[len] = byte;
+      len += 1;
+      break;
+    }
+    bytes[len] = byte | 0x80;
+    len += 1;
+  }
+  out.write_all(&bytes[..len])
+}
+
+pub fn write_signed<W: Write>(out: &mut W, value: i64) -> io::Result<()> {
+  write_unsigned(out, ((value << 1) ^ (value >> 63)) as u64)
+}
+
+/// Reads a varint. Returns `None` on a clean end of input before the first
+/// byte.
+pub fn read_unsigned<R: Read>(input: &mut R) -> io::Result<Option<u64>> {
+  let mut value = 0u64;
+  let mut shift = 0;
+  loop {
+    let mut byte = [0u8];
+    if input.read(&mut byte)? == 0 {
+      if shift == 0 {
+        return Ok(None);
+      }
+      return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated record"));
+    }
+    if shift > 63 {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "varint too long"));
+    }
+    value |= u64::from(byte[0] & 0x7f) << shift;
+    if byte[0] & 0x80 == 0 {
+      return Ok(Some(value));
+    }
+    shift += 7;
+  }
+}
+
+pub fn expect_unsigned<R: Read>(input: &mut R) -> io::Result<u64> {
+  read_unsigned(input)?
+    .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "truncated record"))
+}
+
+pub fn expect_signed<R: Read>(input: &mut R) -> io::Result<i64> {
+  let value = expect_unsigned(input)?;
+  Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+}
@@ -0,0 +1,364 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use num::bigint::BigInt;
+use num::traits::{CheckedAdd, CheckedMul, ToPrimitive, Zero};
+
+use super::budget::Meter;
+use super::{Budget, Mode, Opcode, State, VmError, DEFAULT_MEMORY_LIMIT};
+
+/// The value a memory cell of a `WideMachine` holds.
+pub trait Word: Clone + Ord + fmt::Debug + fmt::Display {
+  fn from_i64(value: i64) -> Self;
+
+  /// The value if it fits into an `i64`.
+  fn to_i64(&self) -> Option<i64>;
+
+  /// `None` if the sum doesn't fit into the word.
+  fn checked_add(&self, other: &Self) -> Option<Self>;
+
+  /// `None` if the product doesn't fit into the word.
+  fn checked_mul(&self, other: &Self) -> Option<Self>;
+
+  fn is_zero(&self) -> bool {
+    *self == Self::from_i64(0)
+  }
+
+  /// The value as an `i64`, clamped to its range. Used to report faults.
+  fn saturated(&self) -> i64 {
+    self.to_i64().unwrap_or(if *self < Self::from_i64(0) { i64::MIN } else { i64::MAX })
+  }
+}
+
+impl Word for i64 {
+  fn from_i64(value: i64) -> i64 {
+    value
+  }
+
+  fn to_i64(&self) -> Option<i64> {
+    Some(*self)
+  }
+
+  fn checked_add(&self, other: &i64) -> Option<i64> {
+    i64::checked_add(*self, *other)
+  }
+
+  fn checked_mul(&self, other: &i64) -> Option<i64> {
+    i64::checked_mul(*self, *other)
+  }
+}
+
+impl Word for i128 {
+  fn from_i64(value: i64) -> i128 {
+    value.into()
+  }
+
+  fn to_i64(&self) -> Option<i64> {
+    i64::try_from(*self).ok()
+  }
+
+  fn checked_add(&self, other: &i128) -> Option<i128> {
+    i128::checked_add(*self, *other)
+  }
+
+  fn checked_mul(&self, other: &i128) -> Option<i128> {
+    i128::checked_mul(*self, *other)
+  }
+}
+
+/// Never overflows.
+impl Word for BigInt {
+  fn from_i64(value: i64) -> BigInt {
+    value.into()
+  }
+
+  fn to_i64(&self) -> Option<i64> {
+    ToPrimitive::to_i64(self)
+  }
+
+  fn checked_add(&self, other: &BigInt) -> Option<BigInt> {
+    CheckedAdd::checked_add(self, other)
+  }
+
+  fn checked_mul(&self, other: &BigInt) -> Option<BigInt> {
+    CheckedMul::checked_mul(self, other)
+  }
+
+  fn is_zero(&self) -> bool {
+    Zero::is_zero(self)
+  }
+}
+
+/// An Intcode machine with the word type `W`. `WideMachine<i64>` behaves
+/// like `Machine` and `i128` gives more room, both report results that don't
+/// fit as `VmError::Overflow`. With `BigInt` arithmetic never overflows.
+///
+/// ```
+/// # use num::bigint::BigInt;
+/// # use y2019::intcode::{State, WideMachine};
+/// let mut machine = WideMachine::<BigInt>::new(&[1102, 1 << 40, 1 << 40, 7, 4, 7, 99, 0]);
+/// assert_eq!(machine.run(), Ok(State::Halted));
+/// assert_eq!(machine.pop_output(), Some(BigInt::from(1) << 80));
+/// ```
+///
+/// Addresses, the instruction pointer and the relative base are still
+/// limited by the memory limit, only values can be wide. The machine is
+/// interpreted only and has no tracer.
+#[derive(Debug, Clone)]
+pub struct WideMachine<W: Word> {
+  memory: Vec<W>,
+  limit: usize,
+  ip: usize,
+  relative_base: i64,
+  state: State,
+  input: VecDeque<W>,
+  output: VecDeque<W>,
+  steps: u64,
+  budget: Budget,
+}
+
+impl<W: Word> WideMachine<W> {
+  pub fn new(program: &[i64]) -> WideMachine<W> {
+    WideMachine::with_words(program.iter().map(|value| W::from_i64(*value)).collect())
+  }
+
+  pub fn with_words(program: Vec<W>) -> WideMachine<W> {
+    WideMachine {
+      memory: program,
+      limit: DEFAULT_MEMORY_LIMIT,
+      ip: 0,
+      relative_base: 0,
+      state: State::Running,
+      input: VecDeque::new(),
+      output: VecDeque::new(),
+      steps: 0,
+      budget: Budget::unlimited(),
+    }
+  }
+
+  pub fn ip(&self) -> usize {
+    self.ip
+  }
+
+  pub fn relative_base(&self) -> i64 {
+    self.relative_base
+  }
+
+  pub fn state(&self) -> State {
+    self.state
+  }
+
+  /// Number of instructions executed so far.
+  pub fn steps(&self) -> u64 {
+    self.steps
+  }
+
+  pub fn set_memory_limit(&mut self, limit: usize) {
+    self.limit = limit;
+  }
+
+  /// Limits every following call to `run_until_io` and `run`.
+  pub fn set_budget(&mut self, budget: Budget) {
+    self.budget = budget;
+  }
+
+  /// The memory up to the highest address written so far.
+  pub fn memory(&self) -> &[W] {
+    &self.memory
+  }
+
+  pub fn peek(&self, address: usize) -> W {
+    self.memory.get(address).cloned().unwrap_or_else(|| W::from_i64(0))
+  }
+
+  pub fn poke(&mut self, address: usize, value: W) {
+    if address >= self.memory.len() {
+      self.memory.resize(address + 1, W::from_i64(0));
+    }
+    self.memory[address] = value;
+  }
+
+  pub fn push_input(&mut self, value: W) {
+    self.input.push_back(value);
+  }
+
+  pub fn pop_output(&mut self) -> Option<W> {
+    self.output.pop_front()
+  }
+
+  pub fn drain_output(&mut self) -> Vec<W> {
+    self.output.drain(..).collect()
+  }
+
+  fn address(&self, op: i64, address: &W) -> Result<usize, VmError> {
+    match address.to_i64() {
+      Some(address) if address < 0 => Err(VmError::NegativeAddress { ip: self.ip, opcode: op, address }),
+      Some(address) if (address as u64) < self.limit as u64 => Ok(address as usize),
+      _ => Err(VmError::MemoryLimitExceeded { ip: self.ip, opcode: op, address: address.saturated() }),
+    }
+  }
+
+  fn param_address(&self, op: i64, index: usize) -> Result<usize, VmError> {
+    let param_address = self.ip + index + 1;
+    match Mode::from_raw(op, index) {
+      Ok(Mode::Position) => self.address(op, &self.peek(param_address)),
+      Ok(Mode::Immediate) => Ok(param_address),
+      Ok(Mode::Relative) => {
+        let address = W::from_i64(self.relative_base).checked_add(&self.peek(param_address));
+        self.address(op, &self.checked(op, address)?)
+      },
+      Err(mode) => Err(VmError::InvalidParamMode { ip: self.ip, opcode: op, mode }),
+    }
+  }
+
+  fn checked<T>(&self, op: i64, value: Option<T>) -> Result<T, VmError> {
+    value.ok_or(VmError::Overflow { ip: self.ip, opcode: op })
+  }
+
+  fn param(&self, op: i64, index: usize) -> Result<W, VmError> {
+    Ok(self.peek(self.param_address(op, index)?))
+  }
+
+  fn target(&self, op: i64, index: usize) -> Result<usize, VmError> {
+    if Mode::from_raw(op, index) == Ok(Mode::Immediate) {
+      return Err(VmError::WriteInImmediateMode { ip: self.ip, opcode: op });
+    }
+    self.param_address(op, index)
+  }
+
+  fn jump(&self, op: i64, target: &W) -> Result<usize, VmError> {
+    match target.to_i64() {
+      Some(target) if target >= 0 && (target as u64) < self.limit as u64 => Ok(target as usize),
+      _ => Err(VmError::IpOutOfBounds { ip: self.ip, opcode: op, target: target.saturated() }),
+    }
+  }
+
+  /// Executes a single instruction, see `Machine::step`.
+  pub fn step(&mut self) -> Result<State, VmError> {
+    if self.state == State::Halted {
+      return Ok(self.state);
+    }
+
+    let op = self.peek(self.ip).saturated();
+    let opcode = Opcode::from_raw(op)
+      .ok_or(VmError::InvalidOpcode { ip: self.ip, opcode: op })?;
+    let next = self.ip + opcode.size();
+    let (state, next_ip) = match opcode {
+      Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => {
+        let target = self.target(op, 2)?;
+        let (a, b) = (self.param(op, 0)?, self.param(op, 1)?);
+        let value = match opcode {
+          Opcode::Add => self.checked(op, a.checked_add(&b))?,
+          Opcode::Mul => self.checked(op, a.checked_mul(&b))?,
+          Opcode::Lt => W::from_i64((a < b) as i64),
+          _ => W::from_i64((a == b) as i64),
+        };
+        self.poke(target, value);
+        (State::Running, next)
+      },
+      Opcode::In => {
+        let target = self.target(op, 0)?;
+        if let Some(value) = self.input.pop_front() {
+          self.poke(target, value);
+          (State::Running, next)
+        } else {
+          (State::NeedsInput, self.ip)
+        }
+      },
+      Opcode::Out => {
+        let value = self.param(op, 0)?;
+        self.output.push_back(value);
+        (State::HasOutput, next)
+      },
+      Opcode::Jnz | Opcode::Jz => {
+        if self.param(op, 0)?.is_zero() == (opcode == Opcode::Jz) {
+          (State::Running, self.jump(op, &self.param(op, 1)?)?)
+        } else {
+          (State::Running, next)
+        }
+      },
+      Opcode::Arb => {
+        let offset = self.checked(op, self.param(op, 0)?.to_i64())?;
+        self.relative_base = self.checked(op, self.relative_base.checked_add(offset))?;
+        (State::Running, next)
+      },
+      Opcode::Hlt => (State::Halted, self.ip),
+    };
+
+    self.ip = next_ip;
+    self.state = state;
+    if state != State::NeedsInput {
+      self.steps += 1;
+    }
+
+    Ok(self.state)
+  }
+
+  /// Executes instructions until the machine needs input, produced an
+  /// output value or halted.
+  pub fn run_until_io(&mut self) -> Result<State, VmError> {
+    self.run_metered(&mut self.budget.meter())
+  }
+
+  fn run_metered(&mut self, meter: &mut Option<Meter>) -> Result<State, VmError> {
+    loop {
+      if let (Some(meter), true) = (meter.as_ref(), self.state != State::Halted) {
+        if let Some(limit) = meter.exhausted() {
+          return Err(VmError::BudgetExhausted { ip: self.ip, opcode: self.peek(self.ip).saturated(), limit });
+        }
+      }
+      let state = self.step()?;
+      if let (Some(meter), true) = (meter.as_mut(), state != State::NeedsInput) {
+        meter.charge(state == State::HasOutput);
+      }
+      if state != State::Running {
+        return Ok(state);
+      }
+    }
+  }
+
+  /// Executes instructions until the machine halts or blocks on input.
+  pub fn run(&mut self) -> Result<State, VmError> {
+    let mut meter = self.budget.meter();
+    loop {
+      let state = self.run_metered(&mut meter)?;
+      if state != State::HasOutput {
+        return Ok(state);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn outputs<W: Word>(program: &[i64]) -> Result<Vec<W>, VmError> {
+    let mut machine = WideMachine::<W>::new(program);
+    machine.run()?;
+    Ok(machine.drain_output())
+  }
+
+  #[test]
+  fn day9_large_numbers_on_every_word_type() {
+    let program = [1102, 34915192, 34915192, 7, 4, 7, 99, 0];
+
+    assert_eq!(outputs::<i64>(&program), Ok(vec![1219070632396864]));
+    assert_eq!(outputs::<i128>(&program), Ok(vec![1219070632396864]));
+    assert_eq!(outputs::<BigInt>(&program), Ok(vec![BigInt::from(1219070632396864i64)]));
+  }
+
+  #[test]
+  fn overflow_is_reported_or_avoided() {
+    // squares 2^40, then squares the result
+    let program = [1102, 1 << 40, 1 << 40, 13, 4, 13, 2, 13, 13, 13, 4, 13, 99];
+
+    assert_eq!(outputs::<i64>(&program), Err(VmError::Overflow { ip: 0, opcode: 1102 }));
+    assert_eq!(outputs::<i128>(&program), Err(VmError::Overflow { ip: 6, opcode: 2 }));
+    assert_eq!(outputs::<BigInt>(&program), Ok(vec![BigInt::from(1) << 80, BigInt::from(1) << 160]));
+
+    let mut machine = WideMachine::<i128>::new(&program);
+    assert!(machine.run().is_err());
+    assert_eq!(machine.drain_output(), vec![1 << 80]);
+  }
+}
@@ -6,7 +6,8 @@ extern crate lazy_static;
 extern crate regex;
 extern crate num;
 
-mod intcode;
+pub mod intcode;
+pub mod search;
 mod day1;
 mod day2;
 mod day3;
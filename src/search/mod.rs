@@ -0,0 +1,244 @@
+//! Brute force search over permutations, combinations and subsets.
+//!
+//! The enumerations take any items, e.g. a range, and yield each candidate
+//! as a `Vec`. `maximize` scores candidates on all cores and returns the
+//! best one:
+//!
+//! ```
+//! # use y2019::search::{maximize, permutations};
+//! let best = maximize(permutations(1..4), |digits| {
+//!   Ok::<_, ()>(digits[0] * 100 - digits[1] * 10 + digits[2])
+//! });
+//! assert_eq!(best.unwrap().unwrap().arguments, vec![3, 1, 2]);
+//! ```
+
+use std::fmt;
+use std::thread;
+
+/// Iterator over the orderings of some items, see `permutations`.
+#[derive(Debug, Clone)]
+pub struct Permutations<T> {
+  items: Vec<T>,
+  indices: Vec<usize>,
+  done: bool,
+}
+
+/// All orderings of `items` in lexicographic order of their positions.
+pub fn permutations<T: Clone, I: IntoIterator<Item = T>>(items: I) -> Permutations<T> {
+  let items = items.into_iter().collect::<Vec<_>>();
+  let indices = (0..items.len()).collect();
+  Permutations { items, indices, done: false }
+}
+
+impl<T: Clone> Iterator for Permutations<T> {
+  type Item = Vec<T>;
+
+  fn next(&mut self) -> Option<Vec<T>> {
+    if self.done {
+      return None;
+    }
+    let next = self.indices.iter().map(|&i| self.items[i].clone()).collect();
+
+    // advance to the next permutation of the indices, if there is one
+    let indices = &mut self.indices;
+    match (1..indices.len()).rev().find(|&i| indices[i - 1] < indices[i]) {
+      Some(i) => {
+        let j = (i..indices.len()).rev().find(|&j| indices[i - 1] < indices[j]).unwrap();
+        indices.swap(i - 1, j);
+        indices[i..].reverse();
+      },
+      None => self.done = true,
+    }
+    Some(next)
+  }
+}
+
+/// Iterator over the selections of some items, see `combinations`.
+#[derive(Debug, Clone)]
+pub struct Combinations<T> {
+  items: Vec<T>,
+  indices: Vec<usize>,
+  done: bool,
+}
+
+/// All selections of `k` of `items`, keeping their order.
+pub fn combinations<T: Clone, I: IntoIterator<Item = T>>(items: I, k: usize) -> Combinations<T> {
+  let items = items.into_iter().collect::<Vec<_>>();
+  let done = k > items.len();
+  Combinations { items, indices: (0..k).collect(), done }
+}
+
+impl<T: Clone> Iterator for Combinations<T> {
+  type Item = Vec<T>;
+
+  fn next(&mut self) -> Option<Vec<T>> {
+    if self.done {
+      return None;
+    }
+    let next = self.indices.iter().map(|&i| self.items[i].clone()).collect();
+
+    // move the last index that can move one step on, and the ones after it
+    // right behind it
+    let (n, k) = (self.items.len(), self.indices.len());
+    match (0..k).rev().find(|&i| self.indices[i] < n - k + i) {
+      Some(i) => {
+        self.indices[i] += 1;
+        for j in i + 1..k {
+          self.indices[j] = self.indices[j - 1] + 1;
+        }
+      },
+      None => self.done = true,
+    }
+    Some(next)
+  }
+}
+
+/// Iterator over the subsets of some items, see `subsets`.
+#[derive(Debug, Clone)]
+pub struct Subsets<T> {
+  items: Vec<T>,
+  mask: u64,
+  end: u64,
+}
+
+/// All subsets of `items`, keeping their order. The `n`th subset contains
+/// the items whose bit is set in `n`, so it starts with the empty set.
+pub fn subsets<T: Clone, I: IntoIterator<Item = T>>(items: I) -> Subsets<T> {
+  let items = items.into_iter().collect::<Vec<_>>();
+  assert!(items.len() < 64, "too many items for subsets: {}", items.len());
+  let end = 1 << items.len();
+  Subsets { items, mask: 0, end }
+}
+
+impl<T: Clone> Iterator for Subsets<T> {
+  type Item = Vec<T>;
+
+  fn next(&mut self) -> Option<Vec<T>> {
+    if self.mask == self.end {
+      return None;
+    }
+    let next = self
+      .items
+      .iter()
+      .enumerate()
+      .filter(|(i, _)| self.mask & (1 << i) != 0)
+      .map(|(_, item)| item.clone())
+      .collect();
+    self.mask += 1;
+    Some(next)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let left = (self.end - self.mask) as usize;
+    (left, Some(left))
+  }
+}
+
+impl<T: Clone> ExactSizeIterator for Subsets<T> {}
+
+/// The best candidate of a search and its score.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Best<T, S> {
+  pub score: S,
+  pub arguments: T,
+}
+
+impl<T: fmt::Debug, S: fmt::Display> fmt::Display for Best<T, S> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} for {:?}", self.score, self.arguments)
+  }
+}
+
+/// Scores every candidate on all available cores and returns the one with
+/// the highest score, the first one of them if several are equally good.
+/// Returns `None` without candidates, and the error of the first candidate
+/// that fails to score. Wrap scores in `std::cmp::Reverse` to minimize.
+pub fn maximize<T, S, E, I, F>(candidates: I, score: F) -> Result<Option<Best<T, S>>, E>
+where
+  T: Sync,
+  S: Ord + Send,
+  E: Send,
+  I: IntoIterator<Item = T>,
+  F: Fn(&T) -> Result<S, E> + Sync,
+{
+  let mut candidates = candidates.into_iter().collect::<Vec<_>>();
+  if candidates.is_empty() {
+    return Ok(None);
+  }
+  let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(candidates.len());
+  let size = candidates.len().div_ceil(threads);
+
+  // the best index and score of every chunk
+  let results = thread::scope(|scope| {
+    let score = &score;
+    let workers = candidates
+      .chunks(size)
+      .enumerate()
+      .map(|(n, chunk)| {
+        scope.spawn(move || {
+          let mut best: Option<(usize, S)> = None;
+          for (i, candidate) in chunk.iter().enumerate() {
+            let value = score(candidate)?;
+            if best.as_ref().is_none_or(|(_, top)| value > *top) {
+              best = Some((n * size + i, value));
+            }
+          }
+          Ok(best)
+        })
+      })
+      .collect::<Vec<_>>();
+    workers.into_iter().map(|worker| worker.join().unwrap()).collect::<Vec<_>>()
+  });
+
+  let mut best: Option<(usize, S)> = None;
+  for result in results {
+    if let Some((index, value)) = result? {
+      if best.as_ref().is_none_or(|(_, top)| value > *top) {
+        best = Some((index, value));
+      }
+    }
+  }
+  Ok(best.map(|(index, score)| Best { score, arguments: candidates.swap_remove(index) }))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn enumerates_in_order() {
+    assert_eq!(
+      permutations(0..3).collect::<Vec<_>>(),
+      vec![vec![0, 1, 2], vec![0, 2, 1], vec![1, 0, 2], vec![1, 2, 0], vec![2, 0, 1], vec![2, 1, 0]]
+    );
+    assert_eq!(permutations(5..10).count(), 120);
+    assert_eq!(permutations(Vec::<i64>::new()).collect::<Vec<_>>(), vec![vec![]]);
+
+    assert_eq!(
+      combinations(["a", "b", "c", "d"], 2).collect::<Vec<_>>(),
+      vec![vec!["a", "b"], vec!["a", "c"], vec!["a", "d"], vec!["b", "c"], vec!["b", "d"], vec!["c", "d"]]
+    );
+    assert_eq!(combinations(0..10, 0).collect::<Vec<_>>(), vec![vec![]]);
+    assert_eq!(combinations(0..3, 4).count(), 0);
+
+    assert_eq!(
+      subsets(['x', 'y', 'z']).collect::<Vec<_>>(),
+      vec![vec![], vec!['x'], vec!['y'], vec!['x', 'y'], vec!['z'], vec!['x', 'z'], vec!['y', 'z'], vec!['x', 'y', 'z']]
+    );
+    assert_eq!(subsets(0..7).len(), 128);
+  }
+
+  #[test]
+  fn maximize_keeps_the_first_best_candidate() {
+    // sums of 3 of 10 numbers, 17 can be reached in several ways
+    let best = maximize(combinations(0..10, 3), |numbers| {
+      let sum = numbers.iter().sum::<i64>();
+      Ok::<_, ()>(if sum > 17 { 0 } else { sum })
+    });
+    assert_eq!(best, Ok(Some(Best { score: 17, arguments: vec![0, 8, 9] })));
+
+    let failed = maximize(0..100, |&n| if n % 40 == 39 { Err(n) } else { Ok(n) });
+    assert_eq!(failed, Err(39));
+    assert_eq!(maximize(0..0, |&n| Ok::<_, ()>(n)), Ok(None));
+  }
+}
@@ -32,6 +32,8 @@ impl fmt::Display for Error {
   }
 }
 
+impl std::error::Error for Error {}
+
 
 #[derive(Debug)]
 pub struct ParseError {